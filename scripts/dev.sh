@@ -12,14 +12,14 @@ PROJECT_DIR="$(dirname "$SCRIPT_DIR")"
 
 WEB_PORT="${WEB_PORT:-17100}"
 LJC_PORT="${LJC_PORT:-17200}"
-LJC_HTTPS_PORT=$((LJC_PORT + 1))
+LJC_HTTPS_PORT="${LJC_HTTPS_PORT:-$((LJC_PORT + 1))}"
 
 echo "=== SnowSync Development ==="
 echo ""
 
 # Start Rust relay server in background
 echo "[1/2] Starting Rust relay server (HTTP $LJC_PORT + HTTPS $LJC_HTTPS_PORT)..."
-(cd "$PROJECT_DIR/server" && LJC_PORT=$LJC_PORT cargo run) &
+(cd "$PROJECT_DIR/server" && LJC_PORT=$LJC_PORT LJC_HTTPS_PORT=$LJC_HTTPS_PORT cargo run) &
 RUST_PID=$!
 
 # Start Next.js dev server in background
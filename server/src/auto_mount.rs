@@ -0,0 +1,151 @@
+// SNOWCODE - ソフトウェア製品
+// (C) SNOWCODE
+// 開発者: 雪符しき
+// https://snowcode.jp
+// 問い合わせ: info@snowcode.jp
+
+// Auto-mount: a known device with a MountPolicy is mounted when it
+// registers, and unmounted (or kept behind the offline placeholder) when
+// it goes away. Driven by the connection events in ws::handle_ws.
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::Json;
+use axum::Extension;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::mount;
+use crate::mount_registry::{MountPolicy, OfflineAction};
+use crate::state::AppState;
+use crate::tokens::{Scope, TokenGrant};
+
+/// A client registered: mount its share if its policy says so. A share
+/// still mounted from before (placeholder policy) is left as it is.
+pub async fn on_registered(state: Arc<AppState>, client_id: String) {
+    let Some(policy) = state.devices.lock().await.mount_policy(&client_id) else {
+        return;
+    };
+    match mount::mount_client(&state, &client_id, &policy.mount_path).await {
+        Ok(record) => tracing::info!("Auto-mounted {} on {}", client_id, record.mount_point),
+        Err((StatusCode::CONFLICT, _)) => tracing::info!("Auto-mount: {} is already mounted", client_id),
+        Err((status, Json(body))) => {
+            tracing::warn!("Auto-mount of {} failed ({}): {}", client_id, status, body["error"])
+        }
+    }
+}
+
+/// A client's connection ended without a newer one replacing it.
+pub async fn on_disconnected(state: Arc<AppState>, client_id: String) {
+    let Some(policy) = state.devices.lock().await.mount_policy(&client_id) else {
+        return;
+    };
+    if policy.when_offline == OfflineAction::Placeholder {
+        if mount::is_mounted(&state, &client_id).await {
+            tracing::info!("Auto-mount: {} is offline; its mount shows the placeholder", client_id);
+        }
+        return;
+    }
+
+    // A quick reconnect (network blip, page reload) keeps the mount
+    tokio::time::sleep(state.heartbeat_interval).await;
+    if state.clients.read().await.contains_key(&client_id) {
+        return;
+    }
+    match mount::unmount_client(&state, &client_id).await {
+        Ok(0) => {}
+        Ok(_) => tracing::info!("Auto-unmounted {}", client_id),
+        // Until it is unmounted, the share answers from the placeholder
+        Err(e) => tracing::warn!("Auto-unmount of {} failed: {}", client_id, e),
+    }
+    // Came back while unmounting: mount again
+    if state.clients.read().await.contains_key(&client_id) {
+        on_registered(state, client_id).await;
+    }
+}
+
+/// Whether WebDAV requests for an offline or unresponsive client are
+/// answered by the placeholder (an empty read-only share) instead of
+/// failing: the client is auto-mounted and its mount is still up.
+pub async fn shows_placeholder(state: &AppState, client_id: &str) -> bool {
+    let auto_mounted = state.devices.lock().await.mount_policy(client_id).is_some();
+    auto_mounted && mount::is_mounted(state, client_id).await
+}
+
+/// GET /api/mount-policies
+/// Auto-mount policies of known devices.
+pub async fn list_policies(
+    State(state): State<Arc<AppState>>,
+    Extension(grant): Extension<TokenGrant>,
+) -> Json<Value> {
+    let devices = state.devices.lock().await;
+    let list: Vec<Value> = devices
+        .with_mount_policy()
+        .into_iter()
+        .filter(|d| grant.allows_client(&d.client_id))
+        .filter_map(|d| {
+            let policy = d.mount_policy.as_ref()?;
+            Some(json!({
+                "client_id": d.client_id,
+                "name": d.name,
+                "mount_path": policy.mount_path,
+                "when_offline": policy.when_offline,
+            }))
+        })
+        .collect();
+    Json(json!(list))
+}
+
+/// POST /api/mount-policies/{client_id}
+/// Body: {"mount_path": "~/Public/mount", "when_offline": "unmount" | "placeholder"}
+/// Sets the policy of a known device and mounts it now if it is connected.
+pub async fn set_policy(
+    State(state): State<Arc<AppState>>,
+    Extension(grant): Extension<TokenGrant>,
+    Path(client_id): Path<String>,
+    Json(policy): Json<MountPolicy>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    grant.check(Scope::Mount, &client_id)?;
+    if let Err(e) = mount::resolve_new_mount_point(&policy.mount_path, &client_id, &state.allowed_mount_base).await {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(json!({"error": e})),
+        ));
+    }
+    if !state.devices.lock().await.set_mount_policy(&client_id, Some(policy.clone())) {
+        return Err(not_a_known_device());
+    }
+    tracing::info!("Auto-mount policy for {}: {:?}", client_id, policy);
+
+    if state.clients.read().await.contains_key(&client_id) {
+        tokio::spawn(on_registered(state.clone(), client_id.clone()));
+    }
+    Ok(Json(json!({
+        "ok": true,
+        "client_id": client_id,
+        "mount_path": policy.mount_path,
+        "when_offline": policy.when_offline,
+    })))
+}
+
+/// DELETE /api/mount-policies/{client_id}
+/// Turns auto-mount off. A current mount stays until unmounted.
+pub async fn delete_policy(
+    State(state): State<Arc<AppState>>,
+    Extension(grant): Extension<TokenGrant>,
+    Path(client_id): Path<String>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    grant.check(Scope::Mount, &client_id)?;
+    if !state.devices.lock().await.set_mount_policy(&client_id, None) {
+        return Err(not_a_known_device());
+    }
+    tracing::info!("Auto-mount policy for {} removed", client_id);
+    Ok(Json(json!({"ok": true})))
+}
+
+fn not_a_known_device() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({"error": "Not a known device (auto-mount needs a device that registered with a device identity)"})),
+    )
+}
@@ -0,0 +1,699 @@
+// SNOWCODE - ソフトウェア製品
+// (C) SNOWCODE
+// 開発者: 雪符しき
+// https://snowcode.jp
+// 問い合わせ: info@snowcode.jp
+
+// Relay commands served from a local directory with native filesystem calls.
+// Replies mirror the connect page (connect_html.rs) field for field.
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+
+use crate::protocol::{ChunkContent, ErrorCode, FileContent, FileStat, Request, Written};
+
+/// Upper bound for one readChunk, same as the connect page.
+const MAX_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Buffer for copying files in `copy_tree`.
+const COPY_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Progress callback of a long request: done and total (bytes), and the
+/// entry being worked on. Sent to the server as `ClientMessage::Progress`.
+pub type ReportProgress<'a> = &'a (dyn Fn(u64, u64, &str) + Sync);
+
+/// Result of a command: JSON `data`, or raw bytes for a binary readChunk.
+pub enum Reply {
+    Json(Value),
+    Binary(Vec<u8>),
+}
+
+/// A failed command: the code the server maps to a status, and a message.
+pub struct Failure {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+fn fail(code: ErrorCode, message: impl Into<String>) -> Failure {
+    Failure {
+        code,
+        message: message.into(),
+    }
+}
+
+/// An upload in progress. Data goes to a temporary file next to the target,
+/// which replaces the target on commit.
+struct Upload {
+    file: fs::File,
+    temp_path: PathBuf,
+    target: PathBuf,
+    next_seq: u64,
+    written: u64,
+}
+
+pub struct SharedDir {
+    root: PathBuf,
+    uploads: Mutex<HashMap<String, Upload>>,
+}
+
+impl SharedDir {
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            uploads: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn folder_name(&self) -> String {
+        self.root
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| self.root.display().to_string())
+    }
+
+    /// Map a relay path ("/a/b.txt") to a path under the shared root.
+    /// Rejects `..`, drive prefixes and symlinks anywhere in the path: a
+    /// symlink may lead out of the root, and writing through a dangling one
+    /// would create its target wherever it points.
+    fn resolve(&self, path: &str) -> Result<PathBuf, Failure> {
+        let mut resolved = self.root.clone();
+        for segment in path.split('/').filter(|s| !s.is_empty() && *s != ".") {
+            let mut components = Path::new(segment).components();
+            match (components.next(), components.next()) {
+                (Some(Component::Normal(name)), None) => resolved.push(name),
+                _ => return Err(fail(ErrorCode::InvalidPath, format!("invalid path: {}", path))),
+            }
+            match std::fs::symlink_metadata(&resolved) {
+                Ok(meta) if meta.file_type().is_symlink() => {
+                    return Err(fail(
+                        ErrorCode::PermissionDenied,
+                        format!("permission denied: {} goes through a symlink", path),
+                    ));
+                }
+                Ok(_) => {}
+                // Not created yet (the rest of the path cannot exist either)
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(io_error(e, path)),
+            }
+        }
+        Ok(resolved)
+    }
+
+    pub async fn handle(&self, request: Request, progress: ReportProgress<'_>) -> Result<Reply, Failure> {
+        let result = match request {
+            Request::Readdir { path } => reply(self.readdir(&path).await?),
+            Request::ReadFile { path } => reply(self.read_file(&path).await?),
+            Request::ReadChunk {
+                path,
+                offset,
+                length,
+                binary,
+            } => return self.read_chunk(&path, offset, length, binary).await,
+            Request::WriteFile { path, data } => {
+                let target = self.resolve(&path)?;
+                let data = base64_decode(&data)?;
+                fs::write(&target, &data).await.map_err(|e| io_error(e, &path))?;
+                reply(Written {
+                    written: data.len() as u64,
+                })
+            }
+            Request::UploadOpen { upload_id, path } => reply(self.upload_open(&upload_id, &path).await?),
+            Request::UploadChunk {
+                upload_id,
+                seq,
+                data,
+            } => {
+                let data = base64_decode(&data)?;
+                reply(self.upload_chunk(&upload_id, seq, &data).await?)
+            }
+            Request::UploadCommit { upload_id } => reply(self.upload_commit(&upload_id).await?),
+            Request::UploadAbort { upload_id } => {
+                self.upload_abort(&upload_id).await;
+                reply(json!({"aborted": true}))
+            }
+            Request::Mkdir { path } => {
+                let target = self.resolve(&path)?;
+                fs::create_dir_all(&target).await.map_err(|e| io_error(e, &path))?;
+                reply(json!({"created": true}))
+            }
+            Request::Delete { path } => {
+                let target = self.resolve(&path)?;
+                if target == self.root {
+                    return Err(fail(
+                        ErrorCode::PermissionDenied,
+                        "permission denied: cannot delete the shared folder",
+                    ));
+                }
+                let meta = fs::symlink_metadata(&target).await.map_err(|e| io_error(e, &path))?;
+                let result = if meta.is_dir() {
+                    fs::remove_dir_all(&target).await
+                } else {
+                    fs::remove_file(&target).await
+                };
+                result.map_err(|e| io_error(e, &path))?;
+                reply(json!({"deleted": true}))
+            }
+            Request::Rename { old_path, new_path } => {
+                let from = self.resolve(&old_path)?;
+                let to = self.resolve(&new_path)?;
+                if from == self.root || to == self.root {
+                    return Err(fail(
+                        ErrorCode::PermissionDenied,
+                        "permission denied: cannot rename the shared folder",
+                    ));
+                }
+                // rename(2) silently replaces an existing file (and an empty directory)
+                if fs::symlink_metadata(&to).await.is_ok() {
+                    return Err(fail(
+                        ErrorCode::AlreadyExists,
+                        format!("{}: target already exists", new_path),
+                    ));
+                }
+                // Native rename also moves directories (Capability::DirRename)
+                fs::rename(&from, &to).await.map_err(|e| io_error(e, &old_path))?;
+                reply(json!({"renamed": true}))
+            }
+            Request::Copy { old_path, new_path } => {
+                let from = self.resolve(&old_path)?;
+                let to = self.resolve(&new_path)?;
+                if to == self.root {
+                    return Err(fail(
+                        ErrorCode::PermissionDenied,
+                        "permission denied: cannot replace the shared folder",
+                    ));
+                }
+                if to.starts_with(&from) {
+                    return Err(fail(
+                        ErrorCode::PermissionDenied,
+                        "permission denied: cannot copy a folder into itself",
+                    ));
+                }
+                let copied = copy_tree(&from, &to, progress).await.map_err(|e| io_error(e, &old_path))?;
+                reply(json!({"copied": copied}))
+            }
+            Request::Stat { path } => reply(self.stat(&path).await?),
+        };
+        Ok(result)
+    }
+
+    /// A binary frame carrying upload data (seq is the frame sequence number).
+    pub async fn upload_frame(&self, upload_id: &str, seq: u32, data: &[u8]) -> Result<Written, Failure> {
+        self.upload_chunk(upload_id, seq as u64, data).await
+    }
+
+    async fn readdir(&self, path: &str) -> Result<Vec<FileStat>, Failure> {
+        let dir = self.resolve(path)?;
+        let mut reader = fs::read_dir(&dir).await.map_err(|e| io_error(e, path))?;
+        let mut entries = Vec::new();
+        while let Some(entry) = reader.next_entry().await.map_err(|e| io_error(e, path))? {
+            // Entries that vanish or cannot be read are skipped, like Explorer does
+            let Ok(meta) = entry.metadata().await else {
+                continue;
+            };
+            entries.push(file_stat(entry.file_name().to_string_lossy().into_owned(), &meta));
+        }
+        entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+        Ok(entries)
+    }
+
+    async fn read_file(&self, path: &str) -> Result<FileContent, Failure> {
+        let target = self.resolve(path)?;
+        let data = fs::read(&target).await.map_err(|e| io_error(e, path))?;
+        let meta = fs::metadata(&target).await.map_err(|e| io_error(e, path))?;
+        Ok(FileContent {
+            data: base64_encode(&data),
+            size: data.len() as u64,
+            name: target
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            mime_type: String::new(),
+            modified: iso_time(meta.modified().ok()),
+        })
+    }
+
+    async fn read_chunk(&self, path: &str, offset: u64, length: u64, binary: bool) -> Result<Reply, Failure> {
+        let target = self.resolve(path)?;
+        let mut file = fs::File::open(&target).await.map_err(|e| io_error(e, path))?;
+        let meta = file.metadata().await.map_err(|e| io_error(e, path))?;
+        let size = meta.len();
+        let offset = offset.min(size);
+        let length = length.min(MAX_CHUNK_SIZE);
+        let end = size.min(offset + length);
+
+        let mut bytes = vec![0u8; (end - offset) as usize];
+        file.seek(std::io::SeekFrom::Start(offset))
+            .await
+            .map_err(|e| io_error(e, path))?;
+        file.read_exact(&mut bytes).await.map_err(|e| io_error(e, path))?;
+
+        if binary {
+            return Ok(Reply::Binary(bytes));
+        }
+        Ok(reply(ChunkContent {
+            data: base64_encode(&bytes),
+            offset,
+            size,
+            modified: iso_time(meta.modified().ok()),
+            eof: end >= size,
+        }))
+    }
+
+    async fn stat(&self, path: &str) -> Result<FileStat, Failure> {
+        let target = self.resolve(path)?;
+        let meta = fs::metadata(&target).await.map_err(|e| io_error(e, path))?;
+        let name = if target == self.root {
+            self.folder_name()
+        } else {
+            target
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+        Ok(file_stat(name, &meta))
+    }
+
+    async fn upload_open(&self, upload_id: &str, path: &str) -> Result<Value, Failure> {
+        if uuid::Uuid::parse_str(upload_id).is_err() {
+            return Err(fail(ErrorCode::InvalidRequest, "invalid upload id"));
+        }
+        let target = self.resolve(path)?;
+        let file_name = target
+            .file_name()
+            .ok_or_else(|| fail(ErrorCode::InvalidPath, format!("invalid path: {}", path)))?
+            .to_string_lossy()
+            .into_owned();
+        let temp_path = target.with_file_name(format!(".{}.ljc-upload-{}", file_name, &upload_id[..8]));
+
+        let mut uploads = self.uploads.lock().await;
+        if uploads.contains_key(upload_id) {
+            return Err(fail(ErrorCode::InvalidRequest, "invalid upload id"));
+        }
+        let file = fs::File::create(&temp_path).await.map_err(|e| io_error(e, path))?;
+        uploads.insert(
+            upload_id.to_string(),
+            Upload {
+                file,
+                temp_path,
+                target,
+                next_seq: 0,
+                written: 0,
+            },
+        );
+        Ok(json!({"uploadId": upload_id}))
+    }
+
+    async fn upload_chunk(&self, upload_id: &str, seq: u64, data: &[u8]) -> Result<Written, Failure> {
+        let mut uploads = self.uploads.lock().await;
+        let upload = uploads
+            .get_mut(upload_id)
+            .ok_or_else(|| fail(ErrorCode::InvalidRequest, format!("upload not found: {}", upload_id)))?;
+        if seq != upload.next_seq {
+            return Err(fail(
+                ErrorCode::InvalidRequest,
+                format!("chunk out of order: {} (expected {})", seq, upload.next_seq),
+            ));
+        }
+        upload
+            .file
+            .write_all(data)
+            .await
+            .map_err(|e| io_error(e, &upload.target.display().to_string()))?;
+        upload.next_seq += 1;
+        upload.written += data.len() as u64;
+        Ok(Written {
+            written: upload.written,
+        })
+    }
+
+    async fn upload_commit(&self, upload_id: &str) -> Result<Written, Failure> {
+        let mut upload = self
+            .uploads
+            .lock()
+            .await
+            .remove(upload_id)
+            .ok_or_else(|| fail(ErrorCode::InvalidRequest, format!("upload not found: {}", upload_id)))?;
+        let target = upload.target.display().to_string();
+        let result = async {
+            upload.file.flush().await?;
+            upload.file.sync_all().await?;
+            fs::rename(&upload.temp_path, &upload.target).await
+        }
+        .await;
+        if let Err(e) = result {
+            let _ = fs::remove_file(&upload.temp_path).await;
+            return Err(io_error(e, &target));
+        }
+        Ok(Written {
+            written: upload.written,
+        })
+    }
+
+    async fn upload_abort(&self, upload_id: &str) {
+        if let Some(upload) = self.uploads.lock().await.remove(upload_id) {
+            drop(upload.file);
+            let _ = fs::remove_file(&upload.temp_path).await;
+        }
+    }
+
+    /// Discard all uploads (connection lost: the server will not commit them).
+    pub async fn abort_all_uploads(&self) {
+        let ids: Vec<String> = self.uploads.lock().await.keys().cloned().collect();
+        for id in ids {
+            self.upload_abort(&id).await;
+        }
+    }
+}
+
+/// Copy a file, or a directory recursively, returning the number of files
+/// copied. Symlinks inside a directory are skipped so a copy never pulls in
+/// content from outside the shared folder. Bytes copied are reported to
+/// `progress`, so a large copy does not run into the relay timeout.
+///
+/// Nothing is left behind when the copy fails or is cancelled: a directory
+/// is removed again, and a file is copied to a temporary file that only
+/// replaces the target once complete.
+async fn copy_tree(from: &Path, to: &Path, progress: ReportProgress<'_>) -> std::io::Result<u64> {
+    // List everything first, so progress has a total
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    let meta = fs::metadata(from).await?;
+    if meta.is_dir() {
+        if fs::try_exists(to).await? {
+            return Err(std::io::Error::new(ErrorKind::AlreadyExists, "target already exists"));
+        }
+        let mut pending = vec![(from.to_path_buf(), to.to_path_buf())];
+        while let Some((src, dst)) = pending.pop() {
+            let mut reader = fs::read_dir(&src).await?;
+            while let Some(entry) = reader.next_entry().await? {
+                let file_type = entry.file_type().await?;
+                let target = dst.join(entry.file_name());
+                if file_type.is_dir() {
+                    pending.push((entry.path(), target));
+                } else if file_type.is_file() {
+                    files.push((entry.path(), target, entry.metadata().await?.len()));
+                }
+            }
+            // Parents are listed before their subdirectories
+            dirs.push(dst);
+        }
+    } else {
+        files.push((from.to_path_buf(), to.to_path_buf(), meta.len()));
+    }
+
+    let total = files.iter().map(|(_, _, len)| len).sum();
+    let mut done = 0;
+    if dirs.is_empty() {
+        let file_name = to.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let temp_path = to.with_file_name(format!(".{}.ljc-copy", file_name));
+        let partial = PartialCopy::new(temp_path.clone());
+        copy_file(from, &temp_path, &mut done, total, progress).await?;
+        fs::rename(&temp_path, to).await?;
+        partial.keep();
+        return Ok(1);
+    }
+    // dirs[0] is `to` itself. Only remove it once this copy has created it.
+    fs::create_dir(to).await?;
+    let partial = PartialCopy::new(to.to_path_buf());
+    for dir in &dirs[1..] {
+        fs::create_dir(dir).await?;
+    }
+    for (src, dst, _) in &files {
+        copy_file(src, dst, &mut done, total, progress).await?;
+    }
+    partial.keep();
+    Ok(files.len() as u64)
+}
+
+/// Removes what a copy has created so far when dropped before `keep`, i.e.
+/// when the copy failed or its task was aborted.
+struct PartialCopy {
+    path: Option<PathBuf>,
+}
+
+impl PartialCopy {
+    fn new(path: PathBuf) -> Self {
+        Self { path: Some(path) }
+    }
+
+    /// The copy is complete: keep it.
+    fn keep(mut self) {
+        self.path = None;
+    }
+}
+
+impl Drop for PartialCopy {
+    fn drop(&mut self) {
+        let Some(path) = self.path.take() else {
+            return;
+        };
+        // Drop cannot await, and an aborted task may be dropped on any thread
+        let remove = move || {
+            let result = match std::fs::symlink_metadata(&path) {
+                Ok(meta) if meta.is_dir() => std::fs::remove_dir_all(&path),
+                Ok(_) => std::fs::remove_file(&path),
+                Err(_) => Ok(()),
+            };
+            if let Err(e) = result {
+                tracing::warn!("Could not remove partial copy {}: {}", path.display(), e);
+            }
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(remove)),
+            Err(_) => remove(),
+        }
+    }
+}
+
+/// Copy one file in chunks, adding the bytes copied to `done`.
+async fn copy_file(
+    from: &Path,
+    to: &Path,
+    done: &mut u64,
+    total: u64,
+    progress: ReportProgress<'_>,
+) -> std::io::Result<()> {
+    let mut src = fs::File::open(from).await?;
+    let mut dst = fs::File::create(to).await?;
+    let name = from.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut buf = vec![0; COPY_BUFFER_SIZE];
+    loop {
+        let n = src.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        dst.write_all(&buf[..n]).await?;
+        *done += n as u64;
+        progress(*done, total, &name);
+    }
+    dst.flush().await?;
+    fs::set_permissions(to, src.metadata().await?.permissions()).await
+}
+
+/// Successful JSON reply.
+fn reply(data: impl serde::Serialize) -> Reply {
+    Reply::Json(serde_json::to_value(data).unwrap_or(Value::Null))
+}
+
+fn file_stat(name: String, meta: &std::fs::Metadata) -> FileStat {
+    FileStat {
+        name,
+        is_dir: meta.is_dir(),
+        size: if meta.is_dir() { 0 } else { meta.len() },
+        modified: iso_time(meta.modified().ok()),
+    }
+}
+
+fn io_error(e: std::io::Error, path: &str) -> Failure {
+    fail(ErrorCode::from_io(&e), format!("{}: {}", path, e))
+}
+
+/// Format a timestamp like JavaScript's `Date.toISOString()`.
+fn iso_time(time: Option<SystemTime>) -> String {
+    let time = time.unwrap_or_else(SystemTime::now);
+    let since_epoch = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, rem) = (secs / 86400, secs % 86400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+const BASE64_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_CHARS[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn base64_decode(s: &str) -> Result<Vec<u8>, Failure> {
+    let mut out = Vec::with_capacity(s.len() / 4 * 3);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in s.bytes().filter(|c| !c.is_ascii_whitespace() && *c != b'=') {
+        let v = BASE64_CHARS
+            .iter()
+            .position(|&x| x == c)
+            .ok_or_else(|| fail(ErrorCode::InvalidRequest, "invalid base64 data"))? as u32;
+        acc = acc << 6 | v;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh shared folder under the temp dir, removed on drop.
+    struct TempShare(PathBuf);
+
+    impl TempShare {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("ljc-agent-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(dir.join("sub")).unwrap();
+            Self(dir.canonicalize().unwrap())
+        }
+    }
+
+    impl Drop for TempShare {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn code(result: Result<PathBuf, Failure>) -> Option<ErrorCode> {
+        result.err().map(|f| f.code)
+    }
+
+    #[test]
+    fn resolve_inside_root() {
+        let share = TempShare::new("inside");
+        let dir = SharedDir::new(share.0.clone());
+        assert_eq!(dir.resolve("/").ok(), Some(share.0.clone()));
+        assert_eq!(dir.resolve("/sub/./new.txt").ok(), Some(share.0.join("sub/new.txt")));
+        assert_eq!(dir.resolve("/missing/a/b").ok(), Some(share.0.join("missing/a/b")));
+        assert_eq!(code(dir.resolve("/sub/../..")), Some(ErrorCode::InvalidPath));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolve_refuses_symlinks() {
+        use std::os::unix::fs::symlink;
+        let share = TempShare::new("symlinks");
+        let dir = SharedDir::new(share.0.clone());
+        // Dangling: a write through it would create the target outside the root
+        symlink(std::env::temp_dir().join("ljc-agent-nowhere"), share.0.join("dangling")).unwrap();
+        // Even a link that stays inside the root is refused
+        symlink(share.0.join("sub"), share.0.join("inner")).unwrap();
+        symlink("/", share.0.join("sub/escape")).unwrap();
+
+        assert_eq!(code(dir.resolve("/dangling")), Some(ErrorCode::PermissionDenied));
+        assert_eq!(code(dir.resolve("/dangling/new")), Some(ErrorCode::PermissionDenied));
+        assert_eq!(code(dir.resolve("/inner/file")), Some(ErrorCode::PermissionDenied));
+        assert_eq!(code(dir.resolve("/sub/escape/etc/passwd")), Some(ErrorCode::PermissionDenied));
+        assert_eq!(code(dir.resolve("/sub/file")), None);
+    }
+
+    /// Wait for the background removal of a dropped PartialCopy.
+    async fn gone(path: &Path) -> bool {
+        for _ in 0..100 {
+            if std::fs::symlink_metadata(path).is_err() {
+                return true;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn copy_tree_removes_partial_copy() {
+        let share = TempShare::new("partial");
+        for name in ["a", "b", "c"] {
+            std::fs::write(share.0.join("sub").join(name), name).unwrap();
+        }
+        std::fs::write(share.0.join("target.txt"), "keep").unwrap();
+
+        // Fails after the first file: the half-copied directory goes away
+        let (from, to) = (share.0.join("sub"), share.0.join("copy"));
+        let copy = tokio::spawn(async move {
+            let progress = |_: u64, _: u64, _: &str| panic!("copy fails");
+            copy_tree(&from, &to, &progress).await
+        });
+        assert!(copy.await.is_err());
+        assert!(gone(&share.0.join("copy")).await);
+
+        // A file is copied aside: the existing target stays as it was
+        let (from, to) = (share.0.join("sub/a"), share.0.join("target.txt"));
+        let copy = tokio::spawn(async move {
+            let progress = |_: u64, _: u64, _: &str| panic!("copy fails");
+            copy_tree(&from, &to, &progress).await
+        });
+        assert!(copy.await.is_err());
+        assert!(gone(&share.0.join(".target.txt.ljc-copy")).await);
+        assert_eq!(std::fs::read_to_string(share.0.join("target.txt")).unwrap(), "keep");
+
+        // Complete copies are kept
+        let copied = copy_tree(&share.0.join("sub"), &share.0.join("copy"), &|_, _, _| {}).await;
+        assert_eq!(copied.ok(), Some(3));
+        assert_eq!(std::fs::read_to_string(share.0.join("copy/b")).unwrap(), "b");
+    }
+
+    #[tokio::test]
+    async fn rename_refuses_existing_target() {
+        let share = TempShare::new("rename");
+        std::fs::write(share.0.join("a.txt"), "a").unwrap();
+        std::fs::write(share.0.join("b.txt"), "b").unwrap();
+        let dir = SharedDir::new(share.0.clone());
+        let request = Request::Rename {
+            old_path: "/a.txt".to_string(),
+            new_path: "/b.txt".to_string(),
+        };
+        let result = dir.handle(request, &|_, _, _| {}).await;
+        assert_eq!(result.err().map(|f| f.code), Some(ErrorCode::AlreadyExists));
+        assert_eq!(std::fs::read_to_string(share.0.join("b.txt")).unwrap(), "b");
+    }
+}
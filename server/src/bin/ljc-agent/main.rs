@@ -0,0 +1,399 @@
+// SNOWCODE - ソフトウェア製品
+// (C) SNOWCODE
+// 開発者: 雪符しき
+// https://snowcode.jp
+// 問い合わせ: info@snowcode.jp
+
+// Headless client agent: shares a local directory with ljc-server over the
+// same /ws protocol as the browser connect page, without a browser.
+
+mod commands;
+#[path = "../../frame.rs"]
+#[allow(dead_code)]
+mod frame;
+#[path = "../../protocol.rs"]
+#[allow(dead_code)]
+mod protocol;
+#[path = "../../secret_file.rs"]
+#[allow(dead_code)]
+mod secret_file;
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::task::AbortHandle;
+use tokio_tungstenite::tungstenite::Message;
+
+use commands::{Reply, SharedDir};
+use frame::FrameOp;
+use protocol::{
+    Capability, ClientMessage, Envelope, ErrorCode, Progress, Register, Registered, Response, ServerMessage,
+};
+
+const USAGE: &str = "\
+Usage: ljc-agent --server <host[:port]> --token <token> --dir <path> [options]
+
+Options:
+  --server <addr>      Server address, host[:port] or ws://host:port/ws ($LJC_AGENT_SERVER)
+  --token <token>      Token with the register scope ($LJC_AGENT_TOKEN)
+  --dir <path>         Directory to share ($LJC_AGENT_DIR)
+  --name <name>        Name shown in the dashboard (default: host name) ($LJC_AGENT_NAME)
+  --identity <file>    Device identity file (default: ~/.ljc-agent/identity.json)
+  --help               Show this help";
+
+const DEFAULT_PORT: u16 = 17200;
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Minimum time between progress reports, same as the connect page.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Args {
+    url: String,
+    token: String,
+    dir: PathBuf,
+    name: String,
+    identity: PathBuf,
+}
+
+/// Stable device identity, so reconnects keep the same client id
+/// (and the same /webdav/<client_id>/ URL).
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Identity {
+    device_id: String,
+    device_key: String,
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "ljc_agent=info".into()),
+        )
+        .init();
+
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("ljc-agent: {}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let identity = match load_identity(&args.identity) {
+        Ok(identity) => identity,
+        Err(e) => {
+            eprintln!("ljc-agent: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let shared = Arc::new(SharedDir::new(args.dir.clone()));
+    tracing::info!("Sharing {} as '{}'", args.dir.display(), args.name);
+
+    // Reconnect forever with exponential backoff (1s, 2s, 4s ... 30s)
+    let mut backoff = Duration::from_secs(1);
+    loop {
+        match run_session(&args, &identity, &shared).await {
+            Ok(()) => {
+                tracing::info!("Disconnected from server");
+                backoff = Duration::from_secs(1);
+            }
+            Err(e) => tracing::warn!("{:#}", e),
+        }
+        shared.abort_all_uploads().await;
+        tracing::info!("Reconnecting in {}s", backoff.as_secs());
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Option<Args>, String> {
+    let env = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+    let mut server = env("LJC_AGENT_SERVER");
+    let mut token = env("LJC_AGENT_TOKEN");
+    let mut dir = env("LJC_AGENT_DIR");
+    let mut name = env("LJC_AGENT_NAME");
+    let mut identity = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| args.next().ok_or(format!("{} requires a value", flag));
+        match arg.as_str() {
+            "--server" | "-s" => server = Some(value(&arg)?),
+            "--token" | "-t" => token = Some(value(&arg)?),
+            "--dir" | "-d" => dir = Some(value(&arg)?),
+            "--name" | "-n" => name = Some(value(&arg)?),
+            "--identity" => identity = Some(PathBuf::from(value(&arg)?)),
+            "--help" | "-h" => return Ok(None),
+            _ => return Err(format!("unknown argument '{}'", arg)),
+        }
+    }
+
+    let server = server.ok_or("--server is required")?;
+    let token = token.ok_or("--token is required")?;
+    let dir = dir.ok_or("--dir is required")?;
+    let dir = PathBuf::from(expand_tilde(&dir))
+        .canonicalize()
+        .map_err(|e| format!("--dir {}: {}", dir, e))?;
+    if !dir.is_dir() {
+        return Err(format!("--dir {}: not a directory", dir.display()));
+    }
+    let name = name.unwrap_or_else(host_name);
+    let identity = identity
+        .unwrap_or_else(|| PathBuf::from(expand_tilde("~/.ljc-agent/identity.json")));
+
+    Ok(Some(Args {
+        url: ws_url(&server),
+        token,
+        dir,
+        name,
+        identity,
+    }))
+}
+
+/// Accept `host`, `host:port` or a full `ws://` URL.
+fn ws_url(server: &str) -> String {
+    if server.starts_with("ws://") || server.starts_with("wss://") {
+        return server.to_string();
+    }
+    let host = server.trim_start_matches("http://").trim_end_matches('/');
+    if host.contains(':') {
+        format!("ws://{}/ws", host)
+    } else {
+        format!("ws://{}:{}/ws", host, DEFAULT_PORT)
+    }
+}
+
+fn host_name() -> String {
+    ["COMPUTERNAME", "HOSTNAME"]
+        .iter()
+        .find_map(|v| std::env::var(v).ok())
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "ljc-agent".to_string())
+}
+
+fn expand_tilde(path: &str) -> String {
+    if path.starts_with("~/") || path == "~" {
+        if let Ok(home) = std::env::var("HOME").or_else(|_| std::env::var("USERPROFILE")) {
+            return path.replacen('~', &home, 1);
+        }
+    }
+    path.to_string()
+}
+
+fn load_identity(path: &PathBuf) -> Result<Identity> {
+    if let Ok(text) = std::fs::read_to_string(path) {
+        return serde_json::from_str(&text)
+            .with_context(|| format!("invalid identity file {}", path.display()));
+    }
+    let identity = Identity {
+        device_id: uuid::Uuid::new_v4().simple().to_string(),
+        device_key: uuid::Uuid::new_v4().simple().to_string() + &uuid::Uuid::new_v4().simple().to_string(),
+    };
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+    }
+    // The device key lets anyone take over this client id: owner only
+    secret_file::write(path, serde_json::to_string_pretty(&identity)?)
+        .with_context(|| format!("write {}", path.display()))?;
+    tracing::info!("Created device identity {}", path.display());
+    Ok(identity)
+}
+
+/// One connection: register, then serve commands until the socket closes.
+async fn run_session(args: &Args, identity: &Identity, shared: &Arc<SharedDir>) -> Result<()> {
+    let (socket, _) = tokio_tungstenite::connect_async(args.url.as_str())
+        .await
+        .with_context(|| format!("connect to {}", args.url))?;
+    let (mut ws_tx, mut ws_rx) = socket.split();
+
+    let register = Register {
+        token: args.token.clone(),
+        name: args.name.clone(),
+        folder_name: shared.folder_name(),
+        device_id: identity.device_id.clone(),
+        device_key: identity.device_key.clone(),
+        // The persistent identity already brings back the same client id
+        resume_token: String::new(),
+        binary_frames: true,
+        protocol_version: protocol::PROTOCOL_VERSION,
+        capabilities: protocol::CAPABILITIES.to_vec(),
+    };
+    ws_tx
+        .send(Message::Text(serde_json::to_string(&register)?.into()))
+        .await?;
+
+    // The server closes the socket without a reply when registration is refused
+    let registered = loop {
+        match ws_rx.next().await {
+            Some(Ok(Message::Text(text))) => {
+                if let Ok(registered) = serde_json::from_str::<Registered>(&text) {
+                    break registered;
+                }
+            }
+            Some(Ok(Message::Close(_))) | None => {
+                bail!("registration rejected (check the token and its register scope)")
+            }
+            Some(Ok(_)) => {}
+            Some(Err(e)) => return Err(e.into()),
+        }
+    };
+    tracing::info!(
+        "Registered as {} ({}, protocol v{})",
+        registered.client_id,
+        args.url,
+        registered.protocol_version
+    );
+    // Binary frames and progress are only sent when the server negotiated them
+    let binary_frames = registered.capabilities.contains(&Capability::BinaryFrames);
+    let progress = registered.capabilities.contains(&Capability::Progress);
+
+    let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
+    let send_task = tokio::spawn(async move {
+        while let Some(msg) = rx.recv().await {
+            if ws_tx.send(msg).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    // Requests being worked on, so a cancel can abort them
+    let inflight: Arc<Mutex<HashMap<String, AbortHandle>>> = Arc::default();
+
+    let result = loop {
+        let msg = match ws_rx.next().await {
+            Some(Ok(msg)) => msg,
+            Some(Err(e)) => break Err(anyhow!(e)),
+            None => break Ok(()),
+        };
+        match msg {
+            Message::Text(text) => {
+                let envelope = match serde_json::from_str::<ServerMessage>(&text) {
+                    Ok(ServerMessage::Request(envelope)) => envelope,
+                    Ok(ServerMessage::Cancel { id }) => {
+                        // The server's caller went away; stop working on it
+                        if let Some(task) = inflight.lock().unwrap().remove(&id) {
+                            tracing::debug!("Cancelled {}", &id[..8.min(id.len())]);
+                            task.abort();
+                        }
+                        continue;
+                    }
+                    Err(e) => {
+                        // Still answer requests of unknown types, so the server does not wait
+                        tracing::debug!("Invalid request: {}", e);
+                        if let Some(id) = request_id(&text) {
+                            let error = format!("unsupported request: {}", e);
+                            send_json(&tx, &Response::failure(id, ErrorCode::Unsupported, error));
+                        }
+                        continue;
+                    }
+                };
+                // Hold the lock while spawning, so the task cannot finish
+                // (and remove itself) before it is registered
+                let mut tasks = inflight.lock().unwrap();
+                let id = envelope.id.clone();
+                let (shared, tx, inflight) = (shared.clone(), tx.clone(), inflight.clone());
+                let task = tokio::spawn(async move {
+                    let id = envelope.id.clone();
+                    handle_request(&shared, &tx, envelope, binary_frames, progress).await;
+                    inflight.lock().unwrap().remove(&id);
+                });
+                tasks.insert(id, task.abort_handle());
+            }
+            Message::Binary(data) => {
+                // Upload data; acknowledged with a JSON reply to the upload id
+                match frame::decode(data) {
+                    Some(f) if f.op == FrameOp::UploadChunk => {
+                        let (shared, tx) = (shared.clone(), tx.clone());
+                        tokio::spawn(async move {
+                            let response = match shared.upload_frame(&f.id, f.seq, &f.payload).await {
+                                Ok(written) => Response::success(f.id, written),
+                                Err(e) => Response::failure(f.id, e.code, e.message),
+                            };
+                            send_json(&tx, &response);
+                        });
+                    }
+                    _ => tracing::debug!("Ignoring invalid binary frame"),
+                }
+            }
+            Message::Close(_) => break Ok(()),
+            _ => {}
+        }
+    };
+    send_task.abort();
+    for (_, task) in inflight.lock().unwrap().drain() {
+        task.abort();
+    }
+    result
+}
+
+/// Run one request and send its reply.
+async fn handle_request(
+    shared: &SharedDir,
+    tx: &mpsc::UnboundedSender<Message>,
+    envelope: Envelope,
+    binary_frames: bool,
+    progress: bool,
+) {
+    let Envelope { id, mut request } = envelope;
+    if let protocol::Request::ReadChunk { binary, .. } = &mut request {
+        *binary &= binary_frames;
+    }
+    let cmd_type = request.name();
+    // Each report restarts the server's relay timeout for this request
+    let last_report = Mutex::new(None::<Instant>);
+    let report = |done: u64, total: u64, current: &str| {
+        let mut last = last_report.lock().unwrap();
+        if !progress || (done < total && last.is_some_and(|t| t.elapsed() < PROGRESS_INTERVAL)) {
+            return;
+        }
+        *last = Some(Instant::now());
+        let message = ClientMessage::Progress(Progress {
+            id: id.clone(),
+            done,
+            total,
+            current: current.to_string(),
+        });
+        if let Ok(text) = serde_json::to_string(&message) {
+            let _ = tx.send(Message::Text(text.into()));
+        }
+    };
+    let response = match shared.handle(request, &report).await {
+        Ok(Reply::Json(data)) => Response::success(id, data),
+        Ok(Reply::Binary(bytes)) => match frame::encode(FrameOp::ChunkData, &id, 0, &bytes) {
+            Some(f) => {
+                let _ = tx.send(Message::Binary(f));
+                return;
+            }
+            None => Response::failure(id, ErrorCode::InvalidRequest, "invalid request id"),
+        },
+        Err(e) => {
+            tracing::debug!("{} failed: {}", cmd_type, e.message);
+            Response::failure(id, e.code, e.message)
+        }
+    };
+    send_json(tx, &response);
+}
+
+fn send_json(tx: &mpsc::UnboundedSender<Message>, response: &Response) {
+    if let Ok(text) = serde_json::to_string(response) {
+        let _ = tx.send(Message::Text(text.into()));
+    }
+}
+
+/// The `id` of a request that could not be parsed as an `Envelope`.
+fn request_id(text: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(text).ok()?;
+    Some(value.get("id")?.as_str()?.to_string())
+}
@@ -0,0 +1,246 @@
+// SNOWCODE - ソフトウェア製品
+// (C) SNOWCODE
+// 開発者: 雪符しき
+// https://snowcode.jp
+// 問い合わせ: info@snowcode.jp
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::Value;
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::config::AppConfig;
+use crate::tls;
+use crate::tokens::{TokenStore, DEFAULT_TOKEN_NAME};
+
+pub const USAGE: &str = "\
+Usage: ljc-server [--config <path>] [command]
+
+Commands:
+  serve                    Start the relay server (default)
+  token list               List API tokens
+  token show [name]        Print a token's secret (default: \"default\")
+  token rotate [name]      Issue a new secret for a token (default: \"default\")
+  cert show-fingerprint    Print the SHA-256 fingerprint of the HTTPS certificate
+  cert regenerate          Replace the self-signed HTTPS certificate
+  clients list             List clients connected to the running server
+  config check             Validate the configuration and print the effective settings
+  help                     Show this help
+
+Options:
+  --config <path>          TOML config file (default: $LJC_CONFIG)";
+
+pub enum Command {
+    Serve,
+    TokenList,
+    TokenShow { name: String },
+    TokenRotate { name: String },
+    CertShowFingerprint,
+    CertRegenerate,
+    ClientsList,
+    ConfigCheck,
+    Help,
+}
+
+pub struct Cli {
+    pub config: Option<PathBuf>,
+    pub command: Command,
+}
+
+/// Parse command-line arguments (without the program name).
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Cli, String> {
+    let mut config = None;
+    let mut words = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" | "-c" => {
+                let path = args.next().ok_or("--config requires a path")?;
+                config = Some(PathBuf::from(path));
+            }
+            "--help" | "-h" => words.push("help".to_string()),
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+            _ => words.push(arg),
+        }
+    }
+
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    let name = |rest: &[&str]| rest.first().unwrap_or(&DEFAULT_TOKEN_NAME).to_string();
+    let command = match words.as_slice() {
+        [] | ["serve"] => Command::Serve,
+        ["token", "list"] => Command::TokenList,
+        ["token", "show", rest @ ..] if rest.len() <= 1 => Command::TokenShow { name: name(rest) },
+        ["token", "rotate", rest @ ..] if rest.len() <= 1 => Command::TokenRotate { name: name(rest) },
+        ["cert", "show-fingerprint"] => Command::CertShowFingerprint,
+        ["cert", "regenerate"] => Command::CertRegenerate,
+        ["clients", "list"] => Command::ClientsList,
+        ["config", "check"] => Command::ConfigCheck,
+        ["help"] => Command::Help,
+        _ => return Err(format!("unknown command '{}'", words.join(" "))),
+    };
+    Ok(Cli { config, command })
+}
+
+/// Run a management command (everything except `serve`).
+pub fn run(command: Command, config: &AppConfig) -> Result<()> {
+    match command {
+        Command::Serve => unreachable!("serve is handled by main"),
+        Command::Help => println!("{}", USAGE),
+        Command::TokenList => token_list(config)?,
+        Command::TokenShow { name } => {
+            let store = load_tokens(config)?;
+            let token = find_token(&store, &name)?;
+            println!("{}", token);
+        }
+        Command::TokenRotate { name } => token_rotate(config, &name)?,
+        Command::CertShowFingerprint => {
+            let fingerprint = tls::certificate_fingerprint(&config.tls)?;
+            println!("{}", tls::certificate_path(&config.tls).display());
+            println!("SHA-256 {}", fingerprint);
+        }
+        Command::CertRegenerate => {
+            let local_ip = local_ip_address::local_ip()
+                .map(|ip| ip.to_string())
+                .unwrap_or_else(|_| "127.0.0.1".to_string());
+            let path = tls::regenerate_self_signed(&local_ip, &config.tls)?;
+            println!("Generated {} for {}", path.display(), local_ip);
+            println!("SHA-256 {}", tls::certificate_fingerprint(&config.tls)?);
+            println!("Restart the server to use the new certificate.");
+        }
+        Command::ClientsList => clients_list(config)?,
+        Command::ConfigCheck => {
+            println!("Configuration OK");
+            print!("{}", toml::to_string(config)?);
+        }
+    }
+    Ok(())
+}
+
+/// The token store, as the server would load it. On first use this creates
+/// the built-in tokens, so the data directory must exist to save them.
+fn load_tokens(config: &AppConfig) -> Result<TokenStore> {
+    config.create_data_dir()?;
+    Ok(TokenStore::load(config.tokens_file())?)
+}
+
+fn find_token(store: &TokenStore, name: &str) -> Result<String> {
+    store
+        .list()
+        .iter()
+        .find(|t| t.name == name)
+        .map(|t| t.token.clone())
+        .ok_or_else(|| anyhow!("no token named '{}'", name))
+}
+
+fn token_list(config: &AppConfig) -> Result<()> {
+    let store = load_tokens(config)?;
+    println!("{:<16} {:<36} {:<10} SCOPES", "NAME", "ID", "PREFIX");
+    for t in store.list() {
+        let scopes: Vec<&str> = t.scopes.iter().map(|s| s.as_str()).collect();
+        let clients = match &t.clients {
+            Some(ids) => format!(" (clients: {})", ids.join(", ")),
+            None => String::new(),
+        };
+        println!(
+            "{:<16} {:<36} {:<10} {}{}",
+            t.name,
+            t.id,
+            &t.token[..8.min(t.token.len())],
+            scopes.join(","),
+            clients
+        );
+    }
+    Ok(())
+}
+
+/// Rotate through the running server when it is up (it owns the token
+/// store while running), otherwise edit the token file directly.
+fn token_rotate(config: &AppConfig, name: &str) -> Result<()> {
+    let mut store = load_tokens(config)?;
+    let id = store
+        .list()
+        .iter()
+        .find(|t| t.name == name)
+        .map(|t| t.id.clone())
+        .ok_or_else(|| anyhow!("no token named '{}'", name))?;
+    let admin = find_token(&store, DEFAULT_TOKEN_NAME)?;
+
+    let path = format!("/api/tokens/{}/rotate", id);
+    let secret = match api_request(config, "POST", &path, &admin) {
+        Ok(body) => body["token"]
+            .as_str()
+            .ok_or_else(|| anyhow!("unexpected response: {}", body))?
+            .to_string(),
+        Err(e) if is_connection_refused(&e) => {
+            // Server not running
+            store.rotate(&id).context("token disappeared")?.token
+        }
+        Err(e) => return Err(e),
+    };
+    println!("{}", secret);
+    Ok(())
+}
+
+fn is_connection_refused(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == std::io::ErrorKind::ConnectionRefused)
+}
+
+fn clients_list(config: &AppConfig) -> Result<()> {
+    let store = load_tokens(config)?;
+    let admin = find_token(&store, DEFAULT_TOKEN_NAME)?;
+    let body = api_request(config, "GET", "/api/clients", &admin)
+        .context("is the server running?")?;
+    let clients = body.as_array().cloned().unwrap_or_default();
+    if clients.is_empty() {
+        println!("No connected clients");
+        return Ok(());
+    }
+    println!("{:<36} {:<24} {:<24} CONNECTED", "ID", "NAME", "FOLDER");
+    for c in clients {
+        println!(
+            "{:<36} {:<24} {:<24} {}",
+            c["id"].as_str().unwrap_or(""),
+            c["name"].as_str().unwrap_or(""),
+            c["folder_name"].as_str().unwrap_or(""),
+            c["connected_at"].as_str().unwrap_or(""),
+        );
+    }
+    Ok(())
+}
+
+/// Minimal HTTP/1.1 request to the local server's REST API. Connection
+/// failures are returned as `std::io::Error`, HTTP errors as plain errors.
+fn api_request(config: &AppConfig, method: &str, path: &str, token: &str) -> Result<Value> {
+    // Connect to the bind address, or loopback when bound to all interfaces
+    let ip = config
+        .bind_address
+        .parse::<IpAddr>()
+        .ok()
+        .filter(|ip| !ip.is_unspecified())
+        .unwrap_or(IpAddr::from([127, 0, 0, 1]));
+    let addr = SocketAddr::new(ip, config.port);
+
+    let mut stream = TcpStream::connect_timeout(&addr, Duration::from_secs(3))?;
+    stream.set_read_timeout(Some(config.relay_timeout()))?;
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nAuthorization: Bearer {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        method, path, addr, token
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let status: u16 = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0);
+    if !(200..300).contains(&status) {
+        bail!("{} {} failed: HTTP {} {}", method, path, status, body.trim());
+    }
+    serde_json::from_str(body).with_context(|| format!("invalid JSON from {}", path))
+}
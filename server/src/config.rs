@@ -5,15 +5,83 @@
 // 問い合わせ: info@snowcode.jp
 
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+use crate::mount_backend::BACKEND_NAMES;
+
+/// Server configuration.
+///
+/// Loaded from an optional TOML file (`--config <path>` or `LJC_CONFIG`),
+/// then overridden by `LJC_*` environment variables. Every field has a
+/// default, so an empty file (or no file) is a valid configuration.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct AppConfig {
     #[serde(default = "default_port")]
     pub port: u16,
+    /// HTTPS port for WebDAV. Defaults to `port + 1`.
+    #[serde(default)]
+    pub https_port: Option<u16>,
     #[serde(default = "default_bind")]
     pub bind_address: String,
     #[serde(default = "default_allowed_mount_base")]
     pub allowed_mount_base: String,
+    /// Mount backend: auto, macos, davfs or gvfs (see mount_backend.rs)
+    #[serde(default = "default_mount_backend")]
+    pub mount_backend: String,
+    /// Origins allowed to call the REST API from a browser (the dashboard)
+    #[serde(default = "default_cors_origins")]
+    pub cors_origins: Vec<String>,
+    /// Persistent data (devices, tokens, mounts). Defaults to ~/.ljc-data
+    #[serde(default)]
+    pub data_dir: Option<String>,
+    #[serde(default)]
+    pub timeouts: TimeoutConfig,
+    #[serde(default)]
+    pub tls: TlsConfig,
+    #[serde(default)]
+    pub tokens: TokenConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimeoutConfig {
+    /// Seconds to wait for a client's reply to a relayed command
+    #[serde(default = "default_relay_timeout")]
+    pub relay_secs: u64,
+    /// Seconds between WebSocket pings to each client
+    #[serde(default = "default_heartbeat")]
+    pub heartbeat_secs: u64,
+    /// Unanswered pings in a row before a client is disconnected
+    #[serde(default = "default_heartbeat_misses")]
+    pub heartbeat_misses: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// Directory for the generated self-signed certificate. Defaults to ~/.ljc-certs
+    #[serde(default)]
+    pub cert_dir: Option<String>,
+    /// PEM certificate chain to use instead of the self-signed certificate
+    #[serde(default)]
+    pub cert_path: Option<String>,
+    /// PEM private key for `cert_path`
+    #[serde(default)]
+    pub key_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TokenConfig {
+    /// Token store file. Defaults to <data_dir>/tokens.json
+    #[serde(default)]
+    pub file: Option<String>,
+    /// Print the default API token in the startup log
+    #[serde(default = "default_true")]
+    pub show_on_startup: bool,
 }
 
 fn default_port() -> u16 {
@@ -25,31 +93,412 @@ fn default_bind() -> String {
 fn default_allowed_mount_base() -> String {
     "~/Public/mount".to_string()
 }
+fn default_mount_backend() -> String {
+    "auto".to_string()
+}
+fn default_cors_origins() -> Vec<String> {
+    vec![
+        "http://localhost:17100".to_string(),
+        "http://127.0.0.1:17100".to_string(),
+    ]
+}
+fn default_relay_timeout() -> u64 {
+    30
+}
+fn default_heartbeat() -> u64 {
+    10
+}
+fn default_heartbeat_misses() -> u32 {
+    3
+}
+fn default_true() -> bool {
+    true
+}
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
             port: default_port(),
+            https_port: None,
             bind_address: default_bind(),
             allowed_mount_base: default_allowed_mount_base(),
+            mount_backend: default_mount_backend(),
+            cors_origins: default_cors_origins(),
+            data_dir: None,
+            timeouts: TimeoutConfig::default(),
+            tls: TlsConfig::default(),
+            tokens: TokenConfig::default(),
+        }
+    }
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            relay_secs: default_relay_timeout(),
+            heartbeat_secs: default_heartbeat(),
+            heartbeat_misses: default_heartbeat_misses(),
+        }
+    }
+}
+
+impl Default for TokenConfig {
+    fn default() -> Self {
+        Self {
+            file: None,
+            show_on_startup: true,
         }
     }
 }
 
 impl AppConfig {
-    /// Load config from environment variables, falling back to defaults.
+    /// Load the config file (if any), apply environment overrides and validate.
+    /// `path` comes from `--config`; without it, `LJC_CONFIG` is used.
+    /// Errors are human-readable and meant to be printed at startup.
+    pub fn load(path: Option<&Path>) -> Result<Self, Vec<String>> {
+        let path = path
+            .map(PathBuf::from)
+            .or_else(|| std::env::var("LJC_CONFIG").ok().map(PathBuf::from));
+        let mut config = match &path {
+            Some(path) => Self::from_file(path).map_err(|e| vec![e])?,
+            None => Self::default(),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Environment overrides (applied on top of the config file):
     /// - LJC_PORT: server port (default: 17200)
+    /// - LJC_HTTPS_PORT: HTTPS WebDAV port (default: LJC_PORT + 1)
     /// - LJC_BIND: bind address (default: 0.0.0.0)
     /// - LJC_ALLOWED_MOUNT: allowed mount base directory (default: ~/Public/mount)
-    pub fn from_env() -> Self {
-        let port = std::env::var("LJC_PORT")
-            .ok()
-            .and_then(|v| v.parse().ok())
-            .unwrap_or_else(default_port);
-        let bind_address = std::env::var("LJC_BIND")
-            .unwrap_or_else(|_| default_bind());
-        let allowed_mount_base = std::env::var("LJC_ALLOWED_MOUNT")
-            .unwrap_or_else(|_| default_allowed_mount_base());
-        Self { port, bind_address, allowed_mount_base }
+    /// - LJC_MOUNT_BACKEND: auto, macos, davfs or gvfs (default: auto)
+    /// - LJC_CORS_ORIGINS: comma-separated dashboard origins
+    /// - LJC_DATA_DIR: persistent data directory (default: ~/.ljc-data)
+    /// - LJC_RELAY_TIMEOUT: relay reply timeout in seconds (default: 30)
+    /// - LJC_HEARTBEAT: seconds between pings to clients (default: 10)
+    /// - LJC_HEARTBEAT_MISSES: missed pings before disconnecting a client (default: 3)
+    fn apply_env(&mut self) -> Result<(), Vec<String>> {
+        self.apply_vars(|name| std::env::var(name).ok())
+    }
+
+    /// `apply_env` with the variables looked up through `var`.
+    fn apply_vars(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        let mut parse = |name: &str| -> Option<u64> {
+            let value = var(name)?;
+            match value.trim().parse() {
+                Ok(v) => Some(v),
+                Err(_) => {
+                    errors.push(format!("{}: '{}' is not a valid number", name, value));
+                    None
+                }
+            }
+        };
+        let port = parse("LJC_PORT");
+        let https_port = parse("LJC_HTTPS_PORT");
+        let relay_timeout = parse("LJC_RELAY_TIMEOUT");
+        let heartbeat = parse("LJC_HEARTBEAT");
+        let heartbeat_misses = parse("LJC_HEARTBEAT_MISSES");
+
+        if let Some(v) = port {
+            match u16::try_from(v) {
+                Ok(p) => self.port = p,
+                Err(_) => errors.push(format!("LJC_PORT: {} is out of range", v)),
+            }
+        }
+        if let Some(v) = https_port {
+            match u16::try_from(v) {
+                Ok(p) => self.https_port = Some(p),
+                Err(_) => errors.push(format!("LJC_HTTPS_PORT: {} is out of range", v)),
+            }
+        }
+        if let Some(secs) = relay_timeout {
+            self.timeouts.relay_secs = secs;
+        }
+        if let Some(secs) = heartbeat {
+            self.timeouts.heartbeat_secs = secs;
+        }
+        if let Some(v) = heartbeat_misses {
+            match u32::try_from(v) {
+                Ok(n) => self.timeouts.heartbeat_misses = n,
+                Err(_) => errors.push(format!("LJC_HEARTBEAT_MISSES: {} is out of range", v)),
+            }
+        }
+        if let Some(bind) = var("LJC_BIND") {
+            self.bind_address = bind;
+        }
+        if let Some(base) = var("LJC_ALLOWED_MOUNT") {
+            self.allowed_mount_base = base;
+        }
+        if let Some(backend) = var("LJC_MOUNT_BACKEND") {
+            self.mount_backend = backend;
+        }
+        if let Some(origins) = var("LJC_CORS_ORIGINS") {
+            self.cors_origins = origins
+                .split(',')
+                .map(|o| o.trim().to_string())
+                .filter(|o| !o.is_empty())
+                .collect();
+        }
+        if let Some(dir) = var("LJC_DATA_DIR") {
+            self.data_dir = Some(dir);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Check the configuration, collecting every problem instead of stopping at the first.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        if self.port == 0 {
+            errors.push("port: must not be 0".to_string());
+        }
+        match self.https_port {
+            Some(0) => errors.push("https_port: must not be 0".to_string()),
+            Some(p) if p == self.port => {
+                errors.push(format!("https_port: must differ from port ({})", p))
+            }
+            None if self.port == u16::MAX => {
+                errors.push("https_port: port + 1 is out of range, set it explicitly".to_string())
+            }
+            _ => {}
+        }
+        if self.bind_address.parse::<IpAddr>().is_err() {
+            errors.push(format!("bind_address: '{}' is not an IP address", self.bind_address));
+        }
+        if self.allowed_mount_base.trim().is_empty() {
+            errors.push("allowed_mount_base: must not be empty".to_string());
+        }
+        if !BACKEND_NAMES.contains(&self.mount_backend.as_str()) {
+            errors.push(format!(
+                "mount_backend: '{}' is not one of {}",
+                self.mount_backend,
+                BACKEND_NAMES.join(", ")
+            ));
+        }
+        for origin in &self.cors_origins {
+            let valid = (origin.starts_with("http://") || origin.starts_with("https://"))
+                && origin.parse::<axum::http::HeaderValue>().is_ok()
+                && !origin.ends_with('/');
+            if !valid {
+                errors.push(format!(
+                    "cors_origins: '{}' must look like http(s)://host[:port]",
+                    origin
+                ));
+            }
+        }
+        if self.timeouts.relay_secs == 0 {
+            errors.push("timeouts.relay_secs: must be at least 1".to_string());
+        }
+        if self.timeouts.heartbeat_secs == 0 {
+            errors.push("timeouts.heartbeat_secs: must be at least 1".to_string());
+        }
+        if self.timeouts.heartbeat_misses == 0 {
+            errors.push("timeouts.heartbeat_misses: must be at least 1".to_string());
+        }
+        match (&self.tls.cert_path, &self.tls.key_path) {
+            (Some(cert), Some(key)) => {
+                for (field, path) in [("tls.cert_path", cert), ("tls.key_path", key)] {
+                    if !Path::new(&expand_tilde(path)).is_file() {
+                        errors.push(format!("{}: file not found: {}", field, path));
+                    }
+                }
+            }
+            (Some(_), None) => errors.push("tls.key_path: required when tls.cert_path is set".to_string()),
+            (None, Some(_)) => errors.push("tls.cert_path: required when tls.key_path is set".to_string()),
+            (None, None) => {}
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn https_port(&self) -> u16 {
+        self.https_port.unwrap_or(self.port.saturating_add(1))
+    }
+
+    pub fn relay_timeout(&self) -> Duration {
+        Duration::from_secs(self.timeouts.relay_secs)
+    }
+
+    pub fn heartbeat_interval(&self) -> Duration {
+        Duration::from_secs(self.timeouts.heartbeat_secs)
+    }
+
+    /// Directory where persistent server data (known devices, API tokens) is stored.
+    pub fn data_dir(&self) -> PathBuf {
+        match &self.data_dir {
+            Some(dir) => PathBuf::from(expand_tilde(dir)),
+            None => home_dir().join(".ljc-data"),
+        }
+    }
+
+    /// Create the data directory if it does not exist yet, owner only
+    /// (rwx------). An existing directory is left as it is.
+    pub fn create_data_dir(&self) -> std::io::Result<()> {
+        let dir = self.data_dir();
+        if dir.is_dir() {
+            return Ok(());
+        }
+        let mut builder = std::fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        builder.create(&dir)
+    }
+
+    pub fn tokens_file(&self) -> PathBuf {
+        match &self.tokens.file {
+            Some(file) => PathBuf::from(expand_tilde(file)),
+            None => self.data_dir().join("tokens.json"),
+        }
+    }
+}
+
+fn home_dir() -> PathBuf {
+    std::env::var("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("/tmp"))
+}
+
+/// Expand ~ to home directory
+pub fn expand_tilde(path: &str) -> String {
+    if path.starts_with("~/") || path == "~" {
+        if let Ok(home) = std::env::var("HOME") {
+            return path.replacen('~', &home, 1);
+        }
+    }
+    path.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn vars(list: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let map: HashMap<String, String> =
+            list.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        move |name| map.get(name).cloned()
+    }
+
+    #[test]
+    fn env_overrides_toml() {
+        let mut config: AppConfig = toml::from_str(
+            r#"
+            port = 18000
+            bind_address = "127.0.0.1"
+            [timeouts]
+            relay_secs = 60
+            heartbeat_secs = 5
+            "#,
+        )
+        .unwrap();
+        config
+            .apply_vars(vars(&[
+                ("LJC_PORT", "18100"),
+                ("LJC_RELAY_TIMEOUT", " 90 "),
+                ("LJC_CORS_ORIGINS", "http://a:1, ,http://b"),
+            ]))
+            .unwrap();
+        assert_eq!(config.port, 18100);
+        assert_eq!(config.https_port(), 18101);
+        assert_eq!(config.timeouts.relay_secs, 90);
+        // Not overridden: file values, then defaults
+        assert_eq!(config.bind_address, "127.0.0.1");
+        assert_eq!(config.timeouts.heartbeat_secs, 5);
+        assert_eq!(config.timeouts.heartbeat_misses, 3);
+        assert_eq!(config.cors_origins, vec!["http://a:1", "http://b"]);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn toml_rejects_unknown_fields() {
+        assert!(toml::from_str::<AppConfig>("prot = 1").is_err());
+        assert!(toml::from_str::<AppConfig>("[timeouts]\nrelay = 1").is_err());
+    }
+
+    #[test]
+    fn env_errors_are_collected() {
+        let mut config = AppConfig::default();
+        let errors = config
+            .apply_vars(vars(&[("LJC_PORT", "70000"), ("LJC_HEARTBEAT", "soon")]))
+            .unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().any(|e| e.starts_with("LJC_PORT:")));
+        assert!(errors.iter().any(|e| e.starts_with("LJC_HEARTBEAT:")));
+    }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let config: AppConfig = toml::from_str(
+            r#"
+            port = 18000
+            https_port = 18000
+            bind_address = "localhost"
+            mount_backend = "nfs"
+            cors_origins = ["http://localhost:17100/"]
+            [timeouts]
+            relay_secs = 0
+            [tls]
+            cert_path = "/nonexistent/cert.pem"
+            "#,
+        )
+        .unwrap();
+        let errors = config.validate().unwrap_err();
+        let fields = [
+            "https_port:",
+            "bind_address:",
+            "mount_backend:",
+            "cors_origins:",
+            "timeouts.relay_secs:",
+            "tls.key_path:",
+        ];
+        for field in fields {
+            assert!(errors.iter().any(|e| e.starts_with(field)), "no {} error in {:?}", field, errors);
+        }
+        assert_eq!(errors.len(), 6);
+    }
+
+    #[test]
+    fn data_dir_is_created_only_on_request() {
+        let dir = std::env::temp_dir().join(format!("ljc-config-{}", std::process::id()));
+        let config = AppConfig {
+            data_dir: Some(dir.join("data").to_string_lossy().into_owned()),
+            ..AppConfig::default()
+        };
+        assert_eq!(config.data_dir(), dir.join("data"));
+        assert!(!dir.exists());
+
+        config.create_data_dir().unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(&config.data_dir()), 0o700);
+            // An existing directory keeps the permissions its owner gave it
+            std::fs::set_permissions(config.data_dir(), std::fs::Permissions::from_mode(0o750)).unwrap();
+            config.create_data_dir().unwrap();
+            assert_eq!(mode(&config.data_dir()), 0o750);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -11,6 +11,7 @@ use axum::response::{Html, IntoResponse};
 use serde::Deserialize;
 use std::sync::Arc;
 
+use crate::protocol::PROTOCOL_VERSION;
 use crate::state::AppState;
 
 #[derive(Deserialize)]
@@ -20,7 +21,8 @@ pub struct ConnectParams {
 }
 
 /// GET /api/connect-html?ip=...&port=...
-/// Downloads the standalone connect HTML file with IP pre-filled and auth token embedded.
+/// Downloads the standalone connect HTML file with IP pre-filled and the
+/// connect token (register scope only) embedded, as the route is public.
 /// If no ip param, uses the Host header (= the IP the client used to reach this server).
 fn extract_host_ip(headers: &HeaderMap) -> String {
     headers
@@ -55,8 +57,15 @@ pub async fn connect_html(
 ) -> impl IntoResponse {
     let ip = html_escape(&params.ip.unwrap_or_else(|| extract_host_ip(&headers)));
     let port = params.port.unwrap_or_else(|| extract_host_port(&headers));
-    let token = &state.api_token;
-    let html = generate_connect_html(&ip, port, token);
+    let token = state
+        .tokens
+        .read()
+        .await
+        .connect_token()
+        .filter(|t| t.is_register_only())
+        .map(|t| t.token.clone())
+        .unwrap_or_default();
+    let html = generate_connect_html(&ip, port, &token);
 
     (
         [
@@ -347,6 +356,11 @@ fn generate_connect_html(server_ip: &str, port: u16, api_token: &str) -> String
   .btn-primary:disabled {{
     opacity: 0.5; cursor: not-allowed; transform: none;
   }}
+  .btn-secondary {{
+    background: rgba(126,184,216,0.12); color: #4a7c9b;
+    border: 1px solid rgba(126,184,216,0.4); margin-left: 8px;
+  }}
+  .btn-secondary:hover {{ background: rgba(126,184,216,0.22); }}
   .btn-danger {{
     background: linear-gradient(135deg, #ff8a9b 0%, #ff6b7a 100%);
     color: #fff; box-shadow: 0 4px 15px rgba(255,107,122,0.3);
@@ -474,6 +488,7 @@ fn generate_connect_html(server_ip: &str, port: u16, api_token: &str) -> String
       <button id="btn-connect" class="btn btn-primary" onclick="handleConnect()">
         &#10052; フォルダを選択して接続
       </button>
+      <button id="btn-resume" class="btn btn-secondary hidden" onclick="handleResume()"></button>
     </div>
 
     <div id="connected-section" class="hidden">
@@ -523,6 +538,75 @@ let ws = null;
 let dirHandle = null;
 let clientId = '';
 let folderName = '';
+let serverIp = '';
+// 再接続: サーバー再起動やネットワーク断の後、同じ端末ID・フォルダで自動的に再登録する
+let resumeToken = '';
+let reconnectAttempts = 0;
+let reconnectTimer = null;
+let userClosed = false;
+const RECONNECT_MAX_DELAY = 30000;
+// 進行中のアップロード: uploadId -> {{ writable, nextSeq, written }}
+const uploads = new Map();
+// 処理中のコマンド: id -> {{ cancelled }} (サーバーからの cancel で中断)
+const inflight = new Map();
+// 中断されたフォルダ移動: "元のパス\n移動先" (同じ移動が再度来たら続きから)
+const moveJobs = new Set();
+const PROGRESS_INTERVAL = 1000;
+let serverCapabilities = [];
+
+// 端末ID: 再接続しても同じクライアントID (WebDAV URL) を維持するため localStorage に保存
+function getDeviceIdentity() {{
+  const gen = () => (crypto.randomUUID ? crypto.randomUUID()
+    : Array.from(crypto.getRandomValues(new Uint8Array(16)), b => b.toString(16).padStart(2, '0')).join(''));
+  try {{
+    let deviceId = localStorage.getItem('snowsync.deviceId');
+    let deviceKey = localStorage.getItem('snowsync.deviceKey');
+    if (!deviceId || !deviceKey) {{
+      deviceId = gen();
+      deviceKey = gen();
+      localStorage.setItem('snowsync.deviceId', deviceId);
+      localStorage.setItem('snowsync.deviceKey', deviceKey);
+    }}
+    return {{ deviceId, deviceKey }};
+  }} catch {{
+    return {{ deviceId: '', deviceKey: '' }};
+  }}
+}}
+
+// フォルダのハンドルを IndexedDB に保存し、ページを開き直してもフォルダを選び直さずに済むようにする
+function idbRequest(mode, fn) {{
+  return new Promise((resolve, reject) => {{
+    const open = indexedDB.open('snowsync', 1);
+    open.onupgradeneeded = () => open.result.createObjectStore('handles');
+    open.onerror = () => reject(open.error);
+    open.onsuccess = () => {{
+      const db = open.result;
+      const req = fn(db.transaction('handles', mode).objectStore('handles'));
+      req.onsuccess = () => {{ db.close(); resolve(req.result); }};
+      req.onerror = () => {{ db.close(); reject(req.error); }};
+    }};
+  }});
+}}
+
+function saveDirHandle(handle) {{
+  return idbRequest('readwrite', store => store.put(handle, 'dir')).catch(() => {{}});
+}}
+
+function loadDirHandle() {{
+  return idbRequest('readonly', store => store.get('dir')).catch(() => null);
+}}
+
+// 前回のフォルダがあれば「前回のフォルダで接続」ボタンを表示
+async function showResumeButton() {{
+  const btn = document.getElementById('btn-resume');
+  const handle = await loadDirHandle();
+  if (!handle) {{
+    btn.classList.add('hidden');
+    return;
+  }}
+  btn.textContent = '前回のフォルダ「' + handle.name + '」で接続';
+  btn.classList.remove('hidden');
+}}
 
 function log(msg) {{
   const box = document.getElementById('log-box');
@@ -539,9 +623,9 @@ function setStatus(s) {{
   const row = document.getElementById('status-row');
   const connectSec = document.getElementById('connect-section');
   const connectedSec = document.getElementById('connected-section');
-  const cls = {{ disconnected:'dot-off', connecting:'dot-connecting', connected:'dot-on' }};
-  const rowCls = {{ disconnected:'status-disconnected', connecting:'status-connecting', connected:'status-connected' }};
-  const labels = {{ disconnected:'未接続', connecting:'接続処理中...', connected:'❄ 接続中' }};
+  const cls = {{ disconnected:'dot-off', connecting:'dot-connecting', reconnecting:'dot-connecting', connected:'dot-on' }};
+  const rowCls = {{ disconnected:'status-disconnected', connecting:'status-connecting', reconnecting:'status-connecting', connected:'status-connected' }};
+  const labels = {{ disconnected:'未接続', connecting:'接続処理中...', reconnecting:'再接続待機中...', connected:'❄ 接続中' }};
   dot.className = 'dot ' + (cls[s] || 'dot-off');
   row.className = 'status-row ' + (rowCls[s] || 'status-disconnected');
   text.textContent = labels[s] || '';
@@ -549,6 +633,10 @@ function setStatus(s) {{
     connectSec.classList.add('hidden');
     connectedSec.classList.remove('hidden');
     showToast('success', 'サーバーに接続しました');
+  }} else if (s === 'reconnecting') {{
+    // 切断ボタンで再接続を止められるよう接続中の表示のままにする
+    connectSec.classList.add('hidden');
+    connectedSec.classList.remove('hidden');
   }} else if (s === 'disconnected' && clientId) {{
     connectSec.classList.remove('hidden');
     connectedSec.classList.add('hidden');
@@ -577,6 +665,95 @@ function showError(msg) {{
   else {{ box.classList.add('hidden'); }}
 }}
 
+function bytesToBase64(bytes) {{
+  let bin = '';
+  for (let i = 0; i < bytes.length; i += 0x8000) {{
+    bin += String.fromCharCode.apply(null, bytes.subarray(i, i + 0x8000));
+  }}
+  return btoa(bin);
+}}
+
+// readChunk 1回あたりの最大サイズ (サーバー側の要求もこれ以下)
+const MAX_CHUNK_SIZE = 4 * 1024 * 1024;
+
+// バイナリフレーム: [version u8][op u8][id 16byte UUID][seq u32 BE][payload]
+const FRAME_VERSION = 1;
+const FRAME_HEADER_LEN = 22;
+const OP_CHUNK_DATA = 1;
+const OP_UPLOAD_CHUNK = 2;
+
+function uuidToBytes(id) {{
+  const hex = id.replace(/-/g, '');
+  const out = new Uint8Array(16);
+  for (let i = 0; i < 16; i++) out[i] = parseInt(hex.substr(i * 2, 2), 16);
+  return out;
+}}
+
+function bytesToUuid(b) {{
+  const hex = Array.from(b, x => x.toString(16).padStart(2, '0')).join('');
+  return hex.slice(0, 8) + '-' + hex.slice(8, 12) + '-' + hex.slice(12, 16) + '-' + hex.slice(16, 20) + '-' + hex.slice(20);
+}}
+
+function encodeFrame(op, id, seq, payload) {{
+  const buf = new Uint8Array(FRAME_HEADER_LEN + payload.length);
+  const view = new DataView(buf.buffer);
+  buf[0] = FRAME_VERSION;
+  buf[1] = op;
+  buf.set(uuidToBytes(id), 2);
+  view.setUint32(18, seq);
+  buf.set(payload, FRAME_HEADER_LEN);
+  return buf;
+}}
+
+function decodeFrame(arrayBuffer) {{
+  const buf = new Uint8Array(arrayBuffer);
+  if (buf.length < FRAME_HEADER_LEN || buf[0] !== FRAME_VERSION) return null;
+  const view = new DataView(arrayBuffer);
+  return {{
+    op: buf[1],
+    id: bytesToUuid(buf.subarray(2, 18)),
+    seq: view.getUint32(18),
+    payload: buf.subarray(FRAME_HEADER_LEN),
+  }};
+}}
+
+// バイナリフレームで返信するコマンド結果 (readChunk + binary)
+class BinaryResult {{
+  constructor(bytes) {{ this.bytes = bytes; }}
+}}
+
+// コマンドの失敗。code はサーバーが WebDAV / HTTP のステータスに変換する (protocol.rs の ErrorCode)
+class CommandError extends Error {{
+  constructor(code, message) {{ super(message); this.code = code; }}
+}}
+
+// 失敗の理由を ErrorCode で返す (File System Access API の例外は名前で判定)
+function errorCode(err) {{
+  if (err instanceof CommandError) return err.code;
+  switch (err.name) {{
+    case 'NotFoundError': return 'notFound';
+    case 'NotAllowedError': case 'SecurityError': return 'permissionDenied';
+    case 'TypeMismatchError': return 'typeMismatch';
+    case 'QuotaExceededError': return 'noSpace';
+    case 'NoModificationAllowedError': return 'busy';
+    case 'InvalidModificationError': return 'notEmpty';
+    case 'TypeError': return 'invalidPath';
+    default: return 'other';
+  }}
+}}
+
+// 応答のエラー文。ブラウザの例外には対象のパスを付ける
+function errorText(err, cmd) {{
+  const target = cmd.path || cmd.oldPath || '';
+  return err instanceof DOMException && target ? target + ': ' + err.message : err.message;
+}}
+
+// 呼び出し元 (Finder / ブラウザ) が待つのをやめたコマンド。応答は送らない
+class CancelledError extends Error {{}}
+function checkCancelled(op) {{
+  if (op && op.cancelled) throw new CancelledError('キャンセルされました');
+}}
+
 async function resolvePath(path) {{
   const segments = path.split('/').filter(Boolean);
   if (segments.length === 0) return {{ parent: dirHandle, name: '', segments: [] }};
@@ -587,7 +764,199 @@ async function resolvePath(path) {{
   return {{ parent: current, name: segments[segments.length - 1], segments }};
 }}
 
-async function handleCommand(cmd) {{
+async function writeUploadChunk(uploadId, seq, bytes) {{
+  const up = uploads.get(uploadId);
+  if (!up) throw new CommandError('invalidRequest', 'アップロードが見つかりません: ' + uploadId);
+  if (seq !== up.nextSeq) throw new CommandError('invalidRequest', 'チャンク順序エラー: ' + seq + ' (期待値 ' + up.nextSeq + ')');
+  await up.writable.write(bytes);
+  up.nextSeq++;
+  up.written += bytes.length;
+  return {{ written: up.written }};
+}}
+
+// サーバーからのバイナリフレーム (アップロードデータ)。uploadId 宛てにJSONで応答する
+async function handleBinaryFrame(data) {{
+  const frame = decodeFrame(data);
+  if (!frame || frame.op !== OP_UPLOAD_CHUNK) {{ log('不正なバイナリフレーム'); return; }}
+  try {{
+    const result = await writeUploadChunk(frame.id, frame.seq, frame.payload);
+    ws.send(JSON.stringify({{ id: frame.id, ok: true, data: result }}));
+  }} catch (err) {{
+    ws.send(JSON.stringify({{ id: frame.id, ok: false, code: errorCode(err), error: err.message }}));
+    log('ERR: uploadChunk - ' + err.message);
+  }}
+}}
+
+// サーバーに進捗を報告する (報告ごとにサーバー側のタイムアウトが延長される)
+function reportProgress(op, done, total, current) {{
+  if (!ws || !serverCapabilities.includes('progress')) return;
+  const now = Date.now();
+  if (done < total && now - op.reportedAt < PROGRESS_INTERVAL) return;
+  op.reportedAt = now;
+  ws.send(JSON.stringify({{ type: 'progress', id: op.id, done, total, current }}));
+}}
+
+async function getEntryHandle(parent, name) {{
+  try {{
+    return await parent.getFileHandle(name);
+  }} catch (err) {{
+    if (err.name !== 'TypeMismatchError') throw err;
+    return await parent.getDirectoryHandle(name);
+  }}
+}}
+
+async function entryExists(parent, name) {{
+  try {{
+    await getEntryHandle(parent, name);
+    return true;
+  }} catch {{
+    return false;
+  }}
+}}
+
+// コピー対象のファイルを列挙 (空のディレクトリも作成できるようディレクトリも含める)
+async function collectEntries(dir, prefix, out) {{
+  for await (const entry of dir.values()) {{
+    const rel = prefix ? prefix + '/' + entry.name : entry.name;
+    out.push({{ rel, handle: entry }});
+    if (entry.kind === 'directory') await collectEntries(entry, rel, out);
+  }}
+  return out;
+}}
+
+async function copyFile(fileHandle, dir, name, resume) {{
+  const file = await fileHandle.getFile();
+  const target = await dir.getFileHandle(name, {{ create: true }});
+  // 中断後の再開: 前回コピー済み (同じサイズ) のファイルはそのまま使う
+  if (resume && (await target.getFile()).size === file.size && file.size > 0) return;
+  const writable = await target.createWritable();
+  await file.stream().pipeTo(writable);
+}}
+
+// ファイル、またはフォルダを再帰的にコピーし、コピーしたファイル数を返す (copy と rename で共用)
+async function copyEntry(source, dstParent, dstName, op, resume) {{
+  if (source.kind === 'file') {{
+    reportProgress(op, 0, 1, source.name);
+    await copyFile(source, dstParent, dstName, resume);
+    checkCancelled(op);
+    reportProgress(op, 1, 1, source.name);
+    return 1;
+  }}
+  const entries = await collectEntries(source, '', []);
+  const files = entries.filter(e => e.handle.kind === 'file').length;
+  const root = await dstParent.getDirectoryHandle(dstName, {{ create: true }});
+  let done = 0;
+  for (const {{ rel, handle }} of entries) {{
+    checkCancelled(op);
+    const segs = rel.split('/');
+    let dir = root;
+    for (const s of segs.slice(0, -1)) dir = await dir.getDirectoryHandle(s, {{ create: true }});
+    const name = segs[segs.length - 1];
+    if (handle.kind === 'directory') {{
+      await dir.getDirectoryHandle(name, {{ create: true }});
+      continue;
+    }}
+    await copyFile(handle, dir, name, resume);
+    done++;
+    reportProgress(op, done, files, rel);
+  }}
+  checkCancelled(op);
+  return files;
+}}
+
+// rename / copy の元と先を解決する
+async function resolveTransfer(cmd, verb) {{
+  const oldSegs = cmd.oldPath.split('/').filter(Boolean);
+  const newSegs = cmd.newPath.split('/').filter(Boolean);
+  if (oldSegs.length === 0 || newSegs.length === 0) throw new CommandError('permissionDenied', 'ルートフォルダは' + verb + 'できません');
+  if (newSegs.length > oldSegs.length && oldSegs.every((s, i) => s === newSegs[i])) {{
+    throw new CommandError('permissionDenied', 'フォルダをそれ自身の中には' + verb + 'できません');
+  }}
+  const {{ parent: srcParent, name: srcName }} = await resolvePath(cmd.oldPath);
+  const {{ parent: dstParent, name: dstName }} = await resolvePath(cmd.newPath);
+  const source = await getEntryHandle(srcParent, srcName);
+  return {{ key: oldSegs.join('/') + '\n' + newSegs.join('/'), source, srcParent, srcName, dstParent, dstName }};
+}}
+
+// copy: クライアント内でコピーする (データはサーバーを経由しない)。既存のファイルは上書きし、
+// フォルダはコピー先が存在しないこと。失敗・キャンセル時は作りかけのフォルダを削除する
+async function copyCommand(cmd, op) {{
+  const {{ source, dstParent, dstName }} = await resolveTransfer(cmd, 'コピー');
+  if (source.kind === 'directory' && await entryExists(dstParent, dstName)) {{
+    throw new CommandError('alreadyExists', 'コピー先が既に存在します: ' + cmd.newPath);
+  }}
+  try {{
+    const copied = await copyEntry(source, dstParent, dstName, op, false);
+    if (source.kind === 'directory') log('コピー: ' + cmd.oldPath + ' → ' + cmd.newPath + ' (' + copied + 'ファイル)');
+    return {{ copied }};
+  }} catch (err) {{
+    if (source.kind === 'directory') await dstParent.removeEntry(dstName, {{ recursive: true }}).catch(() => {{}});
+    throw err;
+  }}
+}}
+
+// rename: ネイティブの move() が使えればそれを使い、使えなければコピーして元を削除する。
+// コピー中に失敗したらコピー先を削除する (元はまだ変更していない)。元の削除が途中で失敗した
+// 場合は、削除済みのファイルがコピー先にしか残っていないためコピー先は残し、元に残っている
+// ものをエラーで返す。キャンセル (切断・タイムアウト) や元の削除の失敗で中断した場合は、
+// 同じ rename がもう一度来たら続きから再開する
+async function moveEntry(cmd, op) {{
+  const {{ key, source, srcParent, srcName, dstParent, dstName }} = await resolveTransfer(cmd, '移動');
+  const resuming = moveJobs.has(key);
+
+  if (!resuming && await entryExists(dstParent, dstName)) throw new CommandError('alreadyExists', '移動先が既に存在します: ' + cmd.newPath);
+  if (!resuming && typeof source.move === 'function') {{
+    try {{
+      await source.move(dstParent, dstName);
+      return {{ renamed: true }};
+    }} catch (err) {{
+      // 未対応 (ディレクトリやフォルダをまたぐ移動など) ならコピーで移動する
+      if (!(await entryExists(srcParent, srcName))) throw err;
+    }}
+  }}
+
+  moveJobs.add(key);
+  try {{
+    const files = await copyEntry(source, dstParent, dstName, op, true);
+    if (source.kind === 'directory') log('移動: ' + cmd.oldPath + ' → ' + cmd.newPath + ' (' + files + 'ファイル)');
+  }} catch (err) {{
+    if (err instanceof CancelledError) {{
+      log('移動を中断しました (同じ移動をもう一度行うと再開します): ' + cmd.oldPath);
+      throw err;
+    }}
+    moveJobs.delete(key);
+    await dstParent.removeEntry(dstName, {{ recursive: true }}).catch(() => {{}});
+    throw new CommandError(errorCode(err), '移動に失敗しました (移動先を削除し、元は変更していません): ' + err.message);
+  }}
+
+  try {{
+    await srcParent.removeEntry(srcName, {{ recursive: true }});
+  }} catch (err) {{
+    const left = await remainingEntries(srcParent, srcName, cmd.oldPath);
+    if (left.length > 0) {{
+      const shown = left.slice(0, 10).join(', ') + (left.length > 10 ? ' 他' + (left.length - 10) + '件' : '');
+      log('移動の途中で元の削除に失敗しました (移動先は残しています): ' + cmd.oldPath);
+      throw new CommandError(errorCode(err), '移動先へのコピーは完了しましたが、元の削除に失敗しました。元に残っているもの: ' + shown + ' (' + err.message + ')');
+    }}
+  }}
+  moveJobs.delete(key);
+  return {{ renamed: true }};
+}}
+
+// 元に残っているファイル・フォルダのパス (削除済みなら空)
+async function remainingEntries(parent, name, path) {{
+  let handle;
+  try {{
+    handle = await getEntryHandle(parent, name);
+  }} catch {{
+    return [];
+  }}
+  if (handle.kind === 'file') return [path];
+  const entries = await collectEntries(handle, '', []).catch(() => []);
+  return [path, ...entries.map(e => path + '/' + e.rel)];
+}}
+
+async function handleCommand(cmd, op) {{
   const type = cmd.type;
   const path = cmd.path || '/';
   switch (type) {{
@@ -597,6 +966,7 @@ async function handleCommand(cmd) {{
       for (const s of segs) dir = await dir.getDirectoryHandle(s);
       const entries = [];
       for await (const entry of dir.values()) {{
+        checkCancelled(op);
         let size = 0, modified = new Date().toISOString();
         if (entry.kind === 'file') {{
           const f = await entry.getFile(); size = f.size;
@@ -612,10 +982,20 @@ async function handleCommand(cmd) {{
       const fh = await parent.getFileHandle(name);
       const file = await fh.getFile();
       const buf = await file.arrayBuffer();
-      const bytes = new Uint8Array(buf);
-      let bin = '';
-      for (let i = 0; i < bytes.length; i++) bin += String.fromCharCode(bytes[i]);
-      return {{ data: btoa(bin), size: file.size, name: file.name, type: file.type, modified: new Date(file.lastModified).toISOString() }};
+      checkCancelled(op);
+      return {{ data: bytesToBase64(new Uint8Array(buf)), size: file.size, name: file.name, type: file.type, modified: new Date(file.lastModified).toISOString() }};
+    }}
+    case 'readChunk': {{
+      const {{ parent, name }} = await resolvePath(path);
+      const fh = await parent.getFileHandle(name);
+      const file = await fh.getFile();
+      const offset = Math.max(0, Number(cmd.offset) || 0);
+      const length = Math.min(Math.max(0, Number(cmd.length) || 0), MAX_CHUNK_SIZE);
+      const end = Math.min(file.size, offset + length);
+      const bytes = new Uint8Array(await file.slice(offset, end).arrayBuffer());
+      checkCancelled(op);
+      if (cmd.binary) return new BinaryResult(bytes);
+      return {{ data: bytesToBase64(bytes), offset, size: file.size, modified: new Date(file.lastModified).toISOString(), eof: end >= file.size }};
     }}
     case 'writeFile': {{
       const {{ parent, name }} = await resolvePath(path);
@@ -627,6 +1007,35 @@ async function handleCommand(cmd) {{
       await w.write(bytes); await w.close();
       return {{ written: bytes.length }};
     }}
+    case 'uploadOpen': {{
+      if (!cmd.uploadId || uploads.has(cmd.uploadId)) throw new CommandError('invalidRequest', '不正なアップロードID');
+      const {{ parent, name }} = await resolvePath(path);
+      const fh = await parent.getFileHandle(name, {{ create: true }});
+      const writable = await fh.createWritable();
+      uploads.set(cmd.uploadId, {{ writable, nextSeq: 0, written: 0 }});
+      return {{ uploadId: cmd.uploadId }};
+    }}
+    case 'uploadChunk': {{
+      const bin = atob(cmd.data);
+      const bytes = new Uint8Array(bin.length);
+      for (let i = 0; i < bin.length; i++) bytes[i] = bin.charCodeAt(i);
+      return await writeUploadChunk(cmd.uploadId, cmd.seq, bytes);
+    }}
+    case 'uploadCommit': {{
+      const up = uploads.get(cmd.uploadId);
+      if (!up) throw new CommandError('invalidRequest', 'アップロードが見つかりません: ' + cmd.uploadId);
+      uploads.delete(cmd.uploadId);
+      await up.writable.close();
+      return {{ written: up.written }};
+    }}
+    case 'uploadAbort': {{
+      const up = uploads.get(cmd.uploadId);
+      if (up) {{
+        uploads.delete(cmd.uploadId);
+        await up.writable.abort();
+      }}
+      return {{ aborted: true }};
+    }}
     case 'mkdir': {{
       const {{ parent, name }} = await resolvePath(path);
       await parent.getDirectoryHandle(name, {{ create: true }});
@@ -637,33 +1046,18 @@ async function handleCommand(cmd) {{
       await parent.removeEntry(name, {{ recursive: true }});
       return {{ deleted: true }};
     }}
-    case 'rename': {{
-      const {{ parent: op, name: on }} = await resolvePath(cmd.oldPath);
-      const {{ parent: np, name: nn }} = await resolvePath(cmd.newPath);
-      try {{
-        const fh = await op.getFileHandle(on);
-        const f = await fh.getFile();
-        const data = await f.arrayBuffer();
-        const nfh = await np.getFileHandle(nn, {{ create: true }});
-        const w = await nfh.createWritable();
-        await w.write(data); await w.close();
-        await op.removeEntry(on);
-        return {{ renamed: true }};
-      }} catch {{ throw new Error('ディレクトリのリネームはFile System Access APIでは非対応です'); }}
-    }}
+    case 'rename': return await moveEntry(cmd, op);
+    case 'copy': return await copyCommand(cmd, op);
     case 'stat': {{
       const segs = path.split('/').filter(Boolean);
       if (segs.length === 0) return {{ name: folderName, is_dir: true, size: 0, modified: new Date().toISOString() }};
       const {{ parent, name }} = await resolvePath(path);
-      try {{
-        const fh = await parent.getFileHandle(name);
-        const f = await fh.getFile();
-        return {{ name: f.name, is_dir: false, size: f.size, modified: new Date(f.lastModified).toISOString() }};
-      }} catch {{
-        return {{ name, is_dir: true, size: 0, modified: new Date().toISOString() }};
-      }}
+      const handle = await getEntryHandle(parent, name);
+      if (handle.kind === 'directory') return {{ name, is_dir: true, size: 0, modified: new Date().toISOString() }};
+      const f = await handle.getFile();
+      return {{ name: f.name, is_dir: false, size: f.size, modified: new Date(f.lastModified).toISOString() }};
     }}
-    default: throw new Error('不明なコマンド: ' + type);
+    default: throw new CommandError('unsupported', '不明なコマンド: ' + type);
   }}
 }}
 
@@ -673,87 +1067,180 @@ async function handleConnect() {{
     showError('File System Access APIに対応していません。このHTMLファイルをChromeまたはEdgeで直接開いてください（file://プロトコル）。');
     return;
   }}
-  const serverIp = document.getElementById('server-ip').value.trim();
-  if (!serverIp) {{
-    showError('MacサーバーのIPアドレスを入力してください。');
-    return;
-  }}
+  if (!readServerIp()) return;
   try {{
     dirHandle = await window.showDirectoryPicker({{ mode: 'readwrite' }});
-    folderName = dirHandle.name;
-    document.getElementById('folder-name').textContent = folderName;
-    setStatus('connecting');
-    log('フォルダ選択: ' + folderName);
-    connectWS(serverIp);
+    await saveDirHandle(dirHandle);
+    startSession('フォルダ選択: ');
   }} catch (err) {{
     if (err.name === 'AbortError') return;
     showError('フォルダの選択に失敗しました: ' + err.message);
   }}
 }}
 
-function connectWS(serverIp) {{
+// 保存済みのフォルダで接続 (ページを開き直すと再度アクセス許可が必要)
+async function handleResume() {{
+  showError(null);
+  if (!readServerIp()) return;
+  const handle = await loadDirHandle();
+  if (!handle) {{
+    showResumeButton();
+    return;
+  }}
+  try {{
+    if (await handle.requestPermission({{ mode: 'readwrite' }}) !== 'granted') {{
+      showError('フォルダへのアクセスが許可されませんでした。');
+      return;
+    }}
+    dirHandle = handle;
+    startSession('前回のフォルダ: ');
+  }} catch (err) {{
+    showError('前回のフォルダを開けませんでした: ' + err.message);
+  }}
+}}
+
+function readServerIp() {{
+  serverIp = document.getElementById('server-ip').value.trim();
+  if (!serverIp) showError('MacサーバーのIPアドレスを入力してください。');
+  return !!serverIp;
+}}
+
+function startSession(label) {{
+  folderName = dirHandle.name;
+  document.getElementById('folder-name').textContent = folderName;
+  userClosed = false;
+  reconnectAttempts = 0;
+  setStatus('connecting');
+  log(label + folderName);
+  connectWS();
+}}
+
+// 指数バックオフ (1, 2, 4 ... 30秒) で再接続。複数のPCが一斉に再接続しないよう揺らぎを加える
+function scheduleReconnect() {{
+  const delay = Math.min(1000 * 2 ** reconnectAttempts, RECONNECT_MAX_DELAY) * (0.75 + Math.random() / 2);
+  reconnectAttempts++;
+  if (reconnectAttempts === 1) showToast('info', '接続が切れました。再接続します...');
+  setStatus('reconnecting');
+  log(Math.round(delay / 1000) + '秒後に再接続します (' + reconnectAttempts + '回目)');
+  reconnectTimer = setTimeout(() => {{
+    reconnectTimer = null;
+    connectWS();
+  }}, delay);
+}}
+
+function connectWS() {{
   const wsUrl = 'ws://' + serverIp + ':{port}/ws';
   log(wsUrl + ' に接続中...');
   ws = new WebSocket(wsUrl);
+  ws.binaryType = 'arraybuffer';
   ws.onopen = () => {{
     log('WebSocket接続完了、登録中...');
     const pcName = document.getElementById('pc-name').value.trim()
       || (navigator.userAgent.includes('Windows') ? 'Windows PC' : 'Client PC');
+    const {{ deviceId, deviceKey }} = getDeviceIdentity();
     ws.send(JSON.stringify({{
       type: 'register',
       name: pcName,
       folderName: folderName,
       token: '{api_token}',
+      deviceId,
+      deviceKey,
+      resumeToken,
+      binaryFrames: true,
+      protocolVersion: {protocol_version},
+      capabilities: ['binaryFrames', 'cancel', 'dirRename', 'progress', 'copy'],
     }}));
   }};
   ws.onmessage = async (event) => {{
+    if (event.data instanceof ArrayBuffer) {{
+      await handleBinaryFrame(event.data);
+      return;
+    }}
     try {{
       const msg = JSON.parse(event.data);
       if (msg.type === 'registered') {{
         clientId = msg.clientId;
+        resumeToken = msg.resumeToken || '';
+        serverCapabilities = msg.capabilities || [];
+        reconnectAttempts = 0;
         document.getElementById('client-id').textContent = clientId;
         const pcNameVal = document.getElementById('pc-name').value.trim()
           || (navigator.userAgent.includes('Windows') ? 'Windows PC' : 'Client PC');
         document.getElementById('connected-name').textContent = pcNameVal;
-        const serverIpVal = document.getElementById('server-ip').value.trim();
         document.getElementById('webdav-url').textContent =
-          'http://' + serverIpVal + ':{port}/webdav/' + clientId + '/';
+          'http://' + serverIp + ':{port}/webdav/' + clientId + '/';
         setStatus('connected');
-        log('クライアント登録完了: ' + clientId);
-        log('WebDAV URL: http://' + serverIpVal + ':{port}/webdav/' + clientId + '/');
+        log((msg.resumed ? 'クライアント再登録完了 (前回のIDを継続): ' : 'クライアント登録完了: ') + clientId
+          + ' (プロトコル v' + (msg.protocolVersion || 1) + ')');
+        log('WebDAV URL: http://' + serverIp + ':{port}/webdav/' + clientId + '/');
+        return;
+      }}
+      if (msg.type === 'cancel') {{
+        const op = inflight.get(msg.id);
+        if (op) op.cancelled = true;
         return;
       }}
       const id = msg.id;
+      const op = {{ id, cancelled: false, reportedAt: 0 }};
+      inflight.set(id, op);
       try {{
-        const result = await handleCommand(msg);
-        ws.send(JSON.stringify({{ id, ok: true, data: result }}));
+        const result = await handleCommand(msg, op);
+        checkCancelled(op);
+        if (result instanceof BinaryResult) ws.send(encodeFrame(OP_CHUNK_DATA, id, 0, result.bytes));
+        else ws.send(JSON.stringify({{ id, ok: true, data: result }}));
         log('OK: ' + msg.type + ' ' + (msg.path || ''));
       }} catch (err) {{
-        ws.send(JSON.stringify({{ id, ok: false, error: err.message }}));
+        if (err instanceof CancelledError) {{
+          log('キャンセル: ' + msg.type + ' ' + (msg.path || ''));
+          return;
+        }}
+        ws.send(JSON.stringify({{ id, ok: false, code: errorCode(err), error: errorText(err, msg) }}));
         log('ERR: ' + msg.type + ' - ' + err.message);
+      }} finally {{
+        inflight.delete(id);
       }}
     }} catch {{ log('メッセージの解析に失敗'); }}
   }};
   ws.onclose = () => {{
     log('切断されました');
+    abortAllUploads();
+    for (const op of inflight.values()) op.cancelled = true;
+    inflight.clear();
+    ws = null;
+    // 切断ボタン以外で切れた場合はフォルダを保持したまま再接続する
+    if (!userClosed && dirHandle) {{
+      scheduleReconnect();
+      return;
+    }}
     setStatus('disconnected');
     clientId = '';
-    ws = null;
   }};
   ws.onerror = () => {{
     log('WebSocketエラー');
+    if (reconnectAttempts > 0) return;
     showError('接続に失敗しました。Rustサーバー(ポート{port})が起動しているか確認してください。IP: ' + serverIp);
   }};
 }}
 
+function abortAllUploads() {{
+  for (const up of uploads.values()) up.writable.abort().catch(() => {{}});
+  uploads.clear();
+}}
+
 function handleDisconnect() {{
+  userClosed = true;
+  if (reconnectTimer) clearTimeout(reconnectTimer);
+  reconnectTimer = null;
   if (ws) ws.close();
   dirHandle = null;
   setStatus('disconnected');
   folderName = '';
   clientId = '';
+  showResumeButton();
 }}
+
+if ('indexedDB' in window) showResumeButton();
 </script>
 </body>
-</html>"##, server_ip = server_ip, port = port, api_token = api_token)
+</html>"##, server_ip = server_ip, port = port, api_token = api_token, protocol_version = PROTOCOL_VERSION)
 }
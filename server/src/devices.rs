@@ -0,0 +1,201 @@
+// SNOWCODE - ソフトウェア製品
+// (C) SNOWCODE
+// 開発者: 雪符しき
+// https://snowcode.jp
+// 問い合わせ: info@snowcode.jp
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::mount_registry::MountPolicy;
+use crate::secret_file;
+
+/// A device that has registered before. The device ID and key are generated
+/// by the client (connect page) and presented on every `register`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnownDevice {
+    pub device_id: String,
+    pub device_key: String,
+    /// client_id assigned on first registration, reused on every reconnect
+    pub client_id: String,
+    pub name: String,
+    pub folder_name: String,
+    pub first_seen: String,
+    pub last_seen: String,
+    /// Auto-mount policy (see auto_mount.rs)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mount_policy: Option<MountPolicy>,
+}
+
+/// Registry of known devices, persisted as JSON in the data directory.
+pub struct DeviceRegistry {
+    path: PathBuf,
+    devices: HashMap<String, KnownDevice>,
+}
+
+/// Result of resolving a device at registration time.
+pub enum DeviceLookup {
+    /// Known device with matching key: reuse its client_id
+    Resumed(String),
+    /// First registration of this device: a new client_id was assigned
+    New(String),
+    /// Device ID is known but the key does not match
+    KeyMismatch,
+}
+
+impl DeviceRegistry {
+    /// Load the registry from `path`. A missing file yields an empty registry,
+    /// an invalid one is kept aside (see `secret_file::read_json`) and an
+    /// unreadable one is an error.
+    pub fn load(path: PathBuf) -> std::io::Result<Self> {
+        let devices = secret_file::read_json::<Vec<KnownDevice>>(&path)?
+            .map(|list| {
+                list.into_iter()
+                    .map(|d| (d.device_id.clone(), d))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(Self { path, devices })
+    }
+
+    fn save(&self) {
+        let mut list: Vec<&KnownDevice> = self.devices.values().collect();
+        list.sort_by(|a, b| a.first_seen.cmp(&b.first_seen));
+        let json = match serde_json::to_string_pretty(&list) {
+            Ok(json) => json,
+            Err(e) => {
+                tracing::warn!("Failed to serialize device registry: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = secret_file::write(&self.path, json) {
+            tracing::warn!("Failed to save device registry {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Resolve a registering device to its client_id: the stored one for a
+    /// known device, or a fresh one for an unknown device. Nothing is saved
+    /// until `record`, so a registration rejected after this leaves no trace.
+    pub fn resolve(&self, device_id: &str, device_key: &str) -> DeviceLookup {
+        match self.devices.get(device_id) {
+            Some(device) if device.device_key == device_key => DeviceLookup::Resumed(device.client_id.clone()),
+            Some(_) => DeviceLookup::KeyMismatch,
+            None => DeviceLookup::New(uuid::Uuid::new_v4().to_string()),
+        }
+    }
+
+    /// Record an accepted registration: create the entry for a new device,
+    /// or refresh name/folder/last_seen of a known one.
+    pub fn record(
+        &mut self,
+        device_id: &str,
+        device_key: &str,
+        client_id: &str,
+        name: &str,
+        folder_name: &str,
+        now: &str,
+    ) {
+        let device = self
+            .devices
+            .entry(device_id.to_string())
+            .or_insert_with(|| KnownDevice {
+                device_id: device_id.to_string(),
+                device_key: device_key.to_string(),
+                client_id: client_id.to_string(),
+                name: String::new(),
+                folder_name: String::new(),
+                first_seen: now.to_string(),
+                last_seen: String::new(),
+                mount_policy: None,
+            });
+        device.name = name.to_string();
+        device.folder_name = folder_name.to_string();
+        device.last_seen = now.to_string();
+        self.save();
+    }
+
+    fn by_client_id(&mut self, client_id: &str) -> Option<&mut KnownDevice> {
+        self.devices.values_mut().find(|d| d.client_id == client_id)
+    }
+
+    /// Auto-mount policy of the device with this client_id.
+    pub fn mount_policy(&self, client_id: &str) -> Option<MountPolicy> {
+        self.devices
+            .values()
+            .find(|d| d.client_id == client_id)
+            .and_then(|d| d.mount_policy.clone())
+    }
+
+    /// Set or clear the auto-mount policy of the device with this
+    /// client_id. False when no known device has it.
+    pub fn set_mount_policy(&mut self, client_id: &str, policy: Option<MountPolicy>) -> bool {
+        let Some(device) = self.by_client_id(client_id) else {
+            return false;
+        };
+        device.mount_policy = policy;
+        self.save();
+        true
+    }
+
+    /// Known devices that have an auto-mount policy.
+    pub fn with_mount_policy(&self) -> Vec<&KnownDevice> {
+        let mut list: Vec<&KnownDevice> = self.devices.values().filter(|d| d.mount_policy.is_some()).collect();
+        list.sort_by(|a, b| a.first_seen.cmp(&b.first_seen));
+        list
+    }
+}
+
+/// Device IDs and keys are opaque client-generated strings (UUIDs from the
+/// connect page). Bound their length and charset before using them as keys.
+pub fn is_valid_device_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.len() <= 128
+        && s.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// How long a disconnected client's resume token stays valid.
+pub const RESUME_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// One-time tokens handed out in `registered`. A client that reconnects
+/// with one gets its previous client_id back, even without a device
+/// identity (e.g. a connect page where localStorage is unavailable).
+/// Kept in memory only: after a server restart, the device identity is
+/// what brings a client back to its ID.
+#[derive(Default)]
+pub struct ResumeTokens {
+    /// token -> (client_id, expiry; `None` while the client is connected)
+    tokens: HashMap<String, (String, Option<Instant>)>,
+}
+
+impl ResumeTokens {
+    /// Issue a token for a freshly registered client, replacing its previous one.
+    pub fn issue(&mut self, client_id: &str) -> String {
+        let now = Instant::now();
+        self.tokens.retain(|_, (id, expires)| {
+            id != client_id && expires.is_none_or(|t| t > now)
+        });
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        self.tokens.insert(token.clone(), (client_id.to_string(), None));
+        token
+    }
+
+    /// Start the expiry clock of a client's token once it disconnects.
+    pub fn release(&mut self, client_id: &str) {
+        let expires = Instant::now() + RESUME_WINDOW;
+        for (id, expiry) in self.tokens.values_mut() {
+            if id == client_id {
+                *expiry = Some(expires);
+            }
+        }
+    }
+
+    /// Use up a token, returning the client_id it was issued for.
+    pub fn redeem(&mut self, token: &str) -> Option<String> {
+        let (client_id, expires) = self.tokens.remove(token)?;
+        expires
+            .is_none_or(|t| t > Instant::now())
+            .then_some(client_id)
+    }
+}
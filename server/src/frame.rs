@@ -0,0 +1,132 @@
+// SNOWCODE - ソフトウェア製品
+// (C) SNOWCODE
+// 開発者: 雪符しき
+// https://snowcode.jp
+// 問い合わせ: info@snowcode.jp
+
+// Binary WebSocket frames for file payloads (`Message::Binary`).
+//
+// Layout (all integers big-endian):
+//
+// | offset | size | field                                   |
+// |--------|------|-----------------------------------------|
+// | 0      | 1    | version (`FRAME_VERSION`)               |
+// | 1      | 1    | op (`FrameOp`)                          |
+// | 2      | 16   | routing id (UUID bytes)                 |
+// | 18     | 4    | sequence number                         |
+// | 22     | ..   | payload (raw file bytes)                |
+//
+// The routing id is the id the receiver replies to: the request id for
+// `ChunkData` (client -> server), and the upload id for `UploadChunk`
+// (server -> client), which the client acknowledges with a JSON response.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+pub const FRAME_VERSION: u8 = 1;
+pub const HEADER_LEN: usize = 22;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameOp {
+    /// Client -> server: file bytes answering a `readChunk` with `"binary": true`
+    ChunkData = 1,
+    /// Server -> client: file bytes for an open upload session
+    UploadChunk = 2,
+}
+
+impl FrameOp {
+    fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            1 => Some(Self::ChunkData),
+            2 => Some(Self::UploadChunk),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Frame {
+    pub op: FrameOp,
+    /// Routing id in the same hyphenated form used for JSON ids
+    pub id: String,
+    pub seq: u32,
+    pub payload: Bytes,
+}
+
+/// Encode a frame. `id` must be a UUID string.
+pub fn encode(op: FrameOp, id: &str, seq: u32, payload: &[u8]) -> Option<Bytes> {
+    let id = uuid::Uuid::parse_str(id).ok()?;
+    let mut buf = BytesMut::with_capacity(HEADER_LEN + payload.len());
+    buf.put_u8(FRAME_VERSION);
+    buf.put_u8(op as u8);
+    buf.put_slice(id.as_bytes());
+    buf.put_u32(seq);
+    buf.put_slice(payload);
+    Some(buf.freeze())
+}
+
+/// Decode a frame. Returns None for truncated frames, unknown versions or ops.
+pub fn decode(data: Bytes) -> Option<Frame> {
+    if data.len() < HEADER_LEN || data[0] != FRAME_VERSION {
+        return None;
+    }
+    let op = FrameOp::from_u8(data[1])?;
+    let id = uuid::Uuid::from_slice(&data[2..18]).ok()?.to_string();
+    let seq = u32::from_be_bytes([data[18], data[19], data[20], data[21]]);
+    Some(Frame {
+        op,
+        id,
+        seq,
+        payload: data.slice(HEADER_LEN..),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "3eda982f-9953-4e51-a409-a92704640b61";
+
+    #[test]
+    fn round_trip() {
+        let data = encode(FrameOp::UploadChunk, ID, 7, b"hello").unwrap();
+        assert_eq!(data.len(), HEADER_LEN + 5);
+        let frame = decode(data).unwrap();
+        assert_eq!(frame.op, FrameOp::UploadChunk);
+        assert_eq!(frame.id, ID);
+        assert_eq!(frame.seq, 7);
+        assert_eq!(&frame.payload[..], b"hello");
+    }
+
+    #[test]
+    fn header_layout() {
+        let data = encode(FrameOp::ChunkData, ID, 0x0102_0304, b"").unwrap();
+        assert_eq!(data[0], FRAME_VERSION);
+        assert_eq!(data[1], FrameOp::ChunkData as u8);
+        assert_eq!(&data[2..18], uuid::Uuid::parse_str(ID).unwrap().as_bytes());
+        assert_eq!(&data[18..22], &[1, 2, 3, 4]);
+        assert!(decode(data).unwrap().payload.is_empty());
+    }
+
+    #[test]
+    fn encode_requires_uuid() {
+        assert!(encode(FrameOp::ChunkData, "not-a-uuid", 0, b"x").is_none());
+    }
+
+    #[test]
+    fn decode_rejects_short_header() {
+        let data = encode(FrameOp::ChunkData, ID, 1, b"").unwrap();
+        assert!(decode(data.slice(..HEADER_LEN - 1)).is_none());
+        assert!(decode(Bytes::new()).is_none());
+    }
+
+    #[test]
+    fn decode_rejects_unknown_version_and_op() {
+        let data = encode(FrameOp::ChunkData, ID, 1, b"x").unwrap();
+        let mut version = data.to_vec();
+        version[0] = FRAME_VERSION + 1;
+        assert!(decode(Bytes::from(version)).is_none());
+        let mut op = data.to_vec();
+        op[1] = 0;
+        assert!(decode(Bytes::from(op)).is_none());
+    }
+}
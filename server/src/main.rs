@@ -4,36 +4,94 @@
 // https://snowcode.jp
 // 問い合わせ: info@snowcode.jp
 
+mod auto_mount;
+mod cli;
 mod config;
 mod connect_html;
+mod devices;
+mod frame;
 mod mount;
+mod mount_backend;
+mod mount_linux;
+mod mount_macos;
+mod mount_registry;
+mod paths;
+#[allow(dead_code)] // Also compiled into ljc-agent, which uses the client-side helpers
+mod protocol;
 mod relay;
+mod secret_file;
 mod server;
 mod state;
 mod tls;
+mod tokens;
+mod transfers;
+mod webdav_auth;
 mod webdav_bridge;
 mod ws;
 
 use anyhow::Result;
 use config::AppConfig;
 use state::AppState;
+use cli::Command;
 use tokio::net::TcpListener;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let cli = match cli::parse(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    let serving = matches!(cli.command, Command::Serve);
+
+    // Management commands only log warnings (to stderr), so their output stays readable
+    let (default_filter, writer) = if serving {
+        ("ljc_server=info,tower_http=info", BoxMakeWriter::new(std::io::stdout))
+    } else {
+        ("ljc_server=warn", BoxMakeWriter::new(std::io::stderr))
+    };
     tracing_subscriber::fmt()
+        .with_writer(writer)
         .with_env_filter(
             tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "ljc_server=info,tower_http=info".into()),
+                .unwrap_or_else(|_| default_filter.into()),
         )
         .init();
 
-    let config = AppConfig::from_env();
+    // --config <path> (otherwise LJC_CONFIG, otherwise defaults)
+    let config = match AppConfig::load(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(errors) => {
+            eprintln!("Invalid configuration:");
+            for e in &errors {
+                eprintln!("  - {}", e);
+            }
+            std::process::exit(2);
+        }
+    };
+
+    if !serving {
+        if let Err(e) = cli::run(cli.command, &config) {
+            eprintln!("error: {:#}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    serve(config).await
+}
+
+/// `ljc-server serve`: run the HTTP and HTTPS listeners.
+async fn serve(config: AppConfig) -> Result<()> {
     let port = config.port;
-    let https_port = port + 1; // 17201
+    let https_port = config.https_port();
     let bind = config.bind_address.clone();
 
-    let state = AppState::new(port, config.allowed_mount_base.clone());
+    config.create_data_dir()?;
+    let state = AppState::new(&config)?;
     let app = server::build_router(state.clone());
 
     let local_ip = local_ip_address::local_ip()
@@ -52,14 +110,23 @@ async fn main() -> Result<()> {
     tracing::info!("  WebDAV:    https://{}:{}/webdav/<client_id>/", local_ip, https_port);
     tracing::info!("===========================================");
     tracing::info!("");
-    tracing::info!("  API Token: {}", state.api_token);
+    if config.tokens.show_on_startup {
+        if let Some(token) = state.tokens.read().await.default_token() {
+            tracing::info!("  API Token: {}", token.token);
+        }
+    }
     tracing::info!("  Mount base: {}", config.allowed_mount_base);
+    match &state.mount_backend {
+        Some(backend) => tracing::info!("  Mount backend: {}", backend.name()),
+        None => tracing::warn!("  Mount backend: none (no mount tools found for '{}')", config.mount_backend),
+    }
+    mount::reconcile_on_startup(&state).await;
     tracing::info!("");
     tracing::info!("  Finderマウント: Cmd+K -> https://{}:{}/webdav/<client_id>/", local_ip, https_port);
     tracing::info!("  接続HTML: http://{}:{}/api/connect-html", local_ip, port);
 
     // --- HTTPS listener (WebDAV for Finder on macOS Tahoe) ---
-    let tls_config = tls::make_tls_config(&local_ip)?;
+    let tls_config = tls::make_tls_config(&local_ip, &config.tls)?;
 
     let https_addr = format!("{}:{}", bind, https_port);
     let https_app = server::build_router(state);
@@ -79,7 +146,12 @@ async fn main() -> Result<()> {
                 }
             };
             let acceptor = tls_acceptor.clone();
-            let app = https_app.clone();
+            // Same extensions the HTTP listener provides, plus the TLS marker
+            // (WebDAV only accepts Basic auth over TLS or loopback)
+            let app = https_app
+                .clone()
+                .layer(axum::Extension(axum::extract::ConnectInfo(addr)))
+                .layer(axum::Extension(server::TlsConnection));
             tokio::spawn(async move {
                 match acceptor.accept(stream).await {
                     Ok(tls_stream) => {
@@ -104,7 +176,10 @@ async fn main() -> Result<()> {
 
     // HTTP server (main thread)
     tokio::select! {
-        r = axum::serve(http_listener, app) => {
+        r = axum::serve(
+            http_listener,
+            app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+        ) => {
             if let Err(e) = r {
                 tracing::error!("HTTP server error: {}", e);
             }
@@ -7,11 +7,19 @@
 use axum::extract::State;
 use axum::http::StatusCode;
 use axum::response::Json;
+use axum::Extension;
 use serde::Deserialize;
 use serde_json::{json, Value};
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
 use std::sync::Arc;
 
-use crate::state::AppState;
+use crate::config::expand_tilde;
+use crate::mount_backend::{MountBackend, MountEntry, MountTarget};
+use crate::mount_registry::MountRecord;
+use crate::state::{unix_now, AppState};
+use crate::tokens::{Scope, TokenGrant};
+use crate::webdav_auth::MountCredential;
 
 #[derive(Deserialize)]
 pub struct MountRequest {
@@ -24,39 +32,101 @@ pub struct UnmountRequest {
     pub mount_path: String,
 }
 
-/// Expand ~ to home directory
-fn expand_tilde(path: &str) -> String {
-    if path.starts_with("~/") || path == "~" {
-        if let Ok(home) = std::env::var("HOME") {
-            return path.replacen('~', &home, 1);
-        }
-    }
-    path.to_string()
+/// Prefix of the directories clients are mounted on.
+const MOUNT_DIR_PREFIX: &str = "ljc-";
+
+/// Directory a client is mounted on: `ljc-` and the first 8 characters of its id.
+fn mount_dir_name(client_id: &str) -> String {
+    format!("{}{}", MOUNT_DIR_PREFIX, &client_id[..8.min(client_id.len())])
+}
+
+fn is_mount_dir_name(name: &str) -> bool {
+    name.strip_prefix(MOUNT_DIR_PREFIX)
+        .is_some_and(|id| id.len() == 8 && id.bytes().all(|b| b.is_ascii_hexdigit()))
 }
 
-/// Check if path is safe (no traversal, within allowed base)
-fn validate_mount_path(path: &str, allowed_base: &str) -> Result<String, String> {
-    // Reject paths containing ".."
-    if path.contains("..") {
+/// Resolve `path` (`~` expanded) to a canonical path inside `allowed_base`.
+/// Both are compared component by component after resolving symlinks, so
+/// neither `~/Public/mountEVIL` nor a symlink inside the base that points
+/// elsewhere passes.
+async fn resolve_under_base(path: &str, allowed_base: &str) -> Result<PathBuf, String> {
+    let expanded = PathBuf::from(expand_tilde(path));
+    if !expanded.is_absolute() {
+        return Err(format!("Mount path must be absolute (got '{}')", path));
+    }
+    if expanded.components().any(|c| c == Component::ParentDir) {
         return Err("Path must not contain '..'".to_string());
     }
-
-    let expanded = expand_tilde(path);
-    let allowed_expanded = expand_tilde(allowed_base);
-
-    // Normalize: ensure both end consistently
-    let expanded_clean = expanded.trim_end_matches('/');
-    let allowed_clean = allowed_expanded.trim_end_matches('/');
-
-    // Must be under allowed base
-    if !expanded_clean.starts_with(allowed_clean) {
+    let base = canonicalize_existing(Path::new(&expand_tilde(allowed_base))).await?;
+    let resolved = canonicalize_existing(&expanded).await?;
+    if !resolved.starts_with(&base) {
         return Err(format!(
             "Mount path must be under '{}' (got '{}')",
             allowed_base, path
         ));
     }
+    Ok(resolved)
+}
 
-    Ok(expanded)
+/// Canonicalize the deepest existing ancestor of `path` and append the
+/// components that do not exist yet (which cannot be symlinks). A dangling
+/// symlink is refused: `mkdir -p` would create its target.
+async fn canonicalize_existing(path: &Path) -> Result<PathBuf, String> {
+    let mut existing = path;
+    let mut missing = Vec::new();
+    loop {
+        match tokio::fs::canonicalize(existing).await {
+            Ok(canonical) => return Ok(missing.iter().rev().fold(canonical, |p, name| p.join(name))),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                if tokio::fs::symlink_metadata(existing).await.is_ok() {
+                    return Err(format!("Mount path contains a dangling symlink: {}", existing.display()));
+                }
+                let (Some(parent), Some(name)) = (existing.parent(), existing.file_name()) else {
+                    return Err(format!("Invalid mount path: {}", path.display()));
+                };
+                missing.push(name);
+                existing = parent;
+            }
+            Err(e) => return Err(format!("{}: {}", existing.display(), e)),
+        }
+    }
+}
+
+/// Refuse a mount point that is a symlink (mounting on it would cover
+/// whatever it points to) or not a directory. A missing one is fine.
+async fn check_mount_point(mount_point: &Path) -> Result<(), String> {
+    match tokio::fs::symlink_metadata(mount_point).await {
+        Ok(meta) if meta.file_type().is_symlink() => {
+            Err(format!("Mount point must not be a symlink: {}", mount_point.display()))
+        }
+        Ok(meta) if !meta.is_dir() => Err(format!("Mount point is not a directory: {}", mount_point.display())),
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(format!("{}: {}", mount_point.display(), e)),
+    }
+}
+
+/// Resolve the mount point of a client for mounting: `ljc-xxxxxxxx` inside
+/// `base`, which must be under the allowed base.
+pub async fn resolve_new_mount_point(base: &str, client_id: &str, allowed_base: &str) -> Result<PathBuf, String> {
+    let mount_point = resolve_under_base(base, allowed_base).await?.join(mount_dir_name(client_id));
+    check_mount_point(&mount_point).await?;
+    Ok(mount_point)
+}
+
+/// Resolve an existing mount point for unmounting: a `ljc-xxxxxxxx`
+/// directory (not a symlink) in a directory under the allowed base.
+async fn resolve_mount_point(path: &str, allowed_base: &str) -> Result<PathBuf, String> {
+    let path = Path::new(path.trim_end_matches('/'));
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .filter(|n| is_mount_dir_name(n))
+        .ok_or("Can only unmount SnowSync mount points (ljc-*)")?;
+    let parent = path.parent().and_then(|p| p.to_str()).unwrap_or_default();
+    let mount_point = resolve_under_base(parent, allowed_base).await?.join(name);
+    check_mount_point(&mount_point).await?;
+    Ok(mount_point)
 }
 
 /// Validate that a string looks like a UUID (8-4-4-4-12 hex)
@@ -66,10 +136,12 @@ fn is_valid_uuid(s: &str) -> bool {
 
 /// POST /api/mount
 /// Body: {"client_id": "xxx", "mount_path": "~/Public/mount"}
-/// Mounts the WebDAV share. Uses 127.0.0.1 HTTP (port 17200) which bypasses
-/// macOS Tahoe's HTTP WebDAV block (only blocks non-loopback HTTP).
+/// Mounts the WebDAV share with the configured backend (see mount_backend.rs).
+/// Uses 127.0.0.1 (default port 17200), which bypasses macOS Tahoe's HTTP
+/// WebDAV block (only blocks non-loopback HTTP).
 pub async fn mount_webdav(
     State(state): State<Arc<AppState>>,
+    Extension(grant): Extension<TokenGrant>,
     Json(body): Json<MountRequest>,
 ) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
     let client_id = &body.client_id;
@@ -81,18 +153,37 @@ pub async fn mount_webdav(
             Json(json!({"error": "Invalid client_id format"})),
         ));
     }
+    grant.check(Scope::Mount, client_id)?;
+
+    let record = mount_client(&state, client_id, &body.mount_path).await?;
+    Ok(Json(json!({
+        "ok": true,
+        "mount_point": record.mount_point,
+        "webdav_url": record.url,
+        "strategy": record.strategy,
+    })))
+}
 
-    // Validate mount path is under allowed base directory
-    let base_mount_path = match validate_mount_path(&body.mount_path, &state.allowed_mount_base) {
-        Ok(path) => path,
-        Err(e) => {
-            return Err((
-                StatusCode::FORBIDDEN,
-                Json(json!({"error": e})),
-            ));
-        }
-    };
-    let base_mount_path = &base_mount_path;
+/// Mount a connected client's share on `{mount_path}/ljc-xxxxxxxx` and
+/// record it in the mount registry. Used by /api/mount and auto-mount.
+pub async fn mount_client(
+    state: &AppState,
+    client_id: &str,
+    mount_path: &str,
+) -> Result<MountRecord, (StatusCode, Json<Value>)> {
+    let backend = backend(state)?;
+
+    // Mount point: {mount_path}/ljc-{client_id_short}, under the allowed base
+    let mount_point =
+        match resolve_new_mount_point(mount_path, client_id, &state.allowed_mount_base).await {
+            Ok(path) => path,
+            Err(e) => {
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    Json(json!({"error": e})),
+                ));
+            }
+        };
 
     // Check client exists
     {
@@ -105,13 +196,24 @@ pub async fn mount_webdav(
         }
     }
 
-    // Create mount point: base_mount_path/ljc-{client_id_short}
-    let short_id = &client_id[..8.min(client_id.len())];
-    let mount_point = format!("{}/ljc-{}", base_mount_path.trim_end_matches('/'), short_id);
+    // One mount per client: a second one would only duplicate the share
+    if let Err(e) = reconcile(state, backend).await {
+        tracing::warn!("Could not read the mount table: {}", e);
+    }
+    if let Some(existing) = state.mounts.lock().await.for_client(client_id).first() {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(json!({
+                "error": format!("既にマウントされています: {}", existing.mount_point),
+                "mount_point": existing.mount_point,
+            })),
+        ));
+    }
 
     // Create directory
     let mkdir_result = tokio::process::Command::new("mkdir")
-        .args(["-p", &mount_point])
+        .arg("-p")
+        .arg(&mount_point)
         .output()
         .await;
 
@@ -133,243 +235,223 @@ pub async fn mount_webdav(
         _ => {}
     }
 
-    // Use 127.0.0.1 HTTP — macOS Tahoe blocks HTTP WebDAV for non-loopback
-    // addresses, but allows loopback (127.0.0.1). Since the server runs on
-    // the same Mac, we can always connect via loopback.
-    let port = state.port;
-    let https_port = port + 1;
-    let webdav_url = format!("http://127.0.0.1:{}/webdav/{}/", port, client_id);
-
-    tracing::info!("Mounting WebDAV: {} -> {}", webdav_url, mount_point);
-
-    // Strategy 1: mount_webdav -S via 127.0.0.1 HTTP
-    tracing::info!("Trying mount_webdav -S (127.0.0.1 HTTP) ...");
-    let mount_result = tokio::process::Command::new("mount_webdav")
-        .args(["-S", &webdav_url, &mount_point])
-        .output()
-        .await;
-
-    match &mount_result {
-        Ok(output) if output.status.success() => {
-            tracing::info!("mount_webdav success: {}", mount_point);
-            return Ok(Json(json!({
-                "ok": true,
-                "mount_point": mount_point,
-                "webdav_url": webdav_url,
-            })));
-        }
-        Ok(output) => {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            let code = output.status.code().unwrap_or(-1);
-            tracing::warn!("mount_webdav 127.0.0.1 failed (exit {}): {}", code, stderr);
-        }
-        Err(e) => {
-            tracing::warn!("mount_webdav exec error: {}", e);
-        }
-    }
-
-    // Strategy 2: mount_webdav via HTTPS (port + 1)
-    let https_url = format!("https://127.0.0.1:{}/webdav/{}/", https_port, client_id);
-    tracing::info!("Trying mount_webdav -S (HTTPS {}) ...", https_port);
-    let mount_result2 = tokio::process::Command::new("mount_webdav")
-        .args(["-S", &https_url, &mount_point])
-        .output()
-        .await;
-
-    match &mount_result2 {
-        Ok(output) if output.status.success() => {
-            tracing::info!("mount_webdav HTTPS success: {}", mount_point);
-            return Ok(Json(json!({
-                "ok": true,
-                "mount_point": mount_point,
-                "webdav_url": https_url,
-            })));
-        }
-        Ok(output) => {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            let code = output.status.code().unwrap_or(-1);
-            tracing::warn!("mount_webdav HTTPS failed (exit {}): {}", code, stderr);
-        }
-        Err(e) => {
-            tracing::warn!("mount_webdav HTTPS exec error: {}", e);
-        }
+    // A symlink swapped in since validation would make the mount land
+    // elsewhere
+    if tokio::fs::canonicalize(&mount_point).await.ok().as_ref() != Some(&mount_point) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(json!({"error": "Mount point changed during mount"})),
+        ));
     }
+    let mount_point = mount_point.to_string_lossy().into_owned();
+
+    // Loopback URLs: macOS Tahoe blocks HTTP WebDAV for non-loopback
+    // addresses, and the server always runs on the machine doing the mount
+    let webdav_url = format!("http://127.0.0.1:{}/webdav/{}/", state.port, client_id);
+    let https_url = format!("https://127.0.0.1:{}/webdav/{}/", state.https_port, client_id);
+
+    // Per-mount credentials: only valid for this client's WebDAV share.
+    // Reused across remounts so an existing mount keeps working.
+    let cred = state
+        .mount_credentials
+        .write()
+        .await
+        .entry(client_id.to_string())
+        .or_insert_with(|| MountCredential::generate(client_id))
+        .clone();
 
-    // Strategy 3: osascript (Finder "mount volume") via 127.0.0.1
-    tracing::info!("Fallback: trying Finder mount volume ...");
-    let applescript = format!(
-        r#"tell application "Finder" to mount volume "{}""#,
-        webdav_url
-    );
-
-    let finder_result = tokio::process::Command::new("osascript")
-        .args(["-e", &applescript])
-        .output()
-        .await;
-
-    match finder_result {
-        Ok(output) if output.status.success() => {
-            let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
-            tracing::info!("Finder mount success: stdout={}", stdout);
-
-            tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-
-            let actual_mount = find_webdav_mount(client_id)
-                .await
-                .unwrap_or_else(|| mount_point.clone());
+    tracing::info!("Mounting WebDAV: {} -> {}", webdav_url, mount_point);
 
-            Ok(Json(json!({
-                "ok": true,
-                "mount_point": actual_mount,
-                "webdav_url": webdav_url,
-            })))
-        }
-        Ok(output) => {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            let exit_code = mount_result
-                .as_ref()
-                .ok()
-                .and_then(|o| o.status.code())
-                .unwrap_or(-1);
-            tracing::error!("All mount strategies failed");
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({
-                    "error": format!(
-                        "マウント失敗 (mount_webdav exit {}, Finder: {})",
-                        exit_code,
-                        stderr.trim()
-                    ),
-                    "mount_point": mount_point,
-                })),
-            ))
+    let target = MountTarget {
+        client_id,
+        mount_point: &mount_point,
+        http_url: webdav_url,
+        https_url,
+        credential: &cred,
+    };
+    match backend.mount(&target).await {
+        Ok(mounted) => {
+            tracing::info!("Mounted ({}): {}", mounted.strategy, mounted.mount_point);
+            let record = MountRecord {
+                client_id: client_id.to_string(),
+                mount_point: mounted.mount_point,
+                url: mounted.webdav_url,
+                fs_type: mounted.fs_type.to_string(),
+                strategy: mounted.strategy.to_string(),
+                mounted_at: unix_now(),
+                credential: Some(cred.clone()),
+            };
+            state.mounts.lock().await.insert(record.clone());
+            Ok(record)
         }
         Err(e) => Err((
             StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({"error": format!("osascript実行エラー: {}", e)})),
+            Json(json!({"error": e, "mount_point": mount_point})),
         )),
     }
 }
 
-/// Find the actual mount point for a WebDAV client from system mount table.
-async fn find_webdav_mount(client_id: &str) -> Option<String> {
-    let output = tokio::process::Command::new("mount")
-        .output()
-        .await
-        .ok()?;
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    for line in stdout.lines() {
-        if line.contains(client_id) || line.contains("/webdav/") {
-            let parts: Vec<&str> = line.splitn(3, " on ").collect();
-            if let Some(rest) = parts.get(1) {
-                let mount_point = rest.split(" (").next().unwrap_or("").trim().to_string();
-                if !mount_point.is_empty() {
-                    return Some(mount_point);
-                }
+/// The configured mount backend, or 501 when this host has none.
+fn backend(state: &AppState) -> Result<&dyn MountBackend, (StatusCode, Json<Value>)> {
+    state.mount_backend.as_deref().ok_or_else(|| {
+        (
+            StatusCode::NOT_IMPLEMENTED,
+            Json(json!({"error": "No mount backend is available on this host (mount_backend)"})),
+        )
+    })
+}
+
+/// Mounts of this server's client shares (by URL port) in the OS mount table.
+async fn own_mounts(state: &AppState, backend: &dyn MountBackend) -> Result<Vec<MountEntry>, String> {
+    let ports = [Some(state.port), Some(state.https_port)];
+    let mounts = backend.mounts().await?;
+    Ok(mounts
+        .into_iter()
+        .filter(|m| m.client_id.is_some() && ports.contains(&m.port()))
+        .collect())
+}
+
+/// Reconcile the mount registry with the OS mount table.
+async fn reconcile(state: &AppState, backend: &dyn MountBackend) -> Result<(), String> {
+    let table = own_mounts(state, backend).await?;
+    state.mounts.lock().await.reconcile(&table, unix_now());
+    Ok(())
+}
+
+/// Reconcile the mount registry once at startup: mounts that disappeared
+/// while the server was down are dropped.
+pub async fn reconcile_on_startup(state: &AppState) {
+    let Some(backend) = state.mount_backend.as_deref() else {
+        return;
+    };
+    match reconcile(state, backend).await {
+        Ok(()) => {
+            let count = state.mounts.lock().await.list().len();
+            if count > 0 {
+                tracing::info!("  Mounted shares: {}", count);
             }
         }
+        Err(e) => tracing::warn!("Could not read the mount table: {}", e),
     }
-    None
 }
 
 /// POST /api/unmount
 /// Body: {"mount_path": "/Users/xxx/Public/mount/ljc-xxx"}
 pub async fn unmount_webdav(
     State(state): State<Arc<AppState>>,
+    Extension(grant): Extension<TokenGrant>,
     Json(body): Json<UnmountRequest>,
 ) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
-    let mount_path = &body.mount_path;
-
-    // Validate: path must contain "ljc-" prefix (our mount points)
-    if !mount_path.contains("/ljc-") {
-        return Err((
-            StatusCode::FORBIDDEN,
-            Json(json!({"error": "Can only unmount SnowSync mount points (ljc-*)"})),
-        ));
-    }
-
-    // Validate: path must be under allowed mount base
-    if let Err(e) = validate_mount_path(mount_path, &state.allowed_mount_base) {
-        return Err((
-            StatusCode::FORBIDDEN,
+    let backend = backend(&state)?;
+    reconcile(&state, backend).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
             Json(json!({"error": e})),
-        ));
-    }
-
-    // Reject path traversal
-    if mount_path.contains("..") {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(json!({"error": "Invalid path"})),
-        ));
-    }
+        )
+    })?;
+
+    // A recorded mount at exactly this path (Finder and GVFS pick their
+    // own mount points); otherwise one of our ljc-* mount points under the
+    // allowed mount base
+    let requested = expand_tilde(body.mount_path.trim_end_matches('/'));
+    let existing = state.mounts.lock().await.get(&requested).cloned();
+    let record = match existing {
+        Some(record) => record,
+        None => {
+            let mount_point = match resolve_mount_point(&body.mount_path, &state.allowed_mount_base).await {
+                Ok(path) => path.to_string_lossy().into_owned(),
+                Err(e) => {
+                    return Err((
+                        StatusCode::FORBIDDEN,
+                        Json(json!({"error": e})),
+                    ));
+                }
+            };
+            let existing = state.mounts.lock().await.get(&mount_point).cloned();
+            existing.ok_or_else(|| {
+                (
+                    StatusCode::NOT_FOUND,
+                    Json(json!({"error": format!("マウントされていません: {}", mount_point)})),
+                )
+            })?
+        }
+    };
 
-    tracing::info!("Unmounting: {}", mount_path);
+    // Client-restricted tokens may only unmount their clients' shares
+    grant.check(Scope::Mount, &record.client_id)?;
 
-    let result = tokio::process::Command::new("umount")
-        .arg(mount_path)
-        .output()
-        .await;
-
-    match result {
-        Ok(output) => {
-            if output.status.success() {
-                tracing::info!("Unmount success: {}", mount_path);
-                Ok(Json(json!({
-                    "ok": true,
-                    "mount_path": mount_path,
-                })))
-            } else {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(json!({"error": format!("アンマウント失敗: {}", stderr)})),
-                ))
-            }
-        }
+    match unmount_record(&state, backend, &record).await {
+        Ok(()) => Ok(Json(json!({
+            "ok": true,
+            "mount_path": record.mount_point,
+        }))),
         Err(e) => Err((
             StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({"error": format!("umount実行エラー: {}", e)})),
+            Json(json!({"error": format!("アンマウント失敗: {}", e)})),
         )),
     }
 }
 
+/// Unmount a recorded mount and drop it from the registry.
+async fn unmount_record(state: &AppState, backend: &dyn MountBackend, record: &MountRecord) -> Result<(), String> {
+    tracing::info!("Unmounting: {}", record.mount_point);
+    backend.unmount(&record.entry()).await?;
+    tracing::info!("Unmount success: {}", record.mount_point);
+    state.mounts.lock().await.remove(&record.mount_point);
+    Ok(())
+}
+
+/// Unmount every recorded mount of a client (auto-unmount); returns how many.
+pub async fn unmount_client(state: &AppState, client_id: &str) -> Result<usize, String> {
+    let backend = state.mount_backend.as_deref().ok_or("no mount backend")?;
+    reconcile(state, backend).await?;
+    let records: Vec<MountRecord> = state
+        .mounts
+        .lock()
+        .await
+        .for_client(client_id)
+        .into_iter()
+        .cloned()
+        .collect();
+    for record in &records {
+        unmount_record(state, backend, record).await?;
+    }
+    Ok(records.len())
+}
+
+/// Whether the mount registry has a mount of this client.
+pub async fn is_mounted(state: &AppState, client_id: &str) -> bool {
+    !state.mounts.lock().await.for_client(client_id).is_empty()
+}
+
 /// GET /api/mounts
-/// Returns list of currently mounted WebDAV filesystems.
+/// Returns the mounted shares of this server's clients, from the mount
+/// registry after reconciling it with the OS mount table.
 pub async fn list_mounts(
     State(state): State<Arc<AppState>>,
+    Extension(grant): Extension<TokenGrant>,
 ) -> Json<Value> {
-    let port_str = state.port.to_string();
-    let https_port_str = (state.port + 1).to_string();
-    let result = tokio::process::Command::new("mount")
-        .output()
-        .await;
-
-    match result {
-        Ok(output) => {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            let mounts: Vec<Value> = stdout
-                .lines()
-                .filter(|line| {
-                    line.contains("webdav")
-                        || line.contains(&port_str)
-                        || line.contains(&https_port_str)
-                        || line.contains("/webdav/")
-                })
-                .map(|line| {
-                    let parts: Vec<&str> = line.splitn(3, " on ").collect();
-                    let url = parts.first().unwrap_or(&"").trim().to_string();
-                    let rest = parts.get(1).unwrap_or(&"").trim().to_string();
-                    let mount_point = rest.split(" (").next().unwrap_or("").to_string();
-                    json!({
-                        "url": url,
-                        "mount_point": mount_point,
-                        "raw": line,
-                    })
-                })
-                .collect();
-            Json(json!(mounts))
-        }
-        Err(_) => Json(json!([])),
+    let Some(backend) = state.mount_backend.as_deref() else {
+        return Json(json!([]));
+    };
+    if let Err(e) = reconcile(&state, backend).await {
+        tracing::warn!("Could not read the mount table: {}", e);
     }
+    let mounts = state.mounts.lock().await;
+    let visible: Vec<_> = mounts
+        .list()
+        .into_iter()
+        .filter(|m| grant.allows_client(&m.client_id))
+        .map(|m| {
+            json!({
+                "client_id": m.client_id,
+                "mount_point": m.mount_point,
+                "url": m.url,
+                "fs_type": m.fs_type,
+                "strategy": m.strategy,
+                "mounted_at": m.mounted_at,
+            })
+        })
+        .collect();
+    Json(json!(visible))
 }
@@ -0,0 +1,312 @@
+// SNOWCODE - ソフトウェア製品
+// (C) SNOWCODE
+// 開発者: 雪符しき
+// https://snowcode.jp
+// 問い合わせ: info@snowcode.jp
+
+// Linux mount backend: davfs2 (`mount -t davfs`) on the ljc-xxxxxxxx
+// directory, or GVFS (`gio mount`), which mounts under its own FUSE
+// directory ($XDG_RUNTIME_DIR/gvfs). Mounts are read from
+// /proc/self/mountinfo.
+
+use futures_util::future::BoxFuture;
+use std::path::Path;
+
+use crate::mount_backend::{run, MountBackend, MountEntry, MountTarget, Mounted};
+use crate::webdav_bridge::url_decode;
+
+const MOUNTINFO: &str = "/proc/self/mountinfo";
+
+/// Filesystem type of the GVFS FUSE daemon's mount.
+const GVFS_FS_TYPE: &str = "fuse.gvfsd-fuse";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinuxTool {
+    /// davfs2: needs root, or a `user` entry for the mount point in /etc/fstab
+    Davfs,
+    /// GVFS: needs the user's desktop session (D-Bus and gvfsd)
+    Gvfs,
+}
+
+impl LinuxTool {
+    /// Program whose presence makes the tool usable.
+    pub fn program(self) -> &'static str {
+        match self {
+            LinuxTool::Davfs => "mount.davfs",
+            LinuxTool::Gvfs => "gio",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            LinuxTool::Davfs => "davfs",
+            LinuxTool::Gvfs => "gvfs",
+        }
+    }
+}
+
+/// Tries each tool in order.
+pub struct LinuxBackend {
+    tools: Vec<LinuxTool>,
+}
+
+impl LinuxBackend {
+    pub fn new(tools: Vec<LinuxTool>) -> Self {
+        Self { tools }
+    }
+
+    async fn mount_with(&self, tool: LinuxTool, target: &MountTarget<'_>) -> Result<Mounted, String> {
+        let cred = target.credential;
+        match tool {
+            LinuxTool::Davfs => {
+                // mount.davfs asks for the user name and password on stdin
+                // (unless /etc/davfs2/secrets has them)
+                run(
+                    "mount",
+                    &["-t", "davfs", &target.http_url, target.mount_point],
+                    Some(format!("{}\n{}\n", cred.username, cred.password).into_bytes()),
+                )
+                .await?;
+                Ok(Mounted {
+                    mount_point: target.mount_point.to_string(),
+                    webdav_url: target.http_url.clone(),
+                    fs_type: tool.name(),
+                    strategy: tool.name(),
+                })
+            }
+            LinuxTool::Gvfs => {
+                // dav://user@host:port/... — gio then only asks for the password
+                let location = format!(
+                    "dav://{}@{}",
+                    cred.username,
+                    target.http_url.trim_start_matches("http://")
+                );
+                run("gio", &["mount", &location], Some(format!("{}\n", cred.password).into_bytes())).await?;
+                let entry = read_mounts()
+                    .await?
+                    .into_iter()
+                    .find(|m| m.fs_type == tool.name() && m.client_id.as_deref() == Some(target.client_id))
+                    .ok_or("gio mount succeeded but the share is not in the GVFS directory")?;
+                Ok(Mounted {
+                    mount_point: entry.mount_point,
+                    webdav_url: target.http_url.clone(),
+                    fs_type: tool.name(),
+                    strategy: tool.name(),
+                })
+            }
+        }
+    }
+}
+
+impl MountBackend for LinuxBackend {
+    fn name(&self) -> String {
+        let tools: Vec<_> = self.tools.iter().map(|t| t.name()).collect();
+        format!("Linux ({})", tools.join(", "))
+    }
+
+    fn mount<'a>(&'a self, target: &'a MountTarget<'a>) -> BoxFuture<'a, Result<Mounted, String>> {
+        Box::pin(async move {
+            let mut errors = Vec::new();
+            for &tool in &self.tools {
+                tracing::info!("Trying {} ...", tool.name());
+                match self.mount_with(tool, target).await {
+                    Ok(mounted) => return Ok(mounted),
+                    Err(e) => {
+                        tracing::warn!("{} mount failed: {}", tool.name(), e);
+                        errors.push(format!("{}: {}", tool.name(), e));
+                    }
+                }
+            }
+            tracing::error!("All mount strategies failed");
+            Err(format!("マウント失敗 ({})", errors.join(", ")))
+        })
+    }
+
+    fn unmount<'a>(&'a self, entry: &'a MountEntry) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            if entry.fs_type == LinuxTool::Gvfs.name() {
+                run("gio", &["mount", "-u", &entry.mount_point], None).await
+            } else {
+                run("umount", &[&entry.mount_point], None).await
+            }
+        })
+    }
+
+    fn mounts(&self) -> BoxFuture<'_, Result<Vec<MountEntry>, String>> {
+        Box::pin(read_mounts())
+    }
+}
+
+/// WebDAV mounts from /proc/self/mountinfo: davfs2 mounts directly, and
+/// the shares inside the GVFS FUSE directory.
+async fn read_mounts() -> Result<Vec<MountEntry>, String> {
+    let mountinfo = tokio::fs::read_to_string(MOUNTINFO)
+        .await
+        .map_err(|e| format!("{}: {}", MOUNTINFO, e))?;
+    let mut mounts = Vec::new();
+    for (mount_point, fs_type, source) in mountinfo.lines().filter_map(parse_mountinfo_line) {
+        if fs_type == GVFS_FS_TYPE {
+            mounts.extend(read_gvfs_dir(&mount_point).await);
+        } else if (fs_type == "davfs" || fs_type.starts_with("fuse"))
+            && (source.starts_with("http://") || source.starts_with("https://"))
+        {
+            mounts.push(MountEntry::new(source, mount_point, LinuxTool::Davfs.name().to_string()));
+        }
+    }
+    Ok(mounts)
+}
+
+/// Mount point, filesystem type and source of a mountinfo line:
+/// `36 35 0:42 / /home/x/Public/mount/ljc-1a2b3c4d rw,nosuid - fuse http://127.0.0.1:17200/webdav/{id}/ rw`
+/// (see proc(5); the optional fields before `-` vary in number).
+fn parse_mountinfo_line(line: &str) -> Option<(String, String, String)> {
+    let (before, after) = line.split_once(" - ")?;
+    let mount_point = before.split(' ').nth(4)?;
+    let mut after = after.split(' ');
+    let fs_type = after.next()?;
+    let source = after.next()?;
+    Some((unescape(mount_point), unescape(fs_type), unescape(source)))
+}
+
+/// Undo mountinfo's octal escapes (`\040` for a space, `\134` for `\`, ...).
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'\\')
+            .then(|| field.get(i + 1..i + 4))
+            .flatten()
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        if let Some(byte) = escaped {
+            result.push(byte);
+            i += 4;
+            continue;
+        }
+        result.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+/// Shares GVFS has mounted over WebDAV. Each is a directory named after
+/// its location, e.g. `dav:host=127.0.0.1,port=17200,ssl=false,user=ljc-1a2b3c4d,prefix=%2Fwebdav%2F{id}`.
+async fn read_gvfs_dir(gvfs_dir: &str) -> Vec<MountEntry> {
+    let mut mounts = Vec::new();
+    // Another user's GVFS directory is not readable; it has none of our mounts
+    let Ok(mut entries) = tokio::fs::read_dir(gvfs_dir).await else {
+        return mounts;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if let Some(url) = gvfs_dav_url(&name) {
+            let mount_point = Path::new(gvfs_dir).join(&name).to_string_lossy().into_owned();
+            mounts.push(MountEntry::new(url, mount_point, LinuxTool::Gvfs.name().to_string()));
+        }
+    }
+    mounts
+}
+
+/// WebDAV URL of a GVFS `dav:`/`davs:` directory name.
+fn gvfs_dav_url(name: &str) -> Option<String> {
+    let (scheme, params) = name.split_once(':')?;
+    if scheme != "dav" && scheme != "davs" {
+        return None;
+    }
+    let param = |key: &str| {
+        params
+            .split(',')
+            .find_map(|p| p.strip_prefix(key)?.strip_prefix('='))
+            .map(url_decode)
+    };
+    let ssl = scheme == "davs" || param("ssl").as_deref() == Some("true");
+    let host = param("host")?;
+    let port = param("port").map(|p| format!(":{}", p)).unwrap_or_default();
+    let prefix = param("prefix").unwrap_or_default();
+    Some(format!(
+        "{}://{}{}{}/",
+        if ssl { "https" } else { "http" },
+        host,
+        port,
+        prefix.trim_end_matches('/')
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "3eda982f-9953-4e51-a409-a92704640b61";
+
+    #[test]
+    fn mountinfo_line() {
+        let line = format!(
+            "36 35 0:42 / /home/x/Public/mount/ljc-3eda982f rw,nosuid shared:7 - fuse http://127.0.0.1:17200/webdav/{}/ rw",
+            ID
+        );
+        let (mount_point, fs_type, source) = parse_mountinfo_line(&line).unwrap();
+        assert_eq!(mount_point, "/home/x/Public/mount/ljc-3eda982f");
+        assert_eq!(fs_type, "fuse");
+        assert_eq!(source, format!("http://127.0.0.1:17200/webdav/{}/", ID));
+    }
+
+    #[test]
+    fn mountinfo_line_without_optional_fields() {
+        let line = "98 25 0:50 / /run/user/1000/gvfs rw,nosuid,nodev - fuse.gvfsd-fuse gvfsd-fuse rw,user_id=1000";
+        let (mount_point, fs_type, source) = parse_mountinfo_line(line).unwrap();
+        assert_eq!(mount_point, "/run/user/1000/gvfs");
+        assert_eq!(fs_type, GVFS_FS_TYPE);
+        assert_eq!(source, "gvfsd-fuse");
+    }
+
+    #[test]
+    fn mountinfo_line_unescapes_fields() {
+        let line = r"36 35 0:42 / /home/x/My\040Mount\134dir rw - davfs http://h/ rw";
+        let (mount_point, _, _) = parse_mountinfo_line(line).unwrap();
+        assert_eq!(mount_point, r"/home/x/My Mount\dir");
+    }
+
+    #[test]
+    fn mountinfo_line_malformed() {
+        assert!(parse_mountinfo_line("").is_none());
+        assert!(parse_mountinfo_line("36 35 0:42 / /mnt rw").is_none());
+        assert!(parse_mountinfo_line("36 35 - fuse").is_none());
+    }
+
+    #[test]
+    fn unescape_octal() {
+        assert_eq!(unescape(r"a\040b"), "a b");
+        assert_eq!(unescape(r"tab\011"), "tab\t");
+        // Not an escape: left as it is
+        assert_eq!(unescape(r"a\9zz"), r"a\9zz");
+        assert_eq!(unescape(r"end\04"), r"end\04");
+        assert_eq!(unescape("日本語"), "日本語");
+    }
+
+    #[test]
+    fn gvfs_dav_directory() {
+        let name = format!(
+            "dav:host=127.0.0.1,port=17200,ssl=false,user=ljc-3eda982f,prefix=%2Fwebdav%2F{}",
+            ID
+        );
+        assert_eq!(
+            gvfs_dav_url(&name).unwrap(),
+            format!("http://127.0.0.1:17200/webdav/{}/", ID)
+        );
+        let entry = MountEntry::new(gvfs_dav_url(&name).unwrap(), "/run/user/1000/gvfs/x".into(), "gvfs".into());
+        assert_eq!(entry.client_id.as_deref(), Some(ID));
+        assert_eq!(entry.port(), Some(17200));
+    }
+
+    #[test]
+    fn gvfs_davs_and_defaults() {
+        assert_eq!(
+            gvfs_dav_url("davs:host=example.com,prefix=%2Fshare%2F").unwrap(),
+            "https://example.com/share/"
+        );
+        assert_eq!(gvfs_dav_url("dav:host=h,ssl=true").unwrap(), "https://h/");
+        assert!(gvfs_dav_url("sftp:host=h").is_none());
+        assert!(gvfs_dav_url("dav:port=80").is_none());
+        assert!(gvfs_dav_url("no-colon").is_none());
+    }
+}
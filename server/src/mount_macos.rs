@@ -0,0 +1,134 @@
+// SNOWCODE - ソフトウェア製品
+// (C) SNOWCODE
+// 開発者: 雪符しき
+// https://snowcode.jp
+// 問い合わせ: info@snowcode.jp
+
+// macOS mount backend: mount_webdav over loopback HTTP, then HTTPS, then
+// Finder's "mount volume". The mount table comes from `mount` output, as
+// macOS has no /proc.
+
+use futures_util::future::BoxFuture;
+
+use crate::mount_backend::{run, MountBackend, MountEntry, MountTarget, Mounted};
+use crate::webdav_auth::MountCredential;
+
+pub struct MacOsBackend;
+
+impl MountBackend for MacOsBackend {
+    fn name(&self) -> String {
+        "macOS (mount_webdav, Finder)".to_string()
+    }
+
+    fn mount<'a>(&'a self, target: &'a MountTarget<'a>) -> BoxFuture<'a, Result<Mounted, String>> {
+        Box::pin(async move {
+            let cred = target.credential;
+            let mounted = |webdav_url: &str, strategy| Mounted {
+                mount_point: target.mount_point.to_string(),
+                webdav_url: webdav_url.to_string(),
+                fs_type: "webdav",
+                strategy,
+            };
+
+            // Strategy 1: mount_webdav -S via 127.0.0.1 HTTP. macOS Tahoe blocks
+            // HTTP WebDAV for non-loopback addresses, but allows loopback.
+            tracing::info!("Trying mount_webdav -S (127.0.0.1 HTTP) ...");
+            let http_error = match mount_webdav(&target.http_url, target.mount_point, cred).await {
+                Ok(()) => return Ok(mounted(&target.http_url, "mount_webdav")),
+                Err(e) => e,
+            };
+            tracing::warn!("mount_webdav 127.0.0.1 failed: {}", http_error);
+
+            // Strategy 2: mount_webdav via HTTPS
+            tracing::info!("Trying mount_webdav -S (HTTPS) ...");
+            match mount_webdav(&target.https_url, target.mount_point, cred).await {
+                Ok(()) => return Ok(mounted(&target.https_url, "mount_webdav_https")),
+                Err(e) => tracing::warn!("mount_webdav HTTPS failed: {}", e),
+            }
+
+            // Strategy 3: osascript (Finder "mount volume") via 127.0.0.1
+            tracing::info!("Fallback: trying Finder mount volume ...");
+            let applescript = format!(
+                r#"tell application "Finder" to mount volume "{}" as user name "{}" with password "{}""#,
+                target.http_url, cred.username, cred.password
+            );
+            // The script holds the password: osascript reads it from stdin
+            if let Err(e) = run("osascript", &[], Some(applescript.into_bytes())).await {
+                tracing::error!("All mount strategies failed");
+                return Err(format!("マウント失敗 ({}, Finder: {})", http_error, e));
+            }
+
+            // Finder picks its own mount point (/Volumes/...)
+            tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+            let actual_mount = self
+                .mounts()
+                .await
+                .unwrap_or_default()
+                .into_iter()
+                .find(|m| m.client_id.as_deref() == Some(target.client_id))
+                .map(|m| m.mount_point);
+            let mut result = mounted(&target.http_url, "finder");
+            if let Some(mount_point) = actual_mount {
+                result.mount_point = mount_point;
+            }
+            Ok(result)
+        })
+    }
+
+    fn unmount<'a>(&'a self, entry: &'a MountEntry) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move { run("umount", &[&entry.mount_point], None).await })
+    }
+
+    fn mounts(&self) -> BoxFuture<'_, Result<Vec<MountEntry>, String>> {
+        Box::pin(async {
+            let output = tokio::process::Command::new("mount")
+                .output()
+                .await
+                .map_err(|e| format!("mount: {}", e))?;
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            Ok(stdout.lines().filter_map(parse_mount_line).collect())
+        })
+    }
+}
+
+/// `mount_webdav -S -a 0`: the credentials are read from the file
+/// descriptor given to -a (stdin here) instead of the URL, each as a
+/// 32-bit length in host byte order followed by the bytes.
+async fn mount_webdav(url: &str, mount_point: &str, cred: &MountCredential) -> Result<(), String> {
+    let mut auth = Vec::new();
+    for field in [&cred.username, &cred.password] {
+        auth.extend_from_slice(&(field.len() as u32).to_ne_bytes());
+        auth.extend_from_slice(field.as_bytes());
+    }
+    run("mount_webdav", &["-S", "-a", "0", url, mount_point], Some(auth)).await
+}
+
+/// Parse a WebDAV line of `mount` output:
+/// `http://127.0.0.1:17200/webdav/{id}/ on /Users/x/Public/mount/ljc-xxxx (webdav, nodev, ...)`
+fn parse_mount_line(line: &str) -> Option<MountEntry> {
+    let (url, rest) = line.split_once(" on ")?;
+    let (mount_point, options) = rest.rsplit_once(" (")?;
+    let fs_type = options.split([',', ')']).next()?.trim();
+    (fs_type == "webdav").then(|| MountEntry::new(url.to_string(), mount_point.to_string(), fs_type.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn webdav_mount_line() {
+        let line = "http://127.0.0.1:17200/webdav/3eda982f-9953-4e51-a409-a92704640b61/ on /Users/x/Public/mount/ljc 3eda982f (webdav, nodev, noexec, nosuid, mounted by x)";
+        let entry = parse_mount_line(line).unwrap();
+        assert_eq!(entry.mount_point, "/Users/x/Public/mount/ljc 3eda982f");
+        assert_eq!(entry.fs_type, "webdav");
+        assert_eq!(entry.client_id.as_deref(), Some("3eda982f-9953-4e51-a409-a92704640b61"));
+    }
+
+    #[test]
+    fn other_mount_lines() {
+        assert!(parse_mount_line("/dev/disk3s1s1 on / (apfs, sealed, local, read-only, journaled)").is_none());
+        assert!(parse_mount_line("map auto_home on /System/Volumes/Data/home (autofs, automounted, nobrowse)").is_none());
+        assert!(parse_mount_line("garbage").is_none());
+    }
+}
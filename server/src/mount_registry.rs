@@ -0,0 +1,178 @@
+// SNOWCODE - ソフトウェア製品
+// (C) SNOWCODE
+// 開発者: 雪符しき
+// https://snowcode.jp
+// 問い合わせ: info@snowcode.jp
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::mount_backend::MountEntry;
+use crate::secret_file;
+use crate::webdav_auth::MountCredential;
+
+/// Strategy recorded for shares found in the OS mount table that were not
+/// mounted through /api/mount (e.g. Finder's "Connect to Server").
+pub const EXTERNAL_STRATEGY: &str = "external";
+
+/// What happens to a client's mount while the client is offline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OfflineAction {
+    /// Unmount once the client has been gone for a heartbeat interval
+    Unmount,
+    /// Keep the mount and show a read-only placeholder until the client is back
+    Placeholder,
+}
+
+/// Auto-mount policy of a known device (stored in devices.json): mount
+/// when it registers, and `when_offline` when it disconnects.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MountPolicy {
+    /// Directory to mount under, like `mount_path` of /api/mount
+    pub mount_path: String,
+    pub when_offline: OfflineAction,
+}
+
+/// A mounted client share.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MountRecord {
+    pub client_id: String,
+    pub mount_point: String,
+    /// Share URL, without credentials
+    pub url: String,
+    /// Filesystem type from the mount table ("webdav", "davfs", "gvfs")
+    pub fs_type: String,
+    /// Strategy that mounted it, e.g. "mount_webdav", or EXTERNAL_STRATEGY
+    pub strategy: String,
+    /// Unix time of the mount (for external mounts: when first seen)
+    pub mounted_at: u64,
+    /// Per-mount credential the OS presents; restored on startup so the
+    /// mount keeps working across server restarts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential: Option<MountCredential>,
+}
+
+impl MountRecord {
+    /// The mount-table entry, for the backend's `unmount`.
+    pub fn entry(&self) -> MountEntry {
+        MountEntry::new(self.url.clone(), self.mount_point.clone(), self.fs_type.clone())
+    }
+}
+
+/// What the server has mounted, by mount point, persisted as JSON in the
+/// data directory and reconciled with the OS mount table.
+pub struct MountRegistry {
+    path: PathBuf,
+    mounts: HashMap<String, MountRecord>,
+}
+
+impl MountRegistry {
+    /// Load the registry from `path`. A missing file yields an empty registry,
+    /// an invalid one is kept aside (see `secret_file::read_json`) and an
+    /// unreadable one is an error.
+    pub fn load(path: PathBuf) -> std::io::Result<Self> {
+        let mounts = secret_file::read_json::<Vec<MountRecord>>(&path)?
+            .map(|list| {
+                list.into_iter()
+                    .map(|m| (m.mount_point.clone(), m))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(Self { path, mounts })
+    }
+
+    fn save(&self) {
+        let json = match serde_json::to_string_pretty(&self.list()) {
+            Ok(json) => json,
+            Err(e) => {
+                tracing::warn!("Failed to serialize mount registry: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = secret_file::write(&self.path, json) {
+            tracing::warn!("Failed to save mount registry {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// All records, oldest mount first.
+    pub fn list(&self) -> Vec<&MountRecord> {
+        let mut list: Vec<&MountRecord> = self.mounts.values().collect();
+        list.sort_by(|a, b| a.mounted_at.cmp(&b.mounted_at).then(a.mount_point.cmp(&b.mount_point)));
+        list
+    }
+
+    pub fn get(&self, mount_point: &str) -> Option<&MountRecord> {
+        self.mounts.get(mount_point)
+    }
+
+    /// Records of one client (normally at most one).
+    pub fn for_client(&self, client_id: &str) -> Vec<&MountRecord> {
+        self.list().into_iter().filter(|m| m.client_id == client_id).collect()
+    }
+
+    pub fn insert(&mut self, record: MountRecord) {
+        self.mounts.insert(record.mount_point.clone(), record);
+        self.save();
+    }
+
+    pub fn remove(&mut self, mount_point: &str) -> Option<MountRecord> {
+        let removed = self.mounts.remove(mount_point);
+        if removed.is_some() {
+            self.save();
+        }
+        removed
+    }
+
+    /// Bring the registry in line with `table`, the OS mount table's
+    /// entries for this server's shares: records whose mount is gone are
+    /// dropped, and shares mounted outside the server are added as external.
+    pub fn reconcile(&mut self, table: &[MountEntry], now: u64) {
+        let before = self.mounts.len();
+        self.mounts.retain(|mount_point, record| {
+            let mounted = table
+                .iter()
+                .any(|e| &e.mount_point == mount_point && e.client_id.as_deref() == Some(&record.client_id));
+            if !mounted {
+                tracing::info!("Mount gone: {} ({})", mount_point, record.client_id);
+            }
+            mounted
+        });
+        let mut changed = self.mounts.len() != before;
+
+        for entry in table {
+            let Some(client_id) = &entry.client_id else {
+                continue;
+            };
+            if self.mounts.contains_key(&entry.mount_point) {
+                continue;
+            }
+            tracing::info!("Found external mount: {} ({})", entry.mount_point, client_id);
+            self.mounts.insert(
+                entry.mount_point.clone(),
+                MountRecord {
+                    client_id: client_id.clone(),
+                    mount_point: entry.mount_point.clone(),
+                    url: entry.url.clone(),
+                    fs_type: entry.fs_type.clone(),
+                    strategy: EXTERNAL_STRATEGY.to_string(),
+                    mounted_at: now,
+                    credential: None,
+                },
+            );
+            changed = true;
+        }
+        if changed {
+            self.save();
+        }
+    }
+
+    /// Credentials of recorded mounts, to seed `AppState::mount_credentials`.
+    pub fn credentials(&self) -> HashMap<String, MountCredential> {
+        self.mounts
+            .values()
+            .filter_map(|m| Some((m.client_id.clone(), m.credential.clone()?)))
+            .collect()
+    }
+}
@@ -0,0 +1,171 @@
+// SNOWCODE - ソフトウェア製品
+// (C) SNOWCODE
+// 開発者: 雪符しき
+// https://snowcode.jp
+// 問い合わせ: info@snowcode.jp
+
+// Relay path normalization. Every path is normalized here before it is
+// sent over /ws (REST, WebDAV and transfers alike), so confinement to the
+// shared folder does not depend on the client's own path handling.
+
+use crate::protocol::ErrorCode;
+use crate::webdav_bridge::RelayError;
+
+/// Longest path component, in UTF-16 units (the NTFS / APFS limit).
+const MAX_COMPONENT_LEN: usize = 255;
+
+/// Device names Windows resolves in every directory, with any extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "COM¹", "COM²", "COM³", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8",
+    "LPT9", "LPT¹", "LPT²", "LPT³",
+];
+
+/// Canonical form of a relay path: a leading `/`, no trailing one, no
+/// empty or `.` segments. The shared folder itself is `/`.
+///
+/// Refuses `..`, NUL and other control characters, `\` and `:` (Windows
+/// separators, drive prefixes and alternate data streams), Windows device
+/// names, names made only of dots and spaces (Windows trims those), and
+/// components over MAX_COMPONENT_LEN.
+pub fn normalize(path: &str) -> Result<String, RelayError> {
+    let mut normalized = String::with_capacity(path.len() + 1);
+    for segment in path.split('/').filter(|s| !s.is_empty() && *s != ".") {
+        check_component(segment).map_err(|(code, reason)| RelayError::Rejected {
+            code,
+            message: format!("{}: {}", reason, path),
+        })?;
+        normalized.push('/');
+        normalized.push_str(segment);
+    }
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+    Ok(normalized)
+}
+
+fn check_component(name: &str) -> Result<(), (ErrorCode, &'static str)> {
+    let invalid = |reason| Err((ErrorCode::InvalidPath, reason));
+    if name.trim_end_matches([' ', '.']).is_empty() {
+        return invalid("invalid path segment");
+    }
+    if name.chars().any(|c| c.is_control()) {
+        return invalid("control character in path");
+    }
+    if name.contains(['\\', ':']) {
+        return invalid("drive or stream separator in path");
+    }
+    // "nul.txt" and "CON " name the device just like "NUL" and "CON"
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        return invalid("reserved device name in path");
+    }
+    if name.encode_utf16().count() > MAX_COMPONENT_LEN {
+        return Err((ErrorCode::NameTooLong, "name too long in path"));
+    }
+    Ok(())
+}
+
+/// Whether a name in a client's `readdir` reply is a single valid
+/// component, i.e. joining it to its directory stays inside that directory.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && !name.contains('/') && check_component(name).is_ok()
+}
+
+/// Whether `path` is `ancestor` or inside it (both normalized).
+pub fn is_within(path: &str, ancestor: &str) -> bool {
+    ancestor == "/"
+        || path == ancestor
+        || path
+            .strip_prefix(ancestor)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webdav_bridge::url_decode;
+
+    /// Error code `normalize` refuses `path` with, None when accepted.
+    fn refused(path: &str) -> Option<ErrorCode> {
+        match normalize(path) {
+            Ok(_) => None,
+            Err(RelayError::Rejected { code, .. }) => Some(code),
+            Err(e) => panic!("unexpected error for {:?}: {}", path, e),
+        }
+    }
+
+    #[test]
+    fn normalize_canonical_form() {
+        assert_eq!(normalize("").unwrap(), "/");
+        assert_eq!(normalize("/").unwrap(), "/");
+        assert_eq!(normalize("a/b").unwrap(), "/a/b");
+        assert_eq!(normalize("//a/./b/").unwrap(), "/a/b");
+        assert_eq!(normalize("/日本語/ファイル.txt").unwrap(), "/日本語/ファイル.txt");
+    }
+
+    #[test]
+    fn normalize_refuses_parent_segments() {
+        assert_eq!(refused("/.."), Some(ErrorCode::InvalidPath));
+        assert_eq!(refused("/a/../../b"), Some(ErrorCode::InvalidPath));
+        assert_eq!(refused("/a/..."), Some(ErrorCode::InvalidPath));
+        assert_eq!(refused("/a/.. "), Some(ErrorCode::InvalidPath));
+        // Dots inside a name are fine
+        assert_eq!(refused("/a/..b/c..d"), None);
+    }
+
+    #[test]
+    fn normalize_refuses_encoded_separators() {
+        // WebDAV paths are percent-decoded before they are normalized
+        assert_eq!(refused(&url_decode("/a/%2E%2E/b")), Some(ErrorCode::InvalidPath));
+        assert_eq!(refused(&url_decode("/a%5C..%5Cb")), Some(ErrorCode::InvalidPath));
+        assert_eq!(refused(&url_decode("/C%3A/Windows")), Some(ErrorCode::InvalidPath));
+        assert_eq!(refused(&url_decode("/a%2F..%2Fb")), Some(ErrorCode::InvalidPath));
+        assert_eq!(normalize(&url_decode("/a%2Fb")).unwrap(), "/a/b");
+    }
+
+    #[test]
+    fn normalize_refuses_control_characters() {
+        assert_eq!(refused("/a\0b"), Some(ErrorCode::InvalidPath));
+        assert_eq!(refused(&url_decode("/a%00b")), Some(ErrorCode::InvalidPath));
+        assert_eq!(refused("/a\nb"), Some(ErrorCode::InvalidPath));
+    }
+
+    #[test]
+    fn normalize_refuses_windows_specials() {
+        assert_eq!(refused("/file.txt:stream"), Some(ErrorCode::InvalidPath));
+        assert_eq!(refused("/a\\b"), Some(ErrorCode::InvalidPath));
+        assert_eq!(refused("/NUL"), Some(ErrorCode::InvalidPath));
+        assert_eq!(refused("/dir/nul.txt"), Some(ErrorCode::InvalidPath));
+        assert_eq!(refused("/CON "), Some(ErrorCode::InvalidPath));
+        assert_eq!(refused("/com1.log"), Some(ErrorCode::InvalidPath));
+        assert_eq!(refused("/console"), None);
+    }
+
+    #[test]
+    fn normalize_limits_component_length() {
+        assert_eq!(refused(&format!("/{}", "a".repeat(255))), None);
+        assert_eq!(refused(&format!("/{}", "a".repeat(256))), Some(ErrorCode::NameTooLong));
+        // Counted in UTF-16 units, not bytes
+        assert_eq!(refused(&format!("/{}", "あ".repeat(255))), None);
+    }
+
+    #[test]
+    fn valid_names() {
+        assert!(is_valid_name("file.txt"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("."));
+        assert!(!is_valid_name(".."));
+        assert!(!is_valid_name("a/b"));
+        assert!(!is_valid_name("aux"));
+    }
+
+    #[test]
+    fn within() {
+        assert!(is_within("/a/b", "/"));
+        assert!(is_within("/a", "/a"));
+        assert!(is_within("/a/b", "/a"));
+        assert!(!is_within("/ab", "/a"));
+        assert!(!is_within("/a", "/a/b"));
+    }
+}
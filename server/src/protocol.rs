@@ -0,0 +1,438 @@
+// SNOWCODE - ソフトウェア製品
+// (C) SNOWCODE
+// 開発者: 雪符しき
+// https://snowcode.jp
+// 問い合わせ: info@snowcode.jp
+
+// Relay protocol spoken over /ws between the server and clients (the
+// connect page and ljc-agent). Text messages are JSON:
+//
+// 1. client -> server: `Register` (token, identity, version, capabilities)
+// 2. server -> client: `Registered` (client id, negotiated version and capabilities)
+// 3. server -> client: `Envelope` (a `Request` with a routing id)
+// 4. client -> server: `Response` to that id, optionally preceded by
+//    `ClientMessage::Progress` reports for long-running requests. Failed
+//    responses carry an `ErrorCode` the server maps to FsError / HTTP status
+// 5. server -> client: `ServerMessage::Cancel` when the caller stopped waiting
+//
+// File payloads may travel as binary frames instead (see frame.rs), when
+// both sides negotiated `Capability::BinaryFrames`.
+//
+// Version history:
+// - 1: implicit protocol, `binaryFrames` flag in `register`
+// - 2: `protocolVersion` and `capabilities` exchanged at registration
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const PROTOCOL_VERSION: u32 = 2;
+/// Oldest client version the server still talks to.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Optional features. Each side only uses a feature when both offered it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Capability {
+    /// File payloads as binary frames instead of base64 JSON
+    BinaryFrames,
+    /// `rename` also moves directories
+    DirRename,
+    /// Understands `Cancel` for in-flight requests
+    Cancel,
+    /// Reports `Progress` on long requests (directory moves, copies), which keeps
+    /// them from timing out
+    Progress,
+    /// Understands `Request::Copy`
+    Copy,
+    /// Offered by a newer peer, unknown to this build
+    #[serde(other)]
+    Unknown,
+}
+
+/// Capabilities this build of the server (or agent) implements.
+pub const CAPABILITIES: &[Capability] = &[
+    Capability::BinaryFrames,
+    Capability::DirRename,
+    Capability::Cancel,
+    Capability::Progress,
+    Capability::Copy,
+];
+
+/// Capabilities offered by the peer that this side also supports.
+pub fn negotiate(offered: &[Capability], supported: &[Capability]) -> Vec<Capability> {
+    supported
+        .iter()
+        .copied()
+        .filter(|c| offered.contains(c))
+        .collect()
+}
+
+fn legacy_version() -> u32 {
+    1
+}
+
+/// First message on a new connection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename = "register", rename_all = "camelCase")]
+pub struct Register {
+    pub token: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub folder_name: String,
+    /// Stable device identity; empty for pages without one
+    #[serde(default)]
+    pub device_id: String,
+    #[serde(default)]
+    pub device_key: String,
+    /// `Registered.resume_token` from the previous connection, if any
+    #[serde(default)]
+    pub resume_token: String,
+    /// Version 1 flag, kept so newer clients still work with older servers
+    #[serde(default)]
+    pub binary_frames: bool,
+    #[serde(default = "legacy_version")]
+    pub protocol_version: u32,
+    #[serde(default)]
+    pub capabilities: Vec<Capability>,
+}
+
+impl Register {
+    /// Capabilities offered by the client, including the version 1 flag.
+    pub fn offered_capabilities(&self) -> Vec<Capability> {
+        let mut offered = self.capabilities.clone();
+        if self.binary_frames && !offered.contains(&Capability::BinaryFrames) {
+            offered.push(Capability::BinaryFrames);
+        }
+        offered
+    }
+}
+
+/// Reply to a successful `Register`. A rejected registration closes the socket.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename = "registered", rename_all = "camelCase")]
+pub struct Registered {
+    pub client_id: String,
+    /// The client id was kept from an earlier connection (device identity or resume token)
+    pub resumed: bool,
+    /// Version both sides speak: the lower of the two
+    #[serde(default = "legacy_version")]
+    pub protocol_version: u32,
+    #[serde(default)]
+    pub capabilities: Vec<Capability>,
+    /// Present this in the next `Register` to get the same client id back
+    /// after a reconnect. Single use; a new one comes with every `Registered`.
+    #[serde(default)]
+    pub resume_token: String,
+}
+
+/// A file operation relayed to the client. Paths are relative to the
+/// shared folder and use `/` separators.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum Request {
+    /// Reply: `Vec<FileStat>`
+    Readdir { path: String },
+    /// Reply: `FileContent`
+    ReadFile { path: String },
+    /// Reply: `ChunkContent`, or a `ChunkData` frame when `binary` is set
+    ReadChunk {
+        path: String,
+        #[serde(default)]
+        offset: u64,
+        length: u64,
+        #[serde(default)]
+        binary: bool,
+    },
+    /// Reply: `Written`
+    WriteFile { path: String, data: String },
+    /// Start a chunked upload to a temporary location
+    UploadOpen { upload_id: String, path: String },
+    /// Reply: `Written` (total so far). Chunks must arrive in `seq` order.
+    UploadChunk { upload_id: String, seq: u64, data: String },
+    /// Replace the target with the uploaded data. Reply: `Written`
+    UploadCommit { upload_id: String },
+    UploadAbort { upload_id: String },
+    Mkdir { path: String },
+    /// Files and directories (recursively)
+    Delete { path: String },
+    Rename { old_path: String, new_path: String },
+    /// Copy on the client, without sending the bytes through the server.
+    /// Directories are copied recursively and must not exist at `new_path`;
+    /// an existing target file is replaced. Reply: `{ "copied": <files> }`
+    Copy { old_path: String, new_path: String },
+    /// Reply: `FileStat`
+    Stat { path: String },
+}
+
+impl Request {
+    /// Wire name of the command (the `type` field).
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Readdir { .. } => "readdir",
+            Self::ReadFile { .. } => "readFile",
+            Self::ReadChunk { .. } => "readChunk",
+            Self::WriteFile { .. } => "writeFile",
+            Self::UploadOpen { .. } => "uploadOpen",
+            Self::UploadChunk { .. } => "uploadChunk",
+            Self::UploadCommit { .. } => "uploadCommit",
+            Self::UploadAbort { .. } => "uploadAbort",
+            Self::Mkdir { .. } => "mkdir",
+            Self::Delete { .. } => "delete",
+            Self::Rename { .. } => "rename",
+            Self::Copy { .. } => "copy",
+            Self::Stat { .. } => "stat",
+        }
+    }
+
+    /// The path (or upload id) the command acts on, for logging.
+    pub fn target(&self) -> &str {
+        match self {
+            Self::Readdir { path }
+            | Self::ReadFile { path }
+            | Self::ReadChunk { path, .. }
+            | Self::WriteFile { path, .. }
+            | Self::UploadOpen { path, .. }
+            | Self::Mkdir { path }
+            | Self::Delete { path }
+            | Self::Stat { path } => path,
+            Self::Rename { old_path, .. } | Self::Copy { old_path, .. } => old_path,
+            Self::UploadChunk { upload_id, .. }
+            | Self::UploadCommit { upload_id }
+            | Self::UploadAbort { upload_id } => upload_id,
+        }
+    }
+
+    /// The paths in the command, for normalizing them before relaying.
+    pub fn paths_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            Self::Readdir { path }
+            | Self::ReadFile { path }
+            | Self::ReadChunk { path, .. }
+            | Self::WriteFile { path, .. }
+            | Self::UploadOpen { path, .. }
+            | Self::Mkdir { path }
+            | Self::Delete { path }
+            | Self::Stat { path } => vec![path],
+            Self::Rename { old_path, new_path } | Self::Copy { old_path, new_path } => {
+                vec![old_path, new_path]
+            }
+            Self::UploadChunk { .. } | Self::UploadCommit { .. } | Self::UploadAbort { .. } => Vec::new(),
+        }
+    }
+
+    /// Commands that do not modify the shared folder.
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Self::Readdir { .. } | Self::ReadFile { .. } | Self::ReadChunk { .. } | Self::Stat { .. }
+        )
+    }
+}
+
+/// A request with the id its `Response` is routed by.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope {
+    pub id: String,
+    #[serde(flatten)]
+    pub request: Request,
+}
+
+/// Any message the server sends after `Registered`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ServerMessage {
+    /// The caller of request `id` went away (HTTP request dropped or timed
+    /// out). The client stops working on it; any reply is ignored.
+    /// Only sent to clients with `Capability::Cancel`.
+    Cancel { id: String },
+    #[serde(untagged)]
+    Request(Envelope),
+}
+
+/// Any text message the client sends after `Register`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ClientMessage {
+    /// Only sent when `Capability::Progress` was negotiated
+    Progress(Progress),
+    #[serde(untagged)]
+    Response(Response),
+}
+
+/// Work done so far on request `id`. Each report restarts the relay
+/// timeout, so a long move only fails when the client stops reporting.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Progress {
+    pub id: String,
+    pub done: u64,
+    pub total: u64,
+    /// Entry being processed, for logging
+    #[serde(default)]
+    pub current: String,
+}
+
+/// The client's answer to an `Envelope` (or to an upload chunk frame,
+/// in which case `id` is the upload id).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Response {
+    pub id: String,
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<ErrorCode>,
+}
+
+impl Response {
+    pub fn success(id: impl Into<String>, data: impl Serialize) -> Self {
+        Self {
+            id: id.into(),
+            ok: true,
+            data: Some(serde_json::to_value(data).unwrap_or(Value::Null)),
+            error: None,
+            code: None,
+        }
+    }
+
+    pub fn failure(id: impl Into<String>, code: ErrorCode, error: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            ok: false,
+            data: None,
+            error: Some(error.into()),
+            code: Some(code),
+        }
+    }
+
+    /// Why the request failed. Clients that predate error codes only send
+    /// text, which is classified the way those clients worded it.
+    pub fn error_code(&self) -> ErrorCode {
+        if let Some(code) = self.code {
+            return code;
+        }
+        let error = self.error.as_deref().unwrap_or_default();
+        if error.contains("not found") || error.contains("NotFound") {
+            ErrorCode::NotFound
+        } else if error.contains("permission") || error.contains("Permission") {
+            ErrorCode::PermissionDenied
+        } else {
+            ErrorCode::Other
+        }
+    }
+
+    /// Decode `data` as the reply type of the request.
+    pub fn data_as<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        T::deserialize(self.data.as_ref().unwrap_or(&Value::Null))
+    }
+}
+
+/// Why a request failed, in a failed `Response`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorCode {
+    NotFound,
+    PermissionDenied,
+    AlreadyExists,
+    /// Directory still has entries
+    NotEmpty,
+    /// A file where a directory was expected, or the other way round
+    TypeMismatch,
+    /// Locked or in use by another program
+    Busy,
+    /// Disk full or quota exceeded
+    NoSpace,
+    NameTooLong,
+    /// File exceeds what the client's filesystem can store
+    TooLarge,
+    /// Malformed path, or one that escapes the shared folder
+    InvalidPath,
+    /// Bad upload id, chunk out of order, undecodable data, ...
+    InvalidRequest,
+    /// Request type the client does not implement
+    Unsupported,
+    /// Anything else (also codes added by a newer peer)
+    #[serde(other)]
+    Other,
+}
+
+impl ErrorCode {
+    /// Classify a native I/O error (ljc-agent).
+    pub fn from_io(e: &std::io::Error) -> Self {
+        use std::io::ErrorKind;
+
+        #[cfg(windows)]
+        match e.raw_os_error() {
+            // Sharing and lock violations: the file is open in another program
+            Some(32 | 33) => return ErrorCode::Busy,
+            // Other invalid names fall through to InvalidPath
+            Some(206) => return ErrorCode::NameTooLong,
+            _ if e.kind() == ErrorKind::InvalidFilename => return ErrorCode::InvalidPath,
+            _ => {}
+        }
+        match e.kind() {
+            ErrorKind::NotFound => ErrorCode::NotFound,
+            ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem => ErrorCode::PermissionDenied,
+            ErrorKind::AlreadyExists => ErrorCode::AlreadyExists,
+            ErrorKind::DirectoryNotEmpty => ErrorCode::NotEmpty,
+            ErrorKind::NotADirectory | ErrorKind::IsADirectory => ErrorCode::TypeMismatch,
+            ErrorKind::ResourceBusy | ErrorKind::ExecutableFileBusy => ErrorCode::Busy,
+            ErrorKind::StorageFull | ErrorKind::QuotaExceeded => ErrorCode::NoSpace,
+            ErrorKind::InvalidFilename => ErrorCode::NameTooLong,
+            ErrorKind::FileTooLarge => ErrorCode::TooLarge,
+            ErrorKind::Unsupported => ErrorCode::Unsupported,
+            _ => ErrorCode::Other,
+        }
+    }
+}
+
+/// `readdir` entry and `stat` reply.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileStat {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub is_dir: bool,
+    #[serde(default)]
+    pub size: u64,
+    /// ISO 8601 (`Date.toISOString()`)
+    #[serde(default)]
+    pub modified: String,
+}
+
+/// `readFile` reply.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileContent {
+    /// Base64 file contents
+    pub data: String,
+    pub size: u64,
+    #[serde(default)]
+    pub name: String,
+    /// MIME type, if the client knows it
+    #[serde(default, rename = "type")]
+    pub mime_type: String,
+    #[serde(default)]
+    pub modified: String,
+}
+
+/// `readChunk` reply when binary frames are not used.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkContent {
+    /// Base64 chunk contents
+    pub data: String,
+    pub offset: u64,
+    /// Size of the whole file
+    pub size: u64,
+    #[serde(default)]
+    pub modified: String,
+    pub eof: bool,
+}
+
+/// `writeFile`, `uploadChunk` and `uploadCommit` reply.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Written {
+    pub written: u64,
+}
@@ -4,33 +4,40 @@
 // https://snowcode.jp
 // 問い合わせ: info@snowcode.jp
 
-use axum::extract::ws::Message;
-use axum::extract::{Path, State};
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
 use axum::response::Json;
+use axum::Extension;
+use futures_util::StreamExt;
+use serde::Deserialize;
 use serde_json::{json, Value};
 use std::sync::Arc;
-use tokio::sync::oneshot;
-use tokio::time::{timeout, Duration};
 
+use crate::protocol::{Request, Response};
 use crate::state::AppState;
+use crate::tokens::{Scope, TokenGrant};
+use crate::transfers::Upload;
+use crate::webdav_bridge::{RelayError, RelayFs, UPLOAD_CHUNK_SIZE};
+
+/// Commands callable over REST. Uploads go through /api/upload (and
+/// WebDAV, transfers), never the raw upload commands: those carry an
+/// upload id instead of a path, so nothing here could validate them.
+const ALLOWED_TYPES: &[&str] = &[
+    "readdir", "readFile", "readChunk", "stat", "mkdir", "delete", "rename", "copy",
+];
 
 /// POST /api/relay/{client_id}
 /// Body: {"type": "readdir", "path": "/", ...}
 /// Sends the command to the Windows client via WS, waits for response.
+/// Failed commands answer with the matching HTTP status.
 pub async fn relay_command(
     State(state): State<Arc<AppState>>,
+    Extension(grant): Extension<TokenGrant>,
     Path(client_id): Path<String>,
-    Json(mut body): Json<Value>,
-) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
-    // Validate command type whitelist
-    let cmd_type = body
-        .get("type")
-        .and_then(|v| v.as_str())
-        .unwrap_or("");
-    const ALLOWED_TYPES: &[&str] = &[
-        "readdir", "readFile", "writeFile", "stat", "mkdir", "delete", "rename",
-    ];
+    Json(body): Json<Value>,
+) -> Result<Json<Response>, (StatusCode, Json<Value>)> {
+    let cmd_type = body.get("type").and_then(|v| v.as_str()).unwrap_or("");
     if !ALLOWED_TYPES.contains(&cmd_type) {
         return Err((
             StatusCode::BAD_REQUEST,
@@ -38,68 +45,100 @@ pub async fn relay_command(
         ));
     }
 
-    // Generate request ID
-    let request_id = uuid::Uuid::new_v4().to_string();
-    body["id"] = json!(request_id);
-
-    // Get the client's WS sender
-    let tx = {
-        let clients = state.clients.read().await;
-        match clients.get(&client_id) {
-            Some(client) => client.tx.clone(),
-            None => {
-                return Err((
-                    StatusCode::NOT_FOUND,
-                    Json(json!({"error": "Client not found"})),
-                ));
-            }
-        }
+    // Only protocol commands are relayed; the required scope depends on the type
+    let mut request: Request = serde_json::from_value(body).map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": format!("Invalid command: {}", e)})),
+        )
+    })?;
+    let scope = if request.is_read_only() {
+        Scope::RelayRead
+    } else {
+        Scope::RelayWrite
     };
+    grant.check(scope, &client_id)?;
 
-    // Register pending request
-    let (resp_tx, resp_rx) = oneshot::channel();
-    {
-        let mut pending = state.pending.write().await;
-        pending.insert(request_id.clone(), resp_tx);
+    // REST callers always get JSON, so binary (frame) responses are never
+    // requested on this path
+    if let Request::ReadChunk { binary, .. } = &mut request {
+        *binary = false;
     }
 
-    // Send command to Windows client
-    if tx
-        .send(Message::Text(body.to_string().into()))
-        .is_err()
-    {
-        let mut pending = state.pending.write().await;
-        pending.remove(&request_id);
-        return Err((
-            StatusCode::BAD_GATEWAY,
-            Json(json!({"error": "Failed to send to client"})),
-        ));
-    }
+    // Failures carry the status of their cause: the client's error code
+    // (404, 409, 423, 507, ...), 502 when the client is unreachable and 504
+    // on timeout (timeouts.relay_secs, restarted by progress reports)
+    let fs = RelayFs::new(state, client_id);
+    fs.send_command(request).await.map(Json).map_err(relay_error)
+}
 
-    // Wait for response with timeout (30s for large file reads)
-    match timeout(Duration::from_secs(30), resp_rx).await {
-        Ok(Ok(response)) => Ok(Json(response)),
-        Ok(Err(_)) => Err((
-            StatusCode::BAD_GATEWAY,
-            Json(json!({"error": "Client connection lost"})),
-        )),
-        Err(_) => {
-            let mut pending = state.pending.write().await;
-            pending.remove(&request_id);
-            Err((
-                StatusCode::GATEWAY_TIMEOUT,
-                Json(json!({"error": "Request timed out"})),
-            ))
+#[derive(Deserialize)]
+pub struct UploadParams {
+    pub path: String,
+}
+
+/// POST /api/upload/{client_id}?path=/folder/file.txt
+/// Body: the file content. Streamed to the client as upload chunks and
+/// committed once the body is complete; an interrupted upload is aborted.
+pub async fn upload_file(
+    State(state): State<Arc<AppState>>,
+    Extension(grant): Extension<TokenGrant>,
+    Path(client_id): Path<String>,
+    Query(params): Query<UploadParams>,
+    body: Body,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    grant.check(Scope::RelayWrite, &client_id)?;
+    let fs = RelayFs::new(state, client_id);
+    // The path is normalized when uploadOpen is sent
+    let upload = Upload::open(&fs, &params.path).await.map_err(relay_error)?;
+
+    let mut stream = body.into_data_stream();
+    let mut pending = Vec::with_capacity(UPLOAD_CHUNK_SIZE);
+    let mut seq = 0;
+    let mut written = 0;
+    loop {
+        let data = stream.next().await.transpose().map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"ok": false, "error": format!("Upload interrupted: {}", e)})),
+            )
+        })?;
+        let end = data.is_none();
+        pending.extend_from_slice(&data.unwrap_or_default());
+        while pending.len() >= UPLOAD_CHUNK_SIZE || (end && !pending.is_empty()) {
+            let chunk: Vec<u8> = pending.drain(..UPLOAD_CHUNK_SIZE.min(pending.len())).collect();
+            fs.upload_chunk(&upload.id, seq, &chunk).await.map_err(relay_error)?;
+            seq += 1;
+            written += chunk.len() as u64;
+        }
+        if end {
+            break;
         }
     }
+    upload.commit().await.map_err(relay_error)?;
+    Ok(Json(json!({"ok": true, "written": written})))
+}
+
+fn relay_error(e: RelayError) -> (StatusCode, Json<Value>) {
+    (
+        e.status(),
+        Json(json!({"ok": false, "error": e.to_string(), "code": e.code()})),
+    )
 }
 
 /// GET /api/clients
 /// Returns list of connected Windows clients.
+/// Needs relay:read; tokens restricted to specific clients only see those clients.
 pub async fn list_clients(
     State(state): State<Arc<AppState>>,
-) -> Json<Value> {
+    Extension(grant): Extension<TokenGrant>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    grant.require(Scope::RelayRead)?;
     let clients = state.clients.read().await;
-    let list: Vec<_> = clients.values().map(|c| &c.info).collect();
-    Json(json!(list))
+    let list: Vec<_> = clients
+        .values()
+        .filter(|c| grant.allows_client(&c.info.id))
+        .map(|c| c.snapshot())
+        .collect();
+    Ok(Json(json!(list)))
 }
@@ -0,0 +1,157 @@
+// SNOWCODE - ソフトウェア製品
+// (C) SNOWCODE
+// 開発者: 雪符しき
+// https://snowcode.jp
+// 問い合わせ: info@snowcode.jp
+
+// Files holding credentials (device keys, API tokens, mount passwords, the
+// TLS private key) are created owner-only (rw-------) rather than chmodded
+// after the fact, so they are never readable by other users, not even
+// briefly. The contents go to a temporary file that is renamed over the
+// target, which also means a crash never leaves a half-written file.
+//
+// Reading them back distinguishes a missing file (first start) from a
+// damaged one: a file that does not parse is moved aside rather than
+// silently replaced, and one that cannot be read at all is an error.
+
+use serde::de::DeserializeOwned;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Read the JSON in `path`. `None` when the file does not exist, or did not
+/// parse and was renamed to `<name>.bad-<unix time>` so that saving a fresh
+/// one keeps it. Any other read error, or a failed rename, is returned.
+pub fn read_json<T: DeserializeOwned>(path: &Path) -> io::Result<Option<T>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(io::Error::new(e.kind(), format!("cannot read {}: {}", path.display(), e))),
+    };
+    match serde_json::from_str(&text) {
+        Ok(value) => Ok(Some(value)),
+        Err(parse_error) => {
+            let aside = bad_path(path);
+            std::fs::rename(path, &aside).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("{} is invalid ({}) and could not be moved aside: {}", path.display(), parse_error, e),
+                )
+            })?;
+            tracing::warn!(
+                "{} is invalid ({}); moved to {} and starting with an empty one",
+                path.display(),
+                parse_error,
+                aside.display()
+            );
+            Ok(None)
+        }
+    }
+}
+
+/// Replace `path` with `contents`, owner-only.
+pub fn write(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let tmp = temp_path(path);
+    let result = write_new(&tmp, contents.as_ref()).and_then(|()| std::fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result
+}
+
+fn write_new(tmp: &Path, contents: &[u8]) -> io::Result<()> {
+    // A leftover from an earlier failed write may have other permissions;
+    // the mode only applies to a file this call creates
+    match std::fs::remove_file(tmp) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+/// `tokens.json` -> `tokens.json.bad-<unix time>`
+fn bad_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    path.with_file_name(format!("{}.bad-{}", name, now))
+}
+
+/// `tokens.json` -> `.tokens.json.tmp`, in the same directory so the
+/// rename stays on one filesystem.
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!(".{}.tmp", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_owner_only_and_replaces() {
+        let dir = std::env::temp_dir().join(format!("ljc-secret-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tokens.json");
+        std::fs::write(&path, "old").unwrap();
+        // A stale temp file with loose permissions is not reused
+        std::fs::write(temp_path(&path), "stale").unwrap();
+
+        write(&path, "new").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert!(!temp_path(&path).exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn read_json_keeps_invalid_file_aside() {
+        let dir = std::env::temp_dir().join(format!("ljc-secret-read-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tokens.json");
+
+        assert_eq!(read_json::<Vec<u32>>(&path).unwrap(), None);
+        std::fs::write(&path, "[1, 2]").unwrap();
+        assert_eq!(read_json::<Vec<u32>>(&path).unwrap(), Some(vec![1, 2]));
+
+        std::fs::write(&path, "[1, 2").unwrap();
+        assert_eq!(read_json::<Vec<u32>>(&path).unwrap(), None);
+        assert!(!path.exists());
+        let kept: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| std::fs::read_to_string(e.unwrap().path()).unwrap())
+            .collect();
+        assert_eq!(kept, vec!["[1, 2".to_string()]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn read_json_fails_on_unreadable_file() {
+        // A directory in place of the file: not NotFound, so not treated as empty
+        let dir = std::env::temp_dir().join(format!("ljc-secret-unreadable-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert!(read_json::<Vec<u32>>(&dir).is_err());
+        assert!(dir.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fails_without_directory() {
+        let path = std::env::temp_dir().join("ljc-secret-missing-dir").join("x").join("tokens.json");
+        assert!(write(&path, "x").is_err());
+    }
+}
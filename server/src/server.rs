@@ -4,10 +4,13 @@
 // https://snowcode.jp
 // 問い合わせ: info@snowcode.jp
 
+use crate::auto_mount;
 use crate::connect_html;
 use crate::mount;
 use crate::relay;
 use crate::state::AppState;
+use crate::tokens::{self, Scope, TokenGrant};
+use crate::transfers;
 use crate::webdav_bridge;
 use crate::ws;
 use axum::extract::ws::WebSocketUpgrade;
@@ -15,17 +18,22 @@ use axum::extract::{Request, State};
 use axum::http::{header, HeaderValue, Method, StatusCode};
 use axum::middleware::{self, Next};
 use axum::response::{IntoResponse, Response};
-use axum::routing::{get, post};
+use axum::routing::{delete, get, post};
 use axum::Router;
 use std::sync::Arc;
 use tower_http::classify::{ServerErrorsAsFailures, SharedClassifier};
 use tower_http::cors::CorsLayer;
 use tower_http::trace::{self, TraceLayer};
 
-/// Authentication middleware: validates Bearer token on protected routes.
+/// Request extension marking requests that arrived on the HTTPS listener.
+#[derive(Debug, Clone, Copy)]
+pub struct TlsConnection;
+
+/// Authentication middleware: validates Bearer token on protected routes
+/// and attaches its `TokenGrant` to the request.
 async fn auth_middleware(
     State(state): State<Arc<AppState>>,
-    req: Request,
+    mut req: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
     let token = req
@@ -35,29 +43,56 @@ async fn auth_middleware(
         .and_then(|v| v.strip_prefix("Bearer "))
         .unwrap_or("");
 
-    if token != state.api_token {
+    let grant = state.tokens.read().await.verify(token).map(|t| t.grant());
+    let Some(grant) = grant else {
         tracing::warn!(
             "Auth rejected: {} {} (invalid token)",
             req.method(),
             req.uri().path()
         );
         return Err(StatusCode::UNAUTHORIZED);
-    }
+    };
 
+    req.extensions_mut().insert(grant);
+    Ok(next.run(req).await)
+}
+
+/// Scope middleware: rejects tokens without `scope`. Runs after `auth_middleware`.
+async fn require_scope(
+    State(scope): State<Scope>,
+    req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let allowed = req
+        .extensions()
+        .get::<TokenGrant>()
+        .is_some_and(|grant| grant.has(scope));
+    if !allowed {
+        tracing::warn!(
+            "Auth rejected: {} {} (missing scope {})",
+            req.method(),
+            req.uri().path(),
+            scope.as_str()
+        );
+        return Err(StatusCode::FORBIDDEN);
+    }
     Ok(next.run(req).await)
 }
 
 pub fn build_router(state: Arc<AppState>) -> Router {
     let webdav_state = state.clone();
 
-    // CORS: allow localhost + LAN origins (not fully permissive)
+    // CORS: allow the configured dashboard origins only (not fully permissive).
+    // Origins are validated when the config is loaded.
+    let origins: Vec<HeaderValue> = state
+        .cors_origins
+        .iter()
+        .filter_map(|o| o.parse().ok())
+        .collect();
     let cors = CorsLayer::new()
-        .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
+        .allow_methods([Method::GET, Method::POST, Method::DELETE, Method::OPTIONS])
         .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION])
-        .allow_origin([
-            "http://localhost:17100".parse::<HeaderValue>().unwrap(),
-            "http://127.0.0.1:17100".parse::<HeaderValue>().unwrap(),
-        ]);
+        .allow_origin(origins);
 
     // Public routes (no auth required)
     let public_routes = Router::new()
@@ -65,13 +100,38 @@ pub fn build_router(state: Arc<AppState>) -> Router {
         .route("/ws", get(ws_upgrade))
         .route("/api/connect-html", get(connect_html::connect_html));
 
-    // Protected routes (require Bearer token)
-    let protected_routes = Router::new()
-        .route("/api/clients", get(relay::list_clients))
-        .route("/api/relay/{client_id}", post(relay::relay_command))
+    // Mount management (require "mount" scope)
+    let mount_routes = Router::new()
         .route("/api/mount", post(mount::mount_webdav))
         .route("/api/unmount", post(mount::unmount_webdav))
         .route("/api/mounts", get(mount::list_mounts))
+        .route("/api/mount-policies", get(auto_mount::list_policies))
+        .route(
+            "/api/mount-policies/{client_id}",
+            post(auto_mount::set_policy).delete(auto_mount::delete_policy),
+        )
+        .route_layer(middleware::from_fn_with_state(Scope::Mount, require_scope));
+
+    // Token management (require "admin" scope)
+    let admin_routes = Router::new()
+        .route("/api/tokens", get(tokens::list_tokens).post(tokens::create_token))
+        .route("/api/tokens/{id}", delete(tokens::revoke_token))
+        .route("/api/tokens/{id}/rotate", post(tokens::rotate_token))
+        .route_layer(middleware::from_fn_with_state(Scope::Admin, require_scope));
+
+    // Protected routes (require Bearer token). Relay read/write scopes and
+    // client restrictions are checked in the handlers.
+    let protected_routes = Router::new()
+        .route("/api/token", get(tokens::current_token))
+        .route("/api/clients", get(relay::list_clients))
+        .route("/api/relay/{client_id}", post(relay::relay_command))
+        .route("/api/upload/{client_id}", post(relay::upload_file))
+        .route("/api/transfers", get(transfers::list_transfers).post(transfers::create_transfer))
+        .route("/api/transfers/{id}", get(transfers::get_transfer).delete(transfers::delete_transfer))
+        .route("/api/transfers/{id}/cancel", post(transfers::cancel_transfer))
+        .route("/api/transfers/{id}/resume", post(transfers::resume_transfer))
+        .merge(mount_routes)
+        .merge(admin_routes)
         .layer(middleware::from_fn_with_state(state.clone(), auth_middleware));
 
     let api_routes = Router::new()
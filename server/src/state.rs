@@ -4,11 +4,22 @@
 // https://snowcode.jp
 // 問い合わせ: info@snowcode.jp
 
+use crate::config::AppConfig;
+use crate::devices::{DeviceRegistry, ResumeTokens};
+use crate::mount_backend::{self, MountBackend};
+use crate::mount_registry::MountRegistry;
+use crate::protocol::{Capability, Response, ServerMessage};
+use crate::tokens::TokenStore;
+use crate::transfers::TransferJob;
+use crate::webdav_auth::MountCredential;
 use axum::extract::ws::Message;
+use bytes::Bytes;
 use serde::Serialize;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::{mpsc, oneshot, RwLock};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, Mutex, Notify, RwLock};
 
 #[derive(Debug, Clone, Serialize)]
 pub struct ClientInfo {
@@ -16,35 +27,288 @@ pub struct ClientInfo {
     pub name: String,
     pub folder_name: String,
     pub connected_at: String,
+    /// Negotiated protocol version (see protocol.rs)
+    pub protocol_version: u32,
+    pub capabilities: Vec<Capability>,
+    /// Last message (or pong) received from the client
+    pub last_seen: String,
+    /// The client missed at least one heartbeat; requests to it fail fast
+    pub stale: bool,
 }
 
 pub struct ConnectedClient {
     pub info: ClientInfo,
     pub tx: mpsc::UnboundedSender<Message>,
+    pub health: Arc<ClientHealth>,
 }
 
-pub type PendingResponder = oneshot::Sender<serde_json::Value>;
+impl ConnectedClient {
+    /// Whether the capability was negotiated at registration.
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.info.capabilities.contains(&capability)
+    }
+
+    /// What a request needs to be relayed over the current connection.
+    pub fn link(&self) -> ClientLink {
+        ClientLink {
+            tx: self.tx.clone(),
+            binary_frames: self.supports(Capability::BinaryFrames),
+            cancel: self.supports(Capability::Cancel),
+        }
+    }
+
+    /// Client info with the current liveness.
+    pub fn snapshot(&self) -> ClientInfo {
+        ClientInfo {
+            last_seen: self.health.last_seen().to_string(),
+            stale: self.health.is_stale(),
+            ..self.info.clone()
+        }
+    }
+}
+
+/// A client's connection as seen by a request about to be relayed.
+#[derive(Clone)]
+pub struct ClientLink {
+    pub tx: mpsc::UnboundedSender<Message>,
+    /// File payloads travel as binary frames (see frame.rs)
+    pub binary_frames: bool,
+    /// The client understands `ServerMessage::Cancel`
+    pub cancel: bool,
+}
+
+/// Liveness of a client connection, kept by the heartbeat in ws.rs.
+#[derive(Debug)]
+pub struct ClientHealth {
+    /// Unix seconds of the last message received
+    last_seen: AtomicU64,
+    /// Heartbeat intervals in a row without any message
+    missed: AtomicU32,
+}
+
+impl ClientHealth {
+    pub fn new() -> Self {
+        Self {
+            last_seen: AtomicU64::new(unix_now()),
+            missed: AtomicU32::new(0),
+        }
+    }
+
+    /// Record a message from the client.
+    pub fn seen(&self) {
+        self.last_seen.store(unix_now(), Ordering::Relaxed);
+        self.missed.store(0, Ordering::Relaxed);
+    }
+
+    /// Record a heartbeat interval without messages; returns the misses in a row.
+    pub fn miss(&self) -> u32 {
+        self.missed.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn last_seen(&self) -> u64 {
+        self.last_seen.load(Ordering::Relaxed)
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.missed.load(Ordering::Relaxed) > 0
+    }
+}
+
+pub(crate) fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// A client's response to a relayed request.
+#[derive(Debug)]
+pub enum RelayReply {
+    /// JSON text message
+    Json(Response),
+    /// Payload of a binary frame (successful readChunk)
+    Binary(Bytes),
+    /// The connection closed before the client replied
+    Disconnected,
+}
+
+pub type PendingResponder = oneshot::Sender<RelayReply>;
+
+/// A relayed request waiting for its client's reply.
+pub struct PendingRequest {
+    /// Sender of the connection the request went out on
+    pub tx: mpsc::UnboundedSender<Message>,
+    /// Tell the client when the request is abandoned
+    pub cancel: bool,
+    pub responder: PendingResponder,
+    /// Woken by the client's progress reports
+    pub progress: Arc<Notify>,
+}
+
+impl PendingRequest {
+    /// The caller stopped waiting: let the client stop working on it.
+    fn abandon(self, request_id: &str) {
+        if !self.cancel {
+            return;
+        }
+        let cancel = ServerMessage::Cancel {
+            id: request_id.to_string(),
+        };
+        if let Ok(text) = serde_json::to_string(&cancel) {
+            let _ = self.tx.send(Message::Text(text.into()));
+        }
+    }
+}
+
+/// Receiving end of a pending request.
+pub struct PendingReply {
+    reply: oneshot::Receiver<RelayReply>,
+    progress: Arc<Notify>,
+}
+
+impl PendingReply {
+    /// Wait for the client's reply, or `None` after `idle` without a reply
+    /// or progress report. A dropped responder counts as a disconnect.
+    pub async fn wait(mut self, idle: Duration) -> Option<RelayReply> {
+        loop {
+            tokio::select! {
+                reply = &mut self.reply => return Some(reply.unwrap_or(RelayReply::Disconnected)),
+                _ = self.progress.notified() => {}
+                _ = tokio::time::sleep(idle) => return None,
+            }
+        }
+    }
+}
+
+/// Removes a pending request when dropped, so requests that time out or
+/// whose HTTP caller went away do not stay in `AppState.pending`. If the
+/// request was still unanswered, the client is sent a cancel.
+pub struct PendingGuard {
+    state: Arc<AppState>,
+    request_id: String,
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        // Usually already removed by the reply; only contended on timeouts
+        if let Ok(mut pending) = self.state.pending.try_write() {
+            if let Some(request) = pending.remove(&self.request_id) {
+                request.abandon(&self.request_id);
+            }
+            return;
+        }
+        let (state, request_id) = (self.state.clone(), std::mem::take(&mut self.request_id));
+        tokio::spawn(async move {
+            if let Some(request) = state.pending.write().await.remove(&request_id) {
+                request.abandon(&request_id);
+            }
+        });
+    }
+}
 
 pub struct AppState {
     pub clients: RwLock<HashMap<String, ConnectedClient>>,
-    pub pending: RwLock<HashMap<String, PendingResponder>>,
+    /// In-flight requests by request id (or upload id for upload chunk frames)
+    pub pending: RwLock<HashMap<String, PendingRequest>>,
+    /// Known devices (device ID -> stable client_id), persisted across restarts
+    pub devices: Mutex<DeviceRegistry>,
+    /// Resume tokens of connected and recently disconnected clients
+    pub resume_tokens: Mutex<ResumeTokens>,
     /// HTTP port (e.g. 17200)
     pub port: u16,
-    /// Bearer token for API authentication (auto-generated on startup)
-    pub api_token: String,
+    /// HTTPS port for WebDAV (e.g. 17201)
+    pub https_port: u16,
+    /// How long to wait for a client's reply to a relayed command
+    pub relay_timeout: Duration,
+    /// Interval between pings to each client
+    pub heartbeat_interval: Duration,
+    /// Missed heartbeats in a row before a client is evicted
+    pub heartbeat_misses: u32,
+    /// Origins allowed by the REST API's CORS layer
+    pub cors_origins: Vec<String>,
+    /// API tokens (Bearer auth), persisted across restarts
+    pub tokens: RwLock<TokenStore>,
     /// Allowed base directory for WebDAV mounts (e.g. ~/Public/mount)
     pub allowed_mount_base: String,
+    /// OS tools for /api/mount (None: mounting is unavailable on this host)
+    pub mount_backend: Option<Box<dyn MountBackend>>,
+    /// Shares mounted on this host, persisted and reconciled with the OS
+    /// mount table (see mount_registry.rs)
+    pub mounts: Mutex<MountRegistry>,
+    /// Per-mount WebDAV credentials created by /api/mount (client_id -> credential)
+    pub mount_credentials: RwLock<HashMap<String, MountCredential>>,
+    /// Client-to-client transfers by id (see transfers.rs)
+    pub transfers: RwLock<HashMap<String, TransferJob>>,
+    /// Secret for stateless WebDAV Digest nonces (regenerated on startup)
+    pub nonce_secret: String,
 }
 
 impl AppState {
-    pub fn new(port: u16, allowed_mount_base: String) -> Arc<Self> {
-        let token = uuid::Uuid::new_v4().to_string();
-        Arc::new(Self {
+    pub fn new(config: &AppConfig) -> std::io::Result<Arc<Self>> {
+        let data_dir = config.data_dir();
+        let mounts = MountRegistry::load(data_dir.join("mounts.json"))?;
+        Ok(Arc::new(Self {
             clients: RwLock::new(HashMap::new()),
             pending: RwLock::new(HashMap::new()),
-            port,
-            api_token: token,
-            allowed_mount_base,
-        })
+            devices: Mutex::new(DeviceRegistry::load(data_dir.join("devices.json"))?),
+            resume_tokens: Mutex::new(ResumeTokens::default()),
+            port: config.port,
+            https_port: config.https_port(),
+            relay_timeout: config.relay_timeout(),
+            heartbeat_interval: config.heartbeat_interval(),
+            heartbeat_misses: config.timeouts.heartbeat_misses,
+            cors_origins: config.cors_origins.clone(),
+            tokens: RwLock::new(TokenStore::load(config.tokens_file())?),
+            allowed_mount_base: config.allowed_mount_base.clone(),
+            mount_backend: mount_backend::select(&config.mount_backend),
+            mount_credentials: RwLock::new(mounts.credentials()),
+            mounts: Mutex::new(mounts),
+            transfers: RwLock::new(HashMap::new()),
+            nonce_secret: uuid::Uuid::new_v4().to_string(),
+        }))
+    }
+
+    /// Register a request sent to a client connection. The reply arrives on
+    /// the `PendingReply`; the entry is removed when the guard is dropped.
+    pub async fn register_pending(
+        self: &Arc<Self>,
+        request_id: &str,
+        link: &ClientLink,
+    ) -> (PendingGuard, PendingReply) {
+        let (responder, reply) = oneshot::channel();
+        let progress = Arc::new(Notify::new());
+        self.pending.write().await.insert(
+            request_id.to_string(),
+            PendingRequest {
+                tx: link.tx.clone(),
+                cancel: link.cancel,
+                responder,
+                progress: progress.clone(),
+            },
+        );
+        let guard = PendingGuard {
+            state: self.clone(),
+            request_id: request_id.to_string(),
+        };
+        (guard, PendingReply { reply, progress })
+    }
+
+    /// Resolve every request still waiting on the connection behind `tx`
+    /// with `RelayReply::Disconnected`, so callers fail now instead of
+    /// waiting for the relay timeout. Requests already sent over a newer
+    /// connection of the same client are left alone.
+    pub async fn fail_pending(&self, tx: &mpsc::UnboundedSender<Message>) -> usize {
+        let mut pending = self.pending.write().await;
+        let ids: Vec<String> = pending
+            .iter()
+            .filter(|(_, p)| p.tx.same_channel(tx))
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &ids {
+            if let Some(request) = pending.remove(id) {
+                let _ = request.responder.send(RelayReply::Disconnected);
+            }
+        }
+        ids.len()
     }
 }
@@ -6,13 +6,22 @@
 
 use rcgen::{CertificateParams, KeyPair, SanType};
 use rustls::ServerConfig;
-use rustls_pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
-use std::path::PathBuf;
+use rustls_pki_types::{
+    CertificateDer, PrivateKeyDer, PrivatePkcs1KeyDer, PrivatePkcs8KeyDer, PrivateSec1KeyDer,
+};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-/// Directory where self-signed cert/key are cached.
-fn cert_dir() -> PathBuf {
-    let dir = dirs_or_home().join(".ljc-certs");
+use crate::config::{expand_tilde, TlsConfig};
+use crate::secret_file;
+
+/// Directory where self-signed cert/key are cached (`tls.cert_dir`, default ~/.ljc-certs).
+fn cert_dir(tls: &TlsConfig) -> PathBuf {
+    let dir = match &tls.cert_dir {
+        Some(dir) => PathBuf::from(expand_tilde(dir)),
+        None => dirs_or_home().join(".ljc-certs"),
+    };
     let _ = std::fs::create_dir_all(&dir);
     // Restrict directory permissions to owner only (rwx------)
     #[cfg(unix)]
@@ -29,9 +38,18 @@ fn dirs_or_home() -> PathBuf {
         .unwrap_or_else(|_| PathBuf::from("/tmp"))
 }
 
-/// Generate (or load cached) self-signed TLS config for the given local IP.
-pub fn make_tls_config(local_ip: &str) -> anyhow::Result<Arc<ServerConfig>> {
-    let dir = cert_dir();
+/// Build the TLS config: the configured certificate (`tls.cert_path` /
+/// `tls.key_path`) if set, otherwise a generated (or cached) self-signed
+/// certificate for the given local IP.
+pub fn make_tls_config(local_ip: &str, tls: &TlsConfig) -> anyhow::Result<Arc<ServerConfig>> {
+    if let (Some(cert_path), Some(key_path)) = (&tls.cert_path, &tls.key_path) {
+        tracing::info!("Using TLS certificate {}", cert_path);
+        let cert_pem = std::fs::read_to_string(expand_tilde(cert_path))?;
+        let key_pem = std::fs::read_to_string(expand_tilde(key_path))?;
+        return server_config(&cert_pem, &key_pem);
+    }
+
+    let dir = cert_dir(tls);
     let cert_path = dir.join("cert.pem");
     let key_path = dir.join("key.pem");
 
@@ -49,27 +67,67 @@ pub fn make_tls_config(local_ip: &str) -> anyhow::Result<Arc<ServerConfig>> {
             std::fs::read_to_string(&key_path)?,
         )
     } else {
-        tracing::info!("Generating self-signed TLS certificate for {}", local_ip);
-        let (cert, key) = generate_self_signed(local_ip)?;
-        std::fs::write(&cert_path, &cert)?;
-        std::fs::write(&key_path, &key)?;
-        std::fs::write(&ip_marker_path, local_ip)?;
-        // Restrict private key file permissions (rw-------)
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
-            let _ = std::fs::set_permissions(&key_path, std::fs::Permissions::from_mode(0o600));
-        }
-        (cert, key)
+        write_self_signed(&dir, local_ip)?
     };
 
+    server_config(&cert_pem, &key_pem)
+}
+
+/// Generate a self-signed certificate into `dir`, replacing any cached one.
+fn write_self_signed(dir: &Path, local_ip: &str) -> anyhow::Result<(String, String)> {
+    tracing::info!("Generating self-signed TLS certificate for {}", local_ip);
+    let cert_path = dir.join("cert.pem");
+    let key_path = dir.join("key.pem");
+    let (cert, key) = generate_self_signed(local_ip)?;
+    std::fs::write(&cert_path, &cert)?;
+    secret_file::write(&key_path, &key)?;
+    std::fs::write(dir.join("ip.txt"), local_ip)?;
+    Ok((cert, key))
+}
+
+/// Replace the cached self-signed certificate. Returns the new certificate path.
+/// Fails when a custom certificate is configured (`tls.cert_path`).
+pub fn regenerate_self_signed(local_ip: &str, tls: &TlsConfig) -> anyhow::Result<PathBuf> {
+    if let Some(cert_path) = &tls.cert_path {
+        anyhow::bail!("a custom certificate is configured ({}); nothing to regenerate", cert_path);
+    }
+    let dir = cert_dir(tls);
+    write_self_signed(&dir, local_ip)?;
+    Ok(dir.join("cert.pem"))
+}
+
+/// Path of the certificate the server presents: the configured one, or the
+/// cached self-signed one.
+pub fn certificate_path(tls: &TlsConfig) -> PathBuf {
+    match &tls.cert_path {
+        Some(path) => PathBuf::from(expand_tilde(path)),
+        None => cert_dir(tls).join("cert.pem"),
+    }
+}
+
+/// SHA-256 fingerprint of the leaf certificate, as colon-separated hex
+/// (the format shown by macOS Keychain Access and browsers).
+pub fn certificate_fingerprint(tls: &TlsConfig) -> anyhow::Result<String> {
+    let path = certificate_path(tls);
+    let pem = std::fs::read_to_string(&path)
+        .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+    let chain = pem_to_cert_chain(&pem)?;
+    let digest = Sha256::digest(chain[0].as_ref());
+    Ok(digest
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":"))
+}
+
+fn server_config(cert_pem: &str, key_pem: &str) -> anyhow::Result<Arc<ServerConfig>> {
     // Parse PEM into DER
-    let cert_der = pem_to_cert_der(&cert_pem)?;
-    let key_der = pem_to_key_der(&key_pem)?;
+    let cert_chain = pem_to_cert_chain(cert_pem)?;
+    let key_der = pem_to_key_der(key_pem)?;
 
     let config = ServerConfig::builder()
         .with_no_client_auth()
-        .with_single_cert(vec![cert_der], key_der)?;
+        .with_single_cert(cert_chain, key_der)?;
 
     Ok(Arc::new(config))
 }
@@ -93,25 +151,48 @@ fn generate_self_signed(local_ip: &str) -> anyhow::Result<(String, String)> {
     Ok((cert_pem, key_pem))
 }
 
-fn pem_to_cert_der(pem: &str) -> anyhow::Result<CertificateDer<'static>> {
-    // Strip PEM headers and decode base64
-    let b64: String = pem
-        .lines()
-        .filter(|l| !l.starts_with("-----"))
-        .collect::<Vec<&str>>()
-        .join("");
-    let der = base64_decode_simple(&b64)?;
-    Ok(CertificateDer::from(der))
+/// Split PEM text into (label, base64 body) blocks.
+fn pem_blocks(pem: &str) -> Vec<(String, String)> {
+    let mut blocks = Vec::new();
+    let mut current: Option<(String, String)> = None;
+    for line in pem.lines().map(str::trim) {
+        if let Some(label) = line
+            .strip_prefix("-----BEGIN ")
+            .and_then(|l| l.strip_suffix("-----"))
+        {
+            current = Some((label.to_string(), String::new()));
+        } else if line.starts_with("-----END ") {
+            blocks.extend(current.take());
+        } else if let Some((_, body)) = current.as_mut() {
+            body.push_str(line);
+        }
+    }
+    blocks
+}
+
+fn pem_to_cert_chain(pem: &str) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let chain = pem_blocks(pem)
+        .into_iter()
+        .filter(|(label, _)| label == "CERTIFICATE")
+        .map(|(_, b64)| base64_decode_simple(&b64).map(CertificateDer::from))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if chain.is_empty() {
+        anyhow::bail!("no CERTIFICATE block found");
+    }
+    Ok(chain)
 }
 
 fn pem_to_key_der(pem: &str) -> anyhow::Result<PrivateKeyDer<'static>> {
-    let b64: String = pem
-        .lines()
-        .filter(|l| !l.starts_with("-----"))
-        .collect::<Vec<&str>>()
-        .join("");
-    let der = base64_decode_simple(&b64)?;
-    Ok(PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(der)))
+    for (label, b64) in pem_blocks(pem) {
+        let der = base64_decode_simple(&b64)?;
+        match label.as_str() {
+            "PRIVATE KEY" => return Ok(PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(der))),
+            "RSA PRIVATE KEY" => return Ok(PrivateKeyDer::Pkcs1(PrivatePkcs1KeyDer::from(der))),
+            "EC PRIVATE KEY" => return Ok(PrivateKeyDer::Sec1(PrivateSec1KeyDer::from(der))),
+            _ => {}
+        }
+    }
+    anyhow::bail!("no supported PRIVATE KEY block found")
 }
 
 fn base64_decode_simple(input: &str) -> anyhow::Result<Vec<u8>> {
@@ -0,0 +1,462 @@
+// SNOWCODE - ソフトウェア製品
+// (C) SNOWCODE
+// 開発者: 雪符しき
+// https://snowcode.jp
+// 問い合わせ: info@snowcode.jp
+
+use axum::extract::{Path, State};
+use axum::Extension;
+use axum::http::StatusCode;
+use axum::response::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::secret_file;
+use crate::state::AppState;
+
+/// Name of the full-access token created on first start. It is printed at
+/// startup and used to log in to the dashboard.
+pub const DEFAULT_TOKEN_NAME: &str = "default";
+
+/// Name of the register-only token embedded in the connect HTML.
+pub const CONNECT_TOKEN_NAME: &str = "connect";
+
+/// Permission carried by a token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+    /// Relay read-only commands (readdir, readFile, readChunk, stat) and
+    /// read-only WebDAV methods
+    #[serde(rename = "relay:read")]
+    RelayRead,
+    /// Relay commands that modify files, and modifying WebDAV methods
+    #[serde(rename = "relay:write")]
+    RelayWrite,
+    /// /api/mount, /api/unmount, /api/mounts
+    #[serde(rename = "mount")]
+    Mount,
+    /// Register as a Windows client over /ws
+    #[serde(rename = "register")]
+    Register,
+    /// Token management (/api/tokens)
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+impl Scope {
+    /// Every scope. Tokens created before scopes existed get all of them.
+    pub fn all() -> Vec<Scope> {
+        vec![
+            Scope::RelayRead,
+            Scope::RelayWrite,
+            Scope::Mount,
+            Scope::Register,
+            Scope::Admin,
+        ]
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::RelayRead => "relay:read",
+            Scope::RelayWrite => "relay:write",
+            Scope::Mount => "mount",
+            Scope::Register => "register",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    /// Stable identifier used by the admin endpoints (not a credential)
+    pub id: String,
+    pub name: String,
+    /// Bearer secret
+    pub token: String,
+    pub created_at: String,
+    #[serde(default = "Scope::all")]
+    pub scopes: Vec<Scope>,
+    /// Client IDs this token may access. None = all clients.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clients: Option<Vec<String>>,
+}
+
+/// What an authenticated request may do. Inserted into request extensions
+/// by the auth middleware.
+#[derive(Debug, Clone)]
+pub struct TokenGrant {
+    pub token_id: String,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub clients: Option<Vec<String>>,
+}
+
+impl TokenGrant {
+    pub fn has(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+
+    pub fn allows_client(&self, client_id: &str) -> bool {
+        self.clients
+            .as_ref()
+            .is_none_or(|ids| ids.iter().any(|id| id == client_id))
+    }
+
+    /// Scope check alone, as a handler error. For endpoints that are not
+    /// about one client (listings, filtered with `allows_client`).
+    pub fn require(&self, scope: Scope) -> Result<(), (StatusCode, Json<Value>)> {
+        if !self.has(scope) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(json!({"error": format!("Token lacks scope '{}'", scope.as_str())})),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Scope check plus client restriction, as a handler error.
+    pub fn check(
+        &self,
+        scope: Scope,
+        client_id: &str,
+    ) -> Result<(), (StatusCode, Json<Value>)> {
+        self.require(scope)?;
+        if !self.allows_client(client_id) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(json!({"error": "Token is not allowed to access this client"})),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl ApiToken {
+    fn generate(name: &str, scopes: Vec<Scope>, clients: Option<Vec<String>>) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: name.to_string(),
+            token: uuid::Uuid::new_v4().to_string(),
+            created_at: now(),
+            scopes,
+            clients,
+        }
+    }
+
+    pub fn grant(&self) -> TokenGrant {
+        TokenGrant {
+            token_id: self.id.clone(),
+            name: self.name.clone(),
+            scopes: self.scopes.clone(),
+            clients: self.clients.clone(),
+        }
+    }
+
+    /// Built-in tokens are recreated on startup, so they can be rotated but not revoked.
+    fn is_builtin(&self) -> bool {
+        self.name == DEFAULT_TOKEN_NAME || self.name == CONNECT_TOKEN_NAME
+    }
+
+    /// Can only register clients (what the connect page needs), for any client.
+    pub fn is_register_only(&self) -> bool {
+        self.scopes == [Scope::Register] && self.clients.is_none()
+    }
+
+    /// JSON returned right after creating or rotating, including the secret.
+    fn reveal(&self) -> Value {
+        json!({
+            "id": self.id,
+            "name": self.name,
+            "token": self.token,
+            "created_at": self.created_at,
+            "scopes": self.scopes,
+            "clients": self.clients,
+        })
+    }
+
+    /// JSON for listings: the secret is only shown as a short prefix.
+    fn summary(&self) -> Value {
+        json!({
+            "id": self.id,
+            "name": self.name,
+            "token_prefix": &self.token[..8.min(self.token.len())],
+            "created_at": self.created_at,
+            "scopes": self.scopes,
+            "clients": self.clients,
+        })
+    }
+}
+
+/// API tokens, persisted as JSON in the data directory so downloaded
+/// connect HTML files and dashboard logins survive server restarts.
+pub struct TokenStore {
+    path: PathBuf,
+    tokens: Vec<ApiToken>,
+}
+
+impl TokenStore {
+    /// Load tokens from `path`, creating the built-in tokens if they are
+    /// missing. An invalid file is kept aside (see `secret_file::read_json`);
+    /// an unreadable one is an error, so its tokens are never overwritten.
+    pub fn load(path: PathBuf) -> std::io::Result<Self> {
+        let tokens: Vec<ApiToken> = secret_file::read_json(&path)?.unwrap_or_default();
+        let mut store = Self { path, tokens };
+        let mut changed = false;
+        if store.default_token().is_none() {
+            store.tokens.push(ApiToken::generate(DEFAULT_TOKEN_NAME, Scope::all(), None));
+            changed = true;
+        }
+        match store.tokens.iter_mut().find(|t| t.name == CONNECT_TOKEN_NAME) {
+            None => {
+                store.tokens.push(ApiToken::generate(CONNECT_TOKEN_NAME, vec![Scope::Register], None));
+                changed = true;
+            }
+            // Embedded in a page anyone can download: never more than register
+            Some(token) if !token.is_register_only() => {
+                tracing::warn!("Connect token had extra scopes or client limits; reset to register only");
+                token.scopes = vec![Scope::Register];
+                token.clients = None;
+                changed = true;
+            }
+            Some(_) => {}
+        }
+        if changed {
+            store.save();
+        }
+        Ok(store)
+    }
+
+    fn save(&self) {
+        let json = match serde_json::to_string_pretty(&self.tokens) {
+            Ok(json) => json,
+            Err(e) => {
+                tracing::warn!("Failed to serialize tokens: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = secret_file::write(&self.path, json) {
+            tracing::warn!("Failed to save tokens {}: {}", self.path.display(), e);
+        }
+    }
+
+    pub fn default_token(&self) -> Option<&ApiToken> {
+        self.tokens.iter().find(|t| t.name == DEFAULT_TOKEN_NAME)
+    }
+
+    pub fn connect_token(&self) -> Option<&ApiToken> {
+        self.tokens.iter().find(|t| t.name == CONNECT_TOKEN_NAME)
+    }
+
+    /// Find the token matching a presented secret.
+    pub fn verify(&self, secret: &str) -> Option<&ApiToken> {
+        if secret.is_empty() {
+            return None;
+        }
+        self.tokens
+            .iter()
+            .find(|t| constant_time_eq(t.token.as_bytes(), secret.as_bytes()))
+    }
+
+    /// Secrets of tokens granting `scope` on `client_id`.
+    pub fn secrets_for(&self, scope: Scope, client_id: &str) -> Vec<String> {
+        self.tokens
+            .iter()
+            .filter(|t| {
+                let grant = t.grant();
+                grant.has(scope) && grant.allows_client(client_id)
+            })
+            .map(|t| t.token.clone())
+            .collect()
+    }
+
+    pub fn list(&self) -> &[ApiToken] {
+        &self.tokens
+    }
+
+    pub fn create(
+        &mut self,
+        name: &str,
+        scopes: Vec<Scope>,
+        clients: Option<Vec<String>>,
+    ) -> ApiToken {
+        let token = ApiToken::generate(name, scopes, clients);
+        self.tokens.push(token.clone());
+        self.save();
+        token
+    }
+
+    /// Replace the secret of a token, keeping its id and name.
+    pub fn rotate(&mut self, id: &str) -> Option<ApiToken> {
+        let token = self.tokens.iter_mut().find(|t| t.id == id)?;
+        token.token = uuid::Uuid::new_v4().to_string();
+        let rotated = token.clone();
+        self.save();
+        Some(rotated)
+    }
+
+    pub fn revoke(&mut self, id: &str) -> Option<ApiToken> {
+        let index = self.tokens.iter().position(|t| t.id == id)?;
+        let removed = self.tokens.remove(index);
+        self.save();
+        Some(removed)
+    }
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn now() -> String {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+        .to_string()
+}
+
+#[derive(Deserialize)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    #[serde(default = "Scope::all")]
+    pub scopes: Vec<Scope>,
+    #[serde(default)]
+    pub clients: Option<Vec<String>>,
+}
+
+/// GET /api/token
+/// Describes the token used for this request (the web UI hides actions it
+/// is not allowed to perform).
+pub async fn current_token(Extension(grant): Extension<TokenGrant>) -> Json<Value> {
+    Json(json!({
+        "id": grant.token_id,
+        "name": grant.name,
+        "scopes": grant.scopes,
+        "clients": grant.clients,
+    }))
+}
+
+/// GET /api/tokens
+pub async fn list_tokens(State(state): State<Arc<AppState>>) -> Json<Value> {
+    let tokens = state.tokens.read().await;
+    Json(json!(tokens.list().iter().map(ApiToken::summary).collect::<Vec<_>>()))
+}
+
+/// POST /api/tokens
+/// Body: {"name": "guest", "scopes": ["relay:read"], "clients": ["<client_id>"]}
+/// `scopes` defaults to all scopes, `clients` to all clients.
+/// The secret is only returned in this response.
+pub async fn create_token(
+    State(state): State<Arc<AppState>>,
+    Json(body): Json<CreateTokenRequest>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let name = body.name.trim();
+    if name.is_empty() || name.len() > 64 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "Token name must be 1-64 characters"})),
+        ));
+    }
+    let mut tokens = state.tokens.write().await;
+    if tokens.list().iter().any(|t| t.name == name) {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(json!({"error": format!("Token '{}' already exists", name)})),
+        ));
+    }
+    if body.scopes.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "Token must have at least one scope"})),
+        ));
+    }
+    if let Some(ids) = &body.clients {
+        if ids.iter().any(|id| uuid::Uuid::parse_str(id).is_err()) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "Invalid client_id format"})),
+            ));
+        }
+    }
+    let token = tokens.create(name, body.scopes, body.clients);
+    tracing::info!("API token created: {} ({})", token.name, token.id);
+    Ok(Json(token.reveal()))
+}
+
+/// POST /api/tokens/{id}/rotate
+/// Issues a new secret for the token; the old secret stops working immediately.
+pub async fn rotate_token(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let mut tokens = state.tokens.write().await;
+    match tokens.rotate(&id) {
+        Some(token) => {
+            tracing::info!("API token rotated: {} ({})", token.name, token.id);
+            Ok(Json(token.reveal()))
+        }
+        None => Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": "Token not found"})),
+        )),
+    }
+}
+
+/// DELETE /api/tokens/{id}
+/// Built-in tokens (default, connect) cannot be revoked (rotate them instead).
+pub async fn revoke_token(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let mut tokens = state.tokens.write().await;
+    if tokens.list().iter().any(|t| t.id == id && t.is_builtin()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "Built-in tokens cannot be revoked; rotate them instead"})),
+        ));
+    }
+    match tokens.revoke(&id) {
+        Some(token) => {
+            tracing::info!("API token revoked: {} ({})", token.name, token.id);
+            Ok(Json(json!({"ok": true, "id": token.id})))
+        }
+        None => Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": "Token not found"})),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grant(scopes: Vec<Scope>, clients: Option<Vec<String>>) -> TokenGrant {
+        TokenGrant {
+            token_id: "id".to_string(),
+            name: "test".to_string(),
+            scopes,
+            clients,
+        }
+    }
+
+    #[test]
+    fn register_only_grant_cannot_read() {
+        let register = grant(vec![Scope::Register], None);
+        // The listings (/api/clients, /api/transfers) and the relay commands
+        let (status, _) = register.require(Scope::RelayRead).unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = register.check(Scope::RelayRead, "client-a").unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn check_applies_client_restriction() {
+        let reader = grant(vec![Scope::RelayRead], Some(vec!["client-a".to_string()]));
+        assert!(reader.require(Scope::RelayRead).is_ok());
+        assert!(reader.check(Scope::RelayRead, "client-a").is_ok());
+        let (status, _) = reader.check(Scope::RelayRead, "client-b").unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(reader.check(Scope::RelayWrite, "client-a").is_err());
+    }
+}
@@ -0,0 +1,687 @@
+// SNOWCODE - ソフトウェア製品
+// (C) SNOWCODE
+// 開発者: 雪符しき
+// https://snowcode.jp
+// 問い合わせ: info@snowcode.jp
+
+// Server-side transfers between two clients: a file or tree is read from
+// the source client in chunks and uploaded to the target client, so moving
+// data between Windows PCs no longer goes through a Finder mount of each.
+//
+// Every file is verified: the source must be unchanged after it was read,
+// and the target is read back after commit and its SHA-256 compared with
+// that of the bytes sent. Verified files are remembered, so a failed or
+// cancelled transfer can be resumed and skips them. Resuming is per file:
+// a file that was only partly copied starts over from offset 0. Transfers
+// (and what was verified) live in memory only, so after a server restart
+// there is nothing to resume and the transfer has to be started again.
+//
+// Finished transfers are forgotten after a while (see `prune`): completed
+// ones after COMPLETED_TTL_SECS, failed or cancelled ones, which can still be
+// resumed, after RESUMABLE_TTL_SECS, and the oldest once more than
+// MAX_FINISHED are kept.
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::Json;
+use axum::Extension;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::task::AbortHandle;
+
+use crate::paths;
+use crate::protocol::{FileStat, Request};
+use crate::state::{unix_now, AppState};
+use crate::tokens::{Scope, TokenGrant};
+use crate::webdav_bridge::{RelayError, RelayFs};
+
+/// Bytes read from the source per `readChunk`, and uploaded per `uploadChunk`.
+const CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// How long a completed transfer stays listed.
+const COMPLETED_TTL_SECS: u64 = 60 * 60;
+
+/// How long a failed or cancelled transfer can still be resumed.
+const RESUMABLE_TTL_SECS: u64 = 24 * 60 * 60;
+
+/// Finished transfers kept at most, whatever their age.
+const MAX_FINISHED: usize = 100;
+
+/// A path on one client's shared folder.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Endpoint {
+    pub client_id: String,
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferMode {
+    Copy,
+    /// Copy, then delete the source once every file is verified
+    Move,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// A transfer as reported by the API.
+#[derive(Debug, Clone, Serialize)]
+pub struct Transfer {
+    pub id: String,
+    pub source: Endpoint,
+    /// Full destination path (not the folder to put the source into)
+    pub target: Endpoint,
+    pub mode: TransferMode,
+    pub status: TransferStatus,
+    pub files_total: u64,
+    pub files_done: u64,
+    pub bytes_total: u64,
+    pub bytes_done: u64,
+    /// File being transferred, relative to the source path
+    pub current: String,
+    pub error: Option<String>,
+    /// Unix seconds
+    pub created_at: String,
+    pub updated_at: String,
+    /// Relative paths already copied and verified, skipped on resume
+    #[serde(skip)]
+    verified: HashSet<String>,
+}
+
+/// A transfer and the task running it.
+pub struct TransferJob {
+    pub info: Transfer,
+    task: Option<AbortHandle>,
+}
+
+#[derive(Deserialize)]
+pub struct TransferRequest {
+    pub source: Endpoint,
+    pub target: Endpoint,
+    #[serde(default = "default_mode")]
+    pub mode: TransferMode,
+}
+
+fn default_mode() -> TransferMode {
+    TransferMode::Copy
+}
+
+fn error(status: StatusCode, message: impl Into<String>) -> (StatusCode, Json<Value>) {
+    (status, Json(json!({"error": message.into()})))
+}
+
+/// Scopes needed for a transfer: read the source (write it too when moving)
+/// and write the target.
+fn check_access(grant: &TokenGrant, transfer: &Transfer) -> Result<(), (StatusCode, Json<Value>)> {
+    let source_scope = match transfer.mode {
+        TransferMode::Copy => Scope::RelayRead,
+        TransferMode::Move => Scope::RelayWrite,
+    };
+    grant.check(source_scope, &transfer.source.client_id)?;
+    grant.check(Scope::RelayWrite, &transfer.target.client_id)
+}
+
+fn visible_to(grant: &TokenGrant, transfer: &Transfer) -> bool {
+    grant.allows_client(&transfer.source.client_id) && grant.allows_client(&transfer.target.client_id)
+}
+
+/// POST /api/transfers
+/// Body: {"source": {"client_id", "path"}, "target": {"client_id", "path"}, "mode": "copy" | "move"}
+/// Starts a transfer and returns it (202); progress via GET /api/transfers/{id}.
+pub async fn create_transfer(
+    State(state): State<Arc<AppState>>,
+    Extension(grant): Extension<TokenGrant>,
+    Json(body): Json<TransferRequest>,
+) -> Result<(StatusCode, Json<Transfer>), (StatusCode, Json<Value>)> {
+    let endpoint = |e: Endpoint| -> Result<Endpoint, (StatusCode, Json<Value>)> {
+        let path = paths::normalize(&e.path).map_err(|err| error(err.status(), err.to_string()))?;
+        Ok(Endpoint {
+            client_id: e.client_id,
+            path,
+        })
+    };
+    let source = endpoint(body.source)?;
+    let target = endpoint(body.target)?;
+    if source.path == "/" || target.path == "/" {
+        return Err(error(StatusCode::BAD_REQUEST, "Cannot transfer a shared folder root"));
+    }
+    if source.client_id == target.client_id && paths::is_within(&target.path, &source.path) {
+        return Err(error(StatusCode::BAD_REQUEST, "Target is inside the source"));
+    }
+
+    let now = unix_now().to_string();
+    let transfer = Transfer {
+        id: uuid::Uuid::new_v4().to_string(),
+        source,
+        target,
+        mode: body.mode,
+        status: TransferStatus::Running,
+        files_total: 0,
+        files_done: 0,
+        bytes_total: 0,
+        bytes_done: 0,
+        current: String::new(),
+        error: None,
+        created_at: now.clone(),
+        updated_at: now,
+        verified: HashSet::new(),
+    };
+    check_access(&grant, &transfer)?;
+
+    // Refuse up front what would fail right away
+    for endpoint in [&transfer.source, &transfer.target] {
+        if !state.clients.read().await.contains_key(&endpoint.client_id) {
+            return Err(error(
+                StatusCode::NOT_FOUND,
+                format!("Client not found: {}", endpoint.client_id),
+            ));
+        }
+    }
+    let source_fs = *RelayFs::new(state.clone(), transfer.source.client_id.clone());
+    let target_fs = *RelayFs::new(state.clone(), transfer.target.client_id.clone());
+    if let Err(e) = stat(&source_fs, &transfer.source.path).await {
+        return Err(error(e.status(), format!("Source: {}", e)));
+    }
+    match stat(&target_fs, &transfer.target.path).await {
+        Err(e) if e.is_not_found() => {}
+        Ok(_) => return Err(error(StatusCode::CONFLICT, "Target already exists")),
+        Err(e) => return Err(error(e.status(), format!("Target: {}", e))),
+    }
+
+    tracing::info!(
+        "Transfer {} started: {}:{} -> {}:{} ({:?})",
+        &transfer.id[..8],
+        &transfer.source.client_id[..8.min(transfer.source.client_id.len())],
+        transfer.source.path,
+        &transfer.target.client_id[..8.min(transfer.target.client_id.len())],
+        transfer.target.path,
+        transfer.mode
+    );
+    let id = transfer.id.clone();
+    {
+        let mut transfers = state.transfers.write().await;
+        prune(&mut transfers, unix_now());
+        transfers.insert(
+            id.clone(),
+            TransferJob {
+                info: transfer.clone(),
+                task: None,
+            },
+        );
+    }
+    spawn(&state, &id).await;
+    Ok((StatusCode::ACCEPTED, Json(transfer)))
+}
+
+/// GET /api/transfers
+/// Transfers between clients the token may access, newest first. Needs relay:read.
+pub async fn list_transfers(
+    State(state): State<Arc<AppState>>,
+    Extension(grant): Extension<TokenGrant>,
+) -> Result<Json<Vec<Transfer>>, (StatusCode, Json<Value>)> {
+    grant.require(Scope::RelayRead)?;
+    let mut transfers = state.transfers.write().await;
+    prune(&mut transfers, unix_now());
+    let mut list: Vec<Transfer> = transfers
+        .values()
+        .map(|job| &job.info)
+        .filter(|t| visible_to(&grant, t))
+        .cloned()
+        .collect();
+    list.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    Ok(Json(list))
+}
+
+/// GET /api/transfers/{id}
+/// Needs relay:read.
+pub async fn get_transfer(
+    State(state): State<Arc<AppState>>,
+    Extension(grant): Extension<TokenGrant>,
+    Path(id): Path<String>,
+) -> Result<Json<Transfer>, (StatusCode, Json<Value>)> {
+    grant.require(Scope::RelayRead)?;
+    let transfers = state.transfers.read().await;
+    match transfers.get(&id) {
+        Some(job) if visible_to(&grant, &job.info) => Ok(Json(job.info.clone())),
+        _ => Err(error(StatusCode::NOT_FOUND, "Transfer not found")),
+    }
+}
+
+/// POST /api/transfers/{id}/cancel
+/// Stops a running transfer. Files verified so far stay on the target.
+pub async fn cancel_transfer(
+    State(state): State<Arc<AppState>>,
+    Extension(grant): Extension<TokenGrant>,
+    Path(id): Path<String>,
+) -> Result<Json<Transfer>, (StatusCode, Json<Value>)> {
+    let mut transfers = state.transfers.write().await;
+    let job = match transfers.get_mut(&id) {
+        Some(job) if visible_to(&grant, &job.info) => job,
+        _ => return Err(error(StatusCode::NOT_FOUND, "Transfer not found")),
+    };
+    check_access(&grant, &job.info)?;
+    if job.info.status != TransferStatus::Running {
+        return Err(error(StatusCode::CONFLICT, "Transfer is not running"));
+    }
+    // Aborting drops the open upload, which aborts it on the target
+    if let Some(task) = job.task.take() {
+        task.abort();
+    }
+    job.info.status = TransferStatus::Cancelled;
+    job.info.updated_at = unix_now().to_string();
+    tracing::info!("Transfer {} cancelled", &id[..8]);
+    Ok(Json(job.info.clone()))
+}
+
+/// POST /api/transfers/{id}/resume
+/// Restarts a failed or cancelled transfer, skipping files already verified.
+pub async fn resume_transfer(
+    State(state): State<Arc<AppState>>,
+    Extension(grant): Extension<TokenGrant>,
+    Path(id): Path<String>,
+) -> Result<Json<Transfer>, (StatusCode, Json<Value>)> {
+    let info = {
+        let mut transfers = state.transfers.write().await;
+        let job = match transfers.get_mut(&id) {
+            Some(job) if visible_to(&grant, &job.info) => job,
+            _ => return Err(error(StatusCode::NOT_FOUND, "Transfer not found")),
+        };
+        check_access(&grant, &job.info)?;
+        if !matches!(job.info.status, TransferStatus::Failed | TransferStatus::Cancelled) {
+            return Err(error(StatusCode::CONFLICT, "Only failed or cancelled transfers can be resumed"));
+        }
+        job.info.status = TransferStatus::Running;
+        job.info.error = None;
+        job.info.updated_at = unix_now().to_string();
+        job.info.clone()
+    };
+    tracing::info!("Transfer {} resumed ({} files already verified)", &id[..8], info.verified.len());
+    spawn(&state, &id).await;
+    Ok(Json(info))
+}
+
+/// DELETE /api/transfers/{id}
+/// Forgets a transfer, cancelling it first if it is still running.
+pub async fn delete_transfer(
+    State(state): State<Arc<AppState>>,
+    Extension(grant): Extension<TokenGrant>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let mut transfers = state.transfers.write().await;
+    match transfers.get(&id) {
+        Some(job) if visible_to(&grant, &job.info) => check_access(&grant, &job.info)?,
+        _ => return Err(error(StatusCode::NOT_FOUND, "Transfer not found")),
+    }
+    if let Some(task) = transfers.remove(&id).and_then(|job| job.task) {
+        task.abort();
+    }
+    Ok(Json(json!({"ok": true})))
+}
+
+// --- Transfer task ---
+
+/// Run the transfer in the background and remember its task for cancel.
+async fn spawn(state: &Arc<AppState>, id: &str) {
+    let task = tokio::spawn(run(state.clone(), id.to_string()));
+    let mut transfers = state.transfers.write().await;
+    match transfers.get_mut(id) {
+        Some(job) => job.task = Some(task.abort_handle()),
+        None => task.abort(),
+    }
+}
+
+async fn run(state: Arc<AppState>, id: String) {
+    let Some(info) = state.transfers.read().await.get(&id).map(|job| job.info.clone()) else {
+        return;
+    };
+    let result = transfer(&state, &info).await;
+
+    let mut transfers = state.transfers.write().await;
+    let Some(job) = transfers.get_mut(&id) else {
+        return;
+    };
+    job.task = None;
+    job.info.updated_at = unix_now().to_string();
+    job.info.current.clear();
+    match result {
+        Ok(()) => {
+            job.info.status = TransferStatus::Completed;
+            tracing::info!(
+                "Transfer {} completed: {} files, {} bytes",
+                &id[..8],
+                job.info.files_done,
+                job.info.bytes_done
+            );
+        }
+        Err(e) => {
+            tracing::warn!("Transfer {} failed: {}", &id[..8], e);
+            job.info.status = TransferStatus::Failed;
+            job.info.error = Some(e);
+        }
+    }
+}
+
+/// Forget finished transfers that are past their TTL, then the oldest ones
+/// beyond MAX_FINISHED. Running transfers are always kept.
+fn prune(transfers: &mut HashMap<String, TransferJob>, now: u64) {
+    let finished_at = |job: &TransferJob| job.info.updated_at.parse::<u64>().unwrap_or(0);
+    transfers.retain(|_, job| {
+        let ttl = match job.info.status {
+            TransferStatus::Running => return true,
+            TransferStatus::Completed => COMPLETED_TTL_SECS,
+            TransferStatus::Failed | TransferStatus::Cancelled => RESUMABLE_TTL_SECS,
+        };
+        now.saturating_sub(finished_at(job)) < ttl
+    });
+    let mut finished: Vec<(u64, String)> = transfers
+        .iter()
+        .filter(|(_, job)| job.info.status != TransferStatus::Running)
+        .map(|(id, job)| (finished_at(job), id.clone()))
+        .collect();
+    if finished.len() > MAX_FINISHED {
+        finished.sort();
+        for (_, id) in &finished[..finished.len() - MAX_FINISHED] {
+            transfers.remove(id);
+        }
+    }
+}
+
+/// Apply `f` to the stored transfer.
+async fn update(state: &AppState, id: &str, f: impl FnOnce(&mut Transfer)) {
+    if let Some(job) = state.transfers.write().await.get_mut(id) {
+        f(&mut job.info);
+        job.info.updated_at = unix_now().to_string();
+    }
+}
+
+async fn transfer(state: &Arc<AppState>, info: &Transfer) -> Result<(), String> {
+    let source = *RelayFs::new(state.clone(), info.source.client_id.clone());
+    let target = *RelayFs::new(state.clone(), info.target.client_id.clone());
+    let failed = |side: &str, op: &str, path: &str, e: RelayError| format!("{} {} {}: {}", side, op, path, e);
+
+    // List what to copy: (path relative to the source, stat)
+    let root = stat(&source, &info.source.path)
+        .await
+        .map_err(|e| failed("source", "stat", &info.source.path, e))?;
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    if root.is_dir {
+        let mut pending = vec![String::new()];
+        while let Some(rel) = pending.pop() {
+            let path = join(&info.source.path, &rel);
+            let listing = source
+                .readdir(&path)
+                .await
+                .map_err(|e| failed("source", "readdir", &path, e))?;
+            dirs.push(rel.clone());
+            for entry in listing {
+                let child = join(&rel, &entry.name).trim_start_matches('/').to_string();
+                if entry.is_dir {
+                    pending.push(child);
+                } else {
+                    files.push((child, entry));
+                }
+            }
+        }
+    } else {
+        files.push((String::new(), root));
+    }
+
+    let bytes_total = files.iter().map(|(_, f)| f.size).sum();
+    let already: u64 = files
+        .iter()
+        .filter(|(rel, _)| info.verified.contains(rel))
+        .map(|(_, f)| f.size)
+        .sum();
+    let files_done = files.iter().filter(|(rel, _)| info.verified.contains(rel)).count() as u64;
+    update(state, &info.id, |t| {
+        t.files_total = files.len() as u64;
+        t.files_done = files_done;
+        t.bytes_total = bytes_total;
+        t.bytes_done = already;
+    })
+    .await;
+
+    for rel in &dirs {
+        let path = join(&info.target.path, rel);
+        target
+            .send_command(Request::Mkdir { path: path.clone() })
+            .await
+            .map_err(|e| failed("target", "mkdir", &path, e))?;
+    }
+
+    for (rel, file) in &files {
+        if info.verified.contains(rel) {
+            continue;
+        }
+        let from = join(&info.source.path, rel);
+        let to = join(&info.target.path, rel);
+        update(state, &info.id, |t| t.current = rel.clone()).await;
+        copy_file(state, &info.id, &source, &target, &from, &to, file).await?;
+        update(state, &info.id, |t| {
+            t.files_done += 1;
+            t.verified.insert(rel.clone());
+        })
+        .await;
+    }
+
+    if info.mode == TransferMode::Move {
+        source
+            .send_command(Request::Delete {
+                path: info.source.path.clone(),
+            })
+            .await
+            .map_err(|e| failed("source", "delete", &info.source.path, e))?;
+    }
+    Ok(())
+}
+
+/// Stream one file from `source` to `target` and verify it.
+async fn copy_file(
+    state: &AppState,
+    id: &str,
+    source: &RelayFs,
+    target: &RelayFs,
+    from: &str,
+    to: &str,
+    file: &FileStat,
+) -> Result<(), String> {
+    let upload = Upload::open(target, to).await.map_err(|e| format!("target upload {}: {}", to, e))?;
+    let mut sent = Sha256::new();
+    let mut offset = 0;
+    let mut seq = 0;
+    while offset < file.size {
+        let length = CHUNK_SIZE.min(file.size - offset);
+        let chunk = source
+            .read_chunk(from, offset, length)
+            .await
+            .map_err(|e| format!("source read {}: {}", from, e))?;
+        if chunk.is_empty() {
+            return Err(format!("source {} changed during the transfer", from));
+        }
+        sent.update(&chunk);
+        target
+            .upload_chunk(&upload.id, seq, &chunk)
+            .await
+            .map_err(|e| format!("target write {}: {}", to, e))?;
+        offset += chunk.len() as u64;
+        seq += 1;
+        let n = chunk.len() as u64;
+        update(state, id, |t| t.bytes_done += n).await;
+    }
+
+    // Verify: the source must not have changed while it was read, and the
+    // target must hold exactly the bytes that were sent
+    let after = stat(source, from)
+        .await
+        .map_err(|e| format!("source stat {}: {}", from, e))?;
+    if after.size != file.size || after.modified != file.modified {
+        return Err(format!("source {} changed during the transfer", from));
+    }
+    upload.commit().await.map_err(|e| format!("target commit {}: {}", to, e))?;
+    let written = stat(target, to)
+        .await
+        .map_err(|e| format!("target stat {}: {}", to, e))?;
+    if written.size != file.size {
+        return Err(format!(
+            "verification failed for {}: {} bytes on the target, expected {}",
+            to, written.size, file.size
+        ));
+    }
+    let stored = sha256(target, to, written.size)
+        .await
+        .map_err(|e| format!("target read back {}: {}", to, e))?;
+    if stored != sent.finalize().as_slice() {
+        return Err(format!("verification failed for {}: content on the target differs", to));
+    }
+    Ok(())
+}
+
+/// SHA-256 of a client's file, read in chunks.
+async fn sha256(fs: &RelayFs, path: &str, size: u64) -> Result<Vec<u8>, RelayError> {
+    let mut hasher = Sha256::new();
+    let mut offset = 0;
+    while offset < size {
+        let chunk = fs.read_chunk(path, offset, CHUNK_SIZE.min(size - offset)).await?;
+        if chunk.is_empty() {
+            break;
+        }
+        hasher.update(&chunk);
+        offset += chunk.len() as u64;
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+/// An upload session on the target, aborted if dropped before commit
+/// (transfer failed or its task was aborted).
+pub(crate) struct Upload {
+    fs: RelayFs,
+    pub(crate) id: String,
+    committed: bool,
+}
+
+impl Upload {
+    pub(crate) async fn open(fs: &RelayFs, path: &str) -> Result<Self, RelayError> {
+        let id = uuid::Uuid::new_v4().to_string();
+        fs.send_command(Request::UploadOpen {
+            upload_id: id.clone(),
+            path: path.to_string(),
+        })
+        .await?;
+        Ok(Self {
+            fs: fs.clone(),
+            id,
+            committed: false,
+        })
+    }
+
+    pub(crate) async fn commit(mut self) -> Result<(), RelayError> {
+        self.committed = true;
+        self.fs
+            .send_command(Request::UploadCommit {
+                upload_id: self.id.clone(),
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+impl Drop for Upload {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        let (fs, upload_id) = (self.fs.clone(), std::mem::take(&mut self.id));
+        tokio::spawn(async move {
+            let _ = fs.send_command(Request::UploadAbort { upload_id }).await;
+        });
+    }
+}
+
+async fn stat(fs: &RelayFs, path: &str) -> Result<FileStat, RelayError> {
+    fs.query(Request::Stat {
+        path: path.to_string(),
+    })
+    .await
+}
+
+fn join(base: &str, rel: &str) -> String {
+    if rel.is_empty() {
+        base.to_string()
+    } else {
+        format!("{}/{}", base.trim_end_matches('/'), rel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(status: TransferStatus, finished_at: u64) -> TransferJob {
+        let endpoint = Endpoint {
+            client_id: "client".to_string(),
+            path: "/a".to_string(),
+        };
+        TransferJob {
+            info: Transfer {
+                id: String::new(),
+                source: endpoint.clone(),
+                target: endpoint,
+                mode: TransferMode::Copy,
+                status,
+                files_total: 0,
+                files_done: 0,
+                bytes_total: 0,
+                bytes_done: 0,
+                current: String::new(),
+                error: None,
+                created_at: finished_at.to_string(),
+                updated_at: finished_at.to_string(),
+                verified: HashSet::new(),
+            },
+            task: None,
+        }
+    }
+
+    #[test]
+    fn prune_keeps_resumable_transfers_longer() {
+        let now = 1_000_000;
+        let hours = |h: u64| now - h * 60 * 60;
+        let mut transfers = HashMap::from([
+            ("running".to_string(), job(TransferStatus::Running, hours(48))),
+            ("completed-new".to_string(), job(TransferStatus::Completed, now - 60)),
+            ("completed-old".to_string(), job(TransferStatus::Completed, hours(2))),
+            ("failed".to_string(), job(TransferStatus::Failed, hours(2))),
+            ("cancelled-old".to_string(), job(TransferStatus::Cancelled, hours(25))),
+        ]);
+        prune(&mut transfers, now);
+        let mut kept: Vec<&str> = transfers.keys().map(|k| k.as_str()).collect();
+        kept.sort();
+        assert_eq!(kept, vec!["completed-new", "failed", "running"]);
+    }
+
+    #[test]
+    fn prune_caps_finished_transfers() {
+        let now = 1_000_000;
+        let mut transfers: HashMap<String, TransferJob> = (0..MAX_FINISHED as u64 + 5)
+            .map(|i| (format!("t{}", i), job(TransferStatus::Failed, now - 1000 + i)))
+            .collect();
+        transfers.insert("running".to_string(), job(TransferStatus::Running, 0));
+        prune(&mut transfers, now);
+        assert_eq!(transfers.len(), MAX_FINISHED + 1);
+        assert!(transfers.contains_key("running"));
+        // The oldest went first
+        assert!(!transfers.contains_key("t4"));
+        assert!(transfers.contains_key("t5"));
+    }
+}
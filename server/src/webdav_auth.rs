@@ -0,0 +1,380 @@
+// SNOWCODE - ソフトウェア製品
+// (C) SNOWCODE
+// 開発者: 雪符しき
+// https://snowcode.jp
+// 問い合わせ: info@snowcode.jp
+
+// WebDAV authentication: Basic (TLS or loopback only) and Digest.
+//
+// Digest nonces are stateless: they carry their issue time and are bound to
+// the address of the client they were issued to, and the server does not
+// track the `nc` counter. A captured Digest request can therefore still be
+// replayed, unchanged (same method and URI), from the same address until
+// its nonce expires, i.e. for at most NONCE_TTL_SECS. Use HTTPS where that
+// matters.
+
+use axum::body::Body;
+use axum::http::{header, HeaderMap, Method};
+use axum::response::Response;
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::state::AppState;
+use crate::tokens::{constant_time_eq, Scope};
+use crate::webdav_bridge::{base64_decode, url_decode};
+
+const REALM: &str = "SnowSync";
+
+/// Digest nonces older than this are answered with `stale=true`, so the
+/// client retries with a fresh nonce without asking the user again. Kept
+/// short because it is also the replay window.
+const NONCE_TTL_SECS: u64 = 5 * 60;
+
+/// Credentials generated by /api/mount for one client's mount point.
+/// Only valid for /webdav/<client_id>/ of that client.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MountCredential {
+    pub username: String,
+    pub password: String,
+}
+
+impl MountCredential {
+    pub fn generate(client_id: &str) -> Self {
+        Self {
+            username: format!("ljc-{}", &client_id[..8.min(client_id.len())]),
+            password: uuid::Uuid::new_v4().simple().to_string(),
+        }
+    }
+}
+
+pub enum AuthOutcome {
+    Authorized,
+    /// Missing or invalid credentials. `stale` marks a valid Digest response
+    /// computed with an expired nonce.
+    Rejected { stale: bool },
+}
+
+/// Check the Authorization header of a WebDAV request for `client_id`.
+///
+/// Accepted passwords are API tokens with the relay scope the method needs
+/// on that client (any user name) and the per-mount credential of that
+/// client. Basic is only accepted when `allow_basic` is set (TLS or
+/// loopback connections); Digest is accepted on any connection, with a
+/// nonce issued to `peer`.
+pub async fn authorize(
+    state: &AppState,
+    headers: &HeaderMap,
+    method: &Method,
+    uri: &str,
+    client_id: &str,
+    allow_basic: bool,
+    peer: Option<IpAddr>,
+) -> AuthOutcome {
+    let value = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+
+    let mount_cred = state.mount_credentials.read().await.get(client_id).cloned();
+    let scope = if is_read_only(method) {
+        Scope::RelayRead
+    } else {
+        Scope::RelayWrite
+    };
+    let api_tokens = state.tokens.read().await.secrets_for(scope, client_id);
+    let passwords_for = |username: &str| -> Vec<String> {
+        let mut list = api_tokens.clone();
+        if let Some(cred) = &mount_cred {
+            if cred.username == username {
+                list.push(cred.password.clone());
+            }
+        }
+        list
+    };
+
+    if let Some(encoded) = value.strip_prefix("Basic ") {
+        if !allow_basic {
+            return AuthOutcome::Rejected { stale: false };
+        }
+        let decoded = String::from_utf8(base64_decode(encoded.trim())).unwrap_or_default();
+        let Some((username, password)) = decoded.split_once(':') else {
+            return AuthOutcome::Rejected { stale: false };
+        };
+        if passwords_for(username)
+            .iter()
+            .any(|p| constant_time_eq(p.as_bytes(), password.as_bytes()))
+        {
+            return AuthOutcome::Authorized;
+        }
+    } else if let Some(params) = value.strip_prefix("Digest ") {
+        let nonce_key = nonce_key(&state.nonce_secret, peer);
+        return verify_digest(&nonce_key, params, method, uri, passwords_for);
+    }
+
+    AuthOutcome::Rejected { stale: false }
+}
+
+fn verify_digest(
+    nonce_key: &str,
+    params: &str,
+    method: &Method,
+    uri: &str,
+    passwords_for: impl Fn(&str) -> Vec<String>,
+) -> AuthOutcome {
+    let params = parse_auth_params(params);
+    let get = |k: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(k))
+            .map(|(_, v)| v.as_str())
+    };
+    let (Some(username), Some(nonce), Some(digest_uri), Some(response)) =
+        (get("username"), get("nonce"), get("uri"), get("response"))
+    else {
+        return AuthOutcome::Rejected { stale: false };
+    };
+    if get("realm") != Some(REALM) || url_decode(digest_uri) != url_decode(uri) {
+        return AuthOutcome::Rejected { stale: false };
+    }
+    let Some(nonce_fresh) = check_nonce(nonce_key, nonce) else {
+        return AuthOutcome::Rejected { stale: false };
+    };
+
+    let ha2 = md5_hex(&format!("{}:{}", method, digest_uri));
+    let matched = passwords_for(username).iter().any(|password| {
+        let ha1 = md5_hex(&format!("{}:{}:{}", username, REALM, password));
+        let expected = match (get("qop"), get("nc"), get("cnonce")) {
+            (Some(qop), Some(nc), Some(cnonce)) => {
+                md5_hex(&format!("{}:{}:{}:{}:{}:{}", ha1, nonce, nc, cnonce, qop, ha2))
+            }
+            // RFC 2069 compatibility (no qop)
+            _ => md5_hex(&format!("{}:{}:{}", ha1, nonce, ha2)),
+        };
+        constant_time_eq(expected.as_bytes(), response.as_bytes())
+    });
+
+    match (matched, nonce_fresh) {
+        (true, true) => AuthOutcome::Authorized,
+        (true, false) => AuthOutcome::Rejected { stale: true },
+        (false, _) => AuthOutcome::Rejected { stale: false },
+    }
+}
+
+/// WebDAV methods that do not modify the share.
+fn is_read_only(method: &Method) -> bool {
+    matches!(method.as_str(), "GET" | "HEAD" | "OPTIONS" | "PROPFIND")
+}
+
+/// 401 response with a Digest challenge for `peer`, plus Basic when it is
+/// allowed on this connection.
+pub fn challenge(state: &AppState, allow_basic: bool, stale: bool, peer: Option<IpAddr>) -> Response<Body> {
+    let digest = format!(
+        r#"Digest realm="{}", qop="auth", algorithm=MD5, nonce="{}"{}"#,
+        REALM,
+        make_nonce(&nonce_key(&state.nonce_secret, peer)),
+        if stale { ", stale=true" } else { "" }
+    );
+    let mut builder = Response::builder()
+        .status(401)
+        .header("DAV", "1, 2")
+        .header(header::WWW_AUTHENTICATE, digest);
+    if allow_basic {
+        builder = builder.header(header::WWW_AUTHENTICATE, format!(r#"Basic realm="{}""#, REALM));
+    }
+    builder.body(Body::from("Authentication required")).unwrap()
+}
+
+/// Key for the nonces of one client address, so a nonce only verifies for
+/// requests from the address it was issued to.
+fn nonce_key(secret: &str, peer: Option<IpAddr>) -> String {
+    match peer {
+        Some(ip) => format!("{}:{}", ip, secret),
+        None => secret.to_string(),
+    }
+}
+
+/// Nonce = "<unix time hex>.<md5(time:key)>", verifiable without server-side state.
+fn make_nonce(secret: &str) -> String {
+    let ts = now_secs();
+    format!("{:x}.{}", ts, md5_hex(&format!("{:x}:{}", ts, secret)))
+}
+
+/// Returns None for forged nonces, Some(false) for expired ones.
+fn check_nonce(secret: &str, nonce: &str) -> Option<bool> {
+    let (ts_hex, mac) = nonce.split_once('.')?;
+    if !constant_time_eq(md5_hex(&format!("{}:{}", ts_hex, secret)).as_bytes(), mac.as_bytes()) {
+        return None;
+    }
+    let ts = u64::from_str_radix(ts_hex, 16).ok()?;
+    Some(now_secs().saturating_sub(ts) < NONCE_TTL_SECS)
+}
+
+/// Parse `key=value, key="quoted, value"` auth parameters.
+fn parse_auth_params(s: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut rest = s.trim();
+    while !rest.is_empty() {
+        let Some((key, after)) = rest.split_once('=') else {
+            break;
+        };
+        let key = key.trim().trim_start_matches(',').trim().to_string();
+        let after = after.trim_start();
+        let (value, remaining) = if let Some(quoted) = after.strip_prefix('"') {
+            match quoted.find('"') {
+                Some(end) => (quoted[..end].to_string(), &quoted[end + 1..]),
+                None => (quoted.to_string(), ""),
+            }
+        } else {
+            match after.find(',') {
+                Some(end) => (after[..end].trim().to_string(), &after[end..]),
+                None => (after.trim().to_string(), ""),
+            }
+        };
+        params.push((key, value));
+        rest = remaining.trim_start().trim_start_matches(',').trim_start();
+    }
+    params
+}
+
+fn md5_hex(s: &str) -> String {
+    Md5::digest(s.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "nonce-secret";
+    const PASSWORD: &str = "mount-password";
+    const URI: &str = "/webdav/3eda982f-9953-4e51-a409-a92704640b61/a%20b.txt";
+
+    fn nonce_at(ts: u64) -> String {
+        format!("{:x}.{}", ts, md5_hex(&format!("{:x}:{}", ts, SECRET)))
+    }
+
+    /// Authorization params as a client computes them (RFC 2617, qop=auth).
+    fn client_params(user: &str, password: &str, method: &str, uri: &str, nonce: &str) -> String {
+        let ha1 = md5_hex(&format!("{}:{}:{}", user, REALM, password));
+        let ha2 = md5_hex(&format!("{}:{}", method, uri));
+        let response = md5_hex(&format!("{}:{}:00000001:abcdef:auth:{}", ha1, nonce, ha2));
+        format!(
+            r#"username="{}", realm="{}", nonce="{}", uri="{}", qop=auth, nc=00000001, cnonce="abcdef", response="{}""#,
+            user, REALM, nonce, uri, response
+        )
+    }
+
+    fn verify(params: &str, method: Method, uri: &str) -> AuthOutcome {
+        verify_digest(SECRET, params, &method, uri, |user| {
+            if user == "ljc-3eda982f" {
+                vec![PASSWORD.to_string()]
+            } else {
+                Vec::new()
+            }
+        })
+    }
+
+    fn authorized(outcome: AuthOutcome) -> bool {
+        matches!(outcome, AuthOutcome::Authorized)
+    }
+
+    #[test]
+    fn digest_accepts_valid_response() {
+        let params = client_params("ljc-3eda982f", PASSWORD, "PUT", URI, &make_nonce(SECRET));
+        assert!(authorized(verify(&params, Method::PUT, URI)));
+    }
+
+    #[test]
+    fn digest_accepts_rfc2069_response() {
+        let nonce = make_nonce(SECRET);
+        let ha1 = md5_hex(&format!("ljc-3eda982f:{}:{}", REALM, PASSWORD));
+        let ha2 = md5_hex(&format!("GET:{}", URI));
+        let response = md5_hex(&format!("{}:{}:{}", ha1, nonce, ha2));
+        let params = format!(
+            r#"username="ljc-3eda982f", realm="{}", nonce="{}", uri="{}", response="{}""#,
+            REALM, nonce, URI, response
+        );
+        assert!(authorized(verify(&params, Method::GET, URI)));
+    }
+
+    #[test]
+    fn digest_rejects_wrong_password_or_user() {
+        let nonce = make_nonce(SECRET);
+        let params = client_params("ljc-3eda982f", "wrong", "GET", URI, &nonce);
+        assert!(!authorized(verify(&params, Method::GET, URI)));
+        let params = client_params("someone", PASSWORD, "GET", URI, &nonce);
+        assert!(!authorized(verify(&params, Method::GET, URI)));
+    }
+
+    #[test]
+    fn digest_binds_method_and_uri() {
+        let params = client_params("ljc-3eda982f", PASSWORD, "GET", URI, &make_nonce(SECRET));
+        // Replayed for another method, or for another request URI
+        assert!(!authorized(verify(&params, Method::DELETE, URI)));
+        assert!(!authorized(verify(&params, Method::GET, "/webdav/other/")));
+    }
+
+    #[test]
+    fn digest_nonce_is_bound_to_client_address() {
+        let ip: IpAddr = "192.168.1.20".parse().unwrap();
+        let other: IpAddr = "192.168.1.30".parse().unwrap();
+        let nonce = make_nonce(&nonce_key(SECRET, Some(ip)));
+        let params = client_params("ljc-3eda982f", PASSWORD, "GET", URI, &nonce);
+        let from = |peer| {
+            let passwords = |_: &str| vec![PASSWORD.to_string()];
+            verify_digest(&nonce_key(SECRET, Some(peer)), &params, &Method::GET, URI, passwords)
+        };
+        assert!(authorized(from(ip)));
+        // Replayed from another machine
+        assert!(matches!(from(other), AuthOutcome::Rejected { stale: false }));
+    }
+
+    #[test]
+    fn digest_rejects_other_realm() {
+        let params = client_params("ljc-3eda982f", PASSWORD, "GET", URI, &make_nonce(SECRET))
+            .replace(REALM, "Other");
+        assert!(!authorized(verify(&params, Method::GET, URI)));
+    }
+
+    #[test]
+    fn digest_rejects_forged_nonce() {
+        let forged = format!("{:x}.{}", now_secs(), md5_hex("anything"));
+        let params = client_params("ljc-3eda982f", PASSWORD, "GET", URI, &forged);
+        assert!(matches!(verify(&params, Method::GET, URI), AuthOutcome::Rejected { stale: false }));
+    }
+
+    #[test]
+    fn digest_marks_expired_nonce_stale() {
+        let expired = nonce_at(now_secs() - NONCE_TTL_SECS - 1);
+        let params = client_params("ljc-3eda982f", PASSWORD, "GET", URI, &expired);
+        assert!(matches!(verify(&params, Method::GET, URI), AuthOutcome::Rejected { stale: true }));
+        // ...but only when the response itself is right
+        let params = client_params("ljc-3eda982f", "wrong", "GET", URI, &expired);
+        assert!(matches!(verify(&params, Method::GET, URI), AuthOutcome::Rejected { stale: false }));
+    }
+
+    #[test]
+    fn auth_params_with_quoted_commas() {
+        let params = parse_auth_params(r#"username="a, b", qop=auth, nc=00000001, uri="/x""#);
+        assert_eq!(
+            params,
+            vec![
+                ("username".to_string(), "a, b".to_string()),
+                ("qop".to_string(), "auth".to_string()),
+                ("nc".to_string(), "00000001".to_string()),
+                ("uri".to_string(), "/x".to_string()),
+            ]
+        );
+    }
+}
@@ -4,29 +4,40 @@
 // https://snowcode.jp
 // 問い合わせ: info@snowcode.jp
 
-use crate::state::AppState;
+use crate::auto_mount;
+use crate::frame::{self, FrameOp};
+use crate::paths;
+use crate::protocol::{
+    Capability, ChunkContent, Envelope, ErrorCode, FileStat, Request as RelayRequest,
+    Response as RelayResponse,
+};
+use crate::server::TlsConnection;
+use crate::state::{AppState, ClientLink, RelayReply};
+use crate::webdav_auth::{self, AuthOutcome};
 use axum::body::Body;
 use axum::extract::ws::Message;
-use axum::extract::Request;
+use axum::extract::{ConnectInfo, Request};
 use axum::response::Response;
 use bytes::Bytes;
 use dav_server::{
     davpath::DavPath,
     fakels::FakeLs,
+    memfs::MemFs,
     fs::{
         DavDirEntry, DavFile, DavFileSystem, DavMetaData, FsError, FsFuture, FsResult, FsStream,
         OpenOptions, ReadDirMeta,
     },
-    DavHandler,
+    DavHandler, DavMethodSet,
 };
 use futures_util::stream;
-use serde_json::{json, Value};
-use std::sync::Arc;
+use http::StatusCode;
+use serde::de::DeserializeOwned;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, SystemTime};
-use tokio::sync::oneshot;
 
 /// URL-decode a percent-encoded path string.
-fn url_decode(s: &str) -> String {
+pub(crate) fn url_decode(s: &str) -> String {
     let mut result = Vec::new();
     let bytes = s.as_bytes();
     let mut i = 0;
@@ -56,7 +67,8 @@ fn hex_val(b: u8) -> Option<u8> {
     }
 }
 
-/// Convert a DavPath to a clean, decoded path string for the Windows FS client.
+/// Convert a DavPath to a decoded path string for the Windows FS client.
+/// It is validated by `paths::normalize` when the request is sent.
 fn dav_path_to_string(path: &DavPath) -> String {
     let raw = url_decode(&path.as_url_string());
     // Ensure we have a leading /
@@ -103,7 +115,96 @@ fn parse_iso_to_systemtime(iso: &str) -> SystemTime {
 }
 
 fn is_leap(y: u64) -> bool {
-    (y % 4 == 0 && y % 100 != 0) || y % 400 == 0
+    (y.is_multiple_of(4) && !y.is_multiple_of(100)) || y.is_multiple_of(400)
+}
+
+/// Why a relayed request failed.
+#[derive(Debug, Clone)]
+pub(crate) enum RelayError {
+    /// The client answered with an error
+    Client { code: ErrorCode, message: String },
+    /// Refused before reaching the client (see paths::normalize)
+    Rejected { code: ErrorCode, message: String },
+    /// No client with this id is connected
+    NotConnected,
+    /// The client stopped responding or went away mid-request
+    Unavailable(&'static str),
+    /// No reply (or progress report) within `timeouts.relay_secs`
+    Timeout,
+    /// The reply did not match the request
+    InvalidReply,
+}
+
+impl RelayError {
+    /// HTTP status for this failure, on both the REST and WebDAV paths.
+    pub(crate) fn status(&self) -> StatusCode {
+        match self {
+            RelayError::Client { code, .. } | RelayError::Rejected { code, .. } => match code {
+                ErrorCode::NotFound => StatusCode::NOT_FOUND,
+                ErrorCode::PermissionDenied => StatusCode::FORBIDDEN,
+                ErrorCode::AlreadyExists | ErrorCode::NotEmpty | ErrorCode::TypeMismatch => {
+                    StatusCode::CONFLICT
+                }
+                ErrorCode::Busy => StatusCode::LOCKED,
+                ErrorCode::NoSpace => StatusCode::INSUFFICIENT_STORAGE,
+                ErrorCode::NameTooLong => StatusCode::URI_TOO_LONG,
+                ErrorCode::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+                ErrorCode::InvalidPath | ErrorCode::InvalidRequest => StatusCode::BAD_REQUEST,
+                ErrorCode::Unsupported => StatusCode::NOT_IMPLEMENTED,
+                ErrorCode::Other => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            RelayError::NotConnected => StatusCode::NOT_FOUND,
+            RelayError::Unavailable(_) | RelayError::InvalidReply => StatusCode::BAD_GATEWAY,
+            RelayError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    /// The client's error code, if the client answered.
+    pub(crate) fn code(&self) -> Option<ErrorCode> {
+        match self {
+            RelayError::Client { code, .. } | RelayError::Rejected { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn is_not_found(&self) -> bool {
+        self.code() == Some(ErrorCode::NotFound)
+    }
+}
+
+impl std::fmt::Display for RelayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RelayError::Client { message, .. } | RelayError::Rejected { message, .. } => f.write_str(message),
+            RelayError::NotConnected => f.write_str("Client not connected"),
+            RelayError::Unavailable(reason) => f.write_str(reason),
+            RelayError::Timeout => f.write_str("Request timed out"),
+            RelayError::InvalidReply => f.write_str("Invalid reply from client"),
+        }
+    }
+}
+
+/// The FsError dav-server acts on (e.g. `Exists` makes MKCOL answer 405).
+/// Failures without a matching variant become `GeneralFailure`, whose 500
+/// `webdav_handler` replaces with `RelayError::status`.
+impl From<RelayError> for FsError {
+    fn from(e: RelayError) -> Self {
+        match e {
+            RelayError::Client { code, .. } | RelayError::Rejected { code, .. } => match code {
+                ErrorCode::NotFound => FsError::NotFound,
+                ErrorCode::PermissionDenied => FsError::Forbidden,
+                ErrorCode::AlreadyExists | ErrorCode::NotEmpty => FsError::Exists,
+                ErrorCode::NoSpace => FsError::InsufficientStorage,
+                ErrorCode::NameTooLong => FsError::PathTooLong,
+                ErrorCode::TooLarge => FsError::TooLarge,
+                ErrorCode::Unsupported => FsError::NotImplemented,
+                _ => FsError::GeneralFailure,
+            },
+            RelayError::NotConnected => FsError::NotFound,
+            RelayError::Unavailable(_) => FsError::IsRemote,
+            RelayError::Timeout | RelayError::InvalidReply => FsError::GeneralFailure,
+        }
+    }
 }
 
 /// A virtual filesystem that proxies file operations to a connected Windows client via WebSocket.
@@ -111,80 +212,195 @@ fn is_leap(y: u64) -> bool {
 pub struct RelayFs {
     state: Arc<AppState>,
     client_id: String,
+    /// Status of the last failed request, for the response of a WebDAV
+    /// request that failed with `GeneralFailure`
+    last_failure: Arc<Mutex<Option<StatusCode>>>,
 }
 
 impl RelayFs {
     pub fn new(state: Arc<AppState>, client_id: String) -> Box<Self> {
-        Box::new(Self { state, client_id })
+        Box::new(Self {
+            state,
+            client_id,
+            last_failure: Arc::new(Mutex::new(None)),
+        })
     }
 
-    async fn send_command(&self, cmd: Value) -> Result<Value, FsError> {
-        let request_id = uuid::Uuid::new_v4().to_string();
-        let mut cmd = cmd;
-        let cmd_type = cmd.get("type").and_then(|v| v.as_str()).unwrap_or("?").to_string();
-        let cmd_path = cmd.get("path").and_then(|v| v.as_str()).unwrap_or("").to_string();
-        cmd["id"] = json!(request_id);
-        tracing::debug!("WebDAV relay: {} {} (id: {})", cmd_type, cmd_path, &request_id[..8]);
-
-        let tx = {
-            let clients = self.state.clients.read().await;
-            match clients.get(&self.client_id) {
-                Some(client) => client.tx.clone(),
-                None => return Err(FsError::NotFound),
-            }
-        };
+    /// Remember the status of a failure before handing it on.
+    fn failed(&self, e: RelayError) -> RelayError {
+        *self.last_failure.lock().unwrap() = Some(e.status());
+        e
+    }
 
-        let (resp_tx, resp_rx) = oneshot::channel();
-        {
-            let mut pending = self.state.pending.write().await;
-            pending.insert(request_id.clone(), resp_tx);
+    /// Look up the client's current connection.
+    async fn client_link(&self) -> Result<ClientLink, RelayError> {
+        let clients = self.state.clients.read().await;
+        match clients.get(&self.client_id) {
+            // Fail fast (502) instead of waiting for the relay timeout
+            Some(client) if client.health.is_stale() => Err(RelayError::Unavailable("Client is not responding")),
+            Some(client) => Ok(client.link()),
+            None => Err(RelayError::NotConnected),
         }
+    }
 
-        if tx
-            .send(Message::Text(cmd.to_string().into()))
-            .is_err()
-        {
-            let mut pending = self.state.pending.write().await;
-            pending.remove(&request_id);
-            return Err(FsError::GeneralFailure);
+    /// Whether the client negotiated `capability` at registration.
+    async fn supports(&self, capability: Capability) -> bool {
+        let clients = self.state.clients.read().await;
+        clients
+            .get(&self.client_id)
+            .is_some_and(|c| c.supports(capability))
+    }
+
+    /// Send `msg` to the client and wait for the reply routed to `request_id`.
+    /// Responses with `ok: false` become `RelayError::Client`.
+    async fn relay(
+        &self,
+        link: &ClientLink,
+        request_id: &str,
+        msg: Message,
+        cmd_type: &str,
+        cmd_path: &str,
+    ) -> Result<RelayReply, RelayError> {
+        // Removed from `pending` when dropped: on reply, timeout, or when
+        // dav-server drops this future because the HTTP request went away
+        let (_pending, reply) = self.state.register_pending(request_id, link).await;
+
+        if link.tx.send(msg).is_err() {
+            return Err(self.failed(RelayError::Unavailable("Failed to send to client")));
         }
 
-        match tokio::time::timeout(
-            tokio::time::Duration::from_secs(30),
-            resp_rx,
-        )
-        .await
-        {
-            Ok(Ok(response)) => {
-                if response.get("ok").and_then(|v| v.as_bool()).unwrap_or(false) {
-                    Ok(response)
+        match reply.wait(self.state.relay_timeout).await {
+            Some(RelayReply::Json(response)) if !response.ok => {
+                let code = response.error_code();
+                let message = response.error.unwrap_or_else(|| "Unknown error".to_string());
+                if code == ErrorCode::NotFound {
+                    // NotFound is expected — Finder probes for .DS_Store,
+                    // .Spotlight-V100, etc. on every mount. Log at debug level.
+                    tracing::debug!("Relay: not found: {} {}", cmd_type, cmd_path);
                 } else {
-                    let err = response
-                        .get("error")
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("Unknown error");
-                    if err.contains("not found") || err.contains("NotFound") {
-                        // NotFound is expected — Finder probes for .DS_Store,
-                        // .Spotlight-V100, etc. on every mount. Log at debug level.
-                        tracing::debug!("Relay: not found: {} {}", cmd_type, cmd_path);
-                        Err(FsError::NotFound)
-                    } else if err.contains("permission") || err.contains("Permission") {
-                        tracing::warn!("Relay: permission denied: {} {}", cmd_type, cmd_path);
-                        Err(FsError::Forbidden)
-                    } else {
-                        tracing::warn!("Relay error: {} (cmd: {} {})", err, cmd_type, cmd_path);
-                        Err(FsError::GeneralFailure)
-                    }
+                    tracing::warn!("Relay error: {} ({:?}, cmd: {} {})", message, code, cmd_type, cmd_path);
                 }
+                Err(self.failed(RelayError::Client { code, message }))
+            }
+            Some(RelayReply::Disconnected) => {
+                // Bad Gateway: the client went away mid-request
+                tracing::debug!("Relay: client disconnected: {} {}", cmd_type, cmd_path);
+                Err(self.failed(RelayError::Unavailable("Client disconnected")))
             }
-            Ok(Err(_)) => Err(FsError::GeneralFailure),
-            Err(_) => {
-                let mut pending = self.state.pending.write().await;
-                pending.remove(&request_id);
-                Err(FsError::GeneralFailure)
+            Some(reply) => Ok(reply),
+            None => {
+                tracing::warn!("Relay: timed out: {} {}", cmd_type, cmd_path);
+                Err(self.failed(RelayError::Timeout))
             }
         }
     }
+
+    /// Send a request and return the client's reply. Paths are normalized
+    /// first; invalid ones are refused without contacting the client.
+    async fn send_request(&self, mut request: RelayRequest) -> Result<RelayReply, RelayError> {
+        let cmd_type = request.name();
+        for path in request.paths_mut() {
+            *path = paths::normalize(path).map_err(|e| {
+                tracing::warn!("Relay: refused {} for {}: {}", cmd_type, self.client_id, e);
+                self.failed(e)
+            })?;
+        }
+        let request_id = uuid::Uuid::new_v4().to_string();
+        let cmd_path = request.target().to_string();
+        tracing::debug!("WebDAV relay: {} {} (id: {})", cmd_type, cmd_path, &request_id[..8]);
+        let envelope = Envelope {
+            id: request_id.clone(),
+            request,
+        };
+        let text = serde_json::to_string(&envelope).map_err(|_| self.failed(RelayError::InvalidReply))?;
+
+        let link = self.client_link().await?;
+        self.relay(&link, &request_id, Message::Text(text.into()), cmd_type, &cmd_path)
+            .await
+    }
+
+    /// Send a request that is answered with a JSON response.
+    pub(crate) async fn send_command(&self, request: RelayRequest) -> Result<RelayResponse, RelayError> {
+        match self.send_request(request).await? {
+            RelayReply::Json(response) => Ok(response),
+            RelayReply::Binary(_) | RelayReply::Disconnected => Err(self.failed(RelayError::InvalidReply)),
+        }
+    }
+
+    /// Send a request and decode the reply's `data`.
+    pub(crate) async fn query<T: DeserializeOwned>(&self, request: RelayRequest) -> Result<T, RelayError> {
+        let cmd_type = request.name();
+        let response = self.send_command(request).await?;
+        response.data_as().map_err(|e| {
+            tracing::warn!("Relay: invalid {} reply from {}: {}", cmd_type, self.client_id, e);
+            self.failed(RelayError::InvalidReply)
+        })
+    }
+}
+
+impl RelayFs {
+    /// List a directory. Entries whose name is not a single valid component
+    /// are dropped, so a client cannot slip `..` or `a/b` into the paths
+    /// built from the listing.
+    pub(crate) async fn readdir(&self, path: &str) -> Result<Vec<FileStat>, RelayError> {
+        let mut listing: Vec<FileStat> = self
+            .query(RelayRequest::Readdir {
+                path: path.to_string(),
+            })
+            .await?;
+        listing.retain(|entry| {
+            let valid = paths::is_valid_name(&entry.name);
+            if !valid {
+                tracing::warn!("Relay: dropped invalid name {:?} in {} from {}", entry.name, path, self.client_id);
+            }
+            valid
+        });
+        Ok(listing)
+    }
+
+    /// Fetch `length` bytes at `offset` of a file with a `readChunk` command.
+    /// Clients that support binary frames answer with raw bytes, others with base64 JSON.
+    pub(crate) async fn read_chunk(&self, path: &str, offset: u64, length: u64) -> Result<Bytes, RelayError> {
+        let binary = self.client_link().await?.binary_frames;
+        let reply = self
+            .send_request(RelayRequest::ReadChunk {
+                path: path.to_string(),
+                offset,
+                length,
+                binary,
+            })
+            .await?;
+        match reply {
+            RelayReply::Binary(data) => Ok(data),
+            RelayReply::Json(resp) => {
+                let chunk: ChunkContent = resp.data_as().map_err(|_| self.failed(RelayError::InvalidReply))?;
+                Ok(Bytes::from(base64_decode(&chunk.data)))
+            }
+            RelayReply::Disconnected => Err(self.failed(RelayError::Unavailable("Client disconnected"))),
+        }
+    }
+
+    /// Send one upload chunk. With binary frames the chunk travels as an
+    /// `UploadChunk` frame routed by the upload id; otherwise as base64 JSON.
+    pub(crate) async fn upload_chunk(&self, upload_id: &str, seq: u64, chunk: &[u8]) -> Result<(), RelayError> {
+        let link = self.client_link().await?;
+        if !link.binary_frames {
+            self.send_command(RelayRequest::UploadChunk {
+                upload_id: upload_id.to_string(),
+                seq,
+                data: base64_encode(chunk),
+            })
+            .await?;
+            return Ok(());
+        }
+
+        let data = frame::encode(FrameOp::UploadChunk, upload_id, seq as u32, chunk)
+            .ok_or_else(|| self.failed(RelayError::InvalidReply))?;
+        tracing::debug!("WebDAV relay: uploadChunk {} #{} ({} bytes)", &upload_id[..8], seq, chunk.len());
+        self.relay(&link, upload_id, Message::Binary(data), "uploadChunk", upload_id)
+            .await?;
+        Ok(())
+    }
 }
 
 impl DavFileSystem for RelayFs {
@@ -194,40 +410,37 @@ impl DavFileSystem for RelayFs {
 
         Box::pin(async move {
             if options.create || options.create_new || options.write {
-                // For write operations, return a writable file handle
-                Ok(Box::new(RelayFile::new_writable(
-                    fs.state.clone(),
-                    fs.client_id.clone(),
-                    path_str.clone(),
-                )) as Box<dyn DavFile>)
+                // Writes always replace the whole file through an upload
+                // session, so an append or an in-place update (PUT with
+                // Content-Range, PATCH) of an existing file is refused
+                // rather than truncating it.
+                if options.append {
+                    return Err(FsError::NotImplemented);
+                }
+                if !options.truncate && !options.create_new {
+                    let existing = fs
+                        .query::<FileStat>(RelayRequest::Stat {
+                            path: path_str.clone(),
+                        })
+                        .await
+                        .map_err(FsError::from);
+                    match existing {
+                        Ok(_) => return Err(FsError::NotImplemented),
+                        Err(FsError::NotFound) => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+                Ok(Box::new(RelayFile::new_writable(fs, path_str)) as Box<dyn DavFile>)
             } else {
-                // Read: fetch file content
-                let resp = fs
-                    .send_command(json!({
-                        "type": "readFile",
-                        "path": path_str,
-                    }))
+                // Read: only stat here. Content is fetched lazily in chunks
+                // by read_bytes, so large files never sit in memory whole.
+                let stat: FileStat = fs
+                    .query(RelayRequest::Stat {
+                        path: path_str.clone(),
+                    })
                     .await?;
 
-                let data = resp
-                    .get("data")
-                    .and_then(|d| d.get("data"))
-                    .and_then(|d| d.as_str())
-                    .unwrap_or("");
-
-                let bytes = base64_decode(data);
-                let size = resp
-                    .get("data")
-                    .and_then(|d| d.get("size"))
-                    .and_then(|d| d.as_u64())
-                    .unwrap_or(bytes.len() as u64);
-                let modified = resp
-                    .get("data")
-                    .and_then(|d| d.get("modified"))
-                    .and_then(|d| d.as_str())
-                    .unwrap_or("");
-
-                Ok(Box::new(RelayFile::new_readable(bytes, size, modified.to_string()))
+                Ok(Box::new(RelayFile::new_readable(fs, path_str, stat.size, stat.modified))
                     as Box<dyn DavFile>)
             }
         })
@@ -242,48 +455,18 @@ impl DavFileSystem for RelayFs {
         let fs = self.clone();
 
         Box::pin(async move {
-            let resp = fs
-                .send_command(json!({
-                    "type": "readdir",
-                    "path": path_str,
-                }))
-                .await?;
-
-            let entries: Vec<Box<dyn DavDirEntry>> = resp
-                .get("data")
-                .and_then(|d| d.as_array())
-                .map(|arr| {
-                    arr.iter()
-                        .map(|entry| {
-                            let name = entry
-                                .get("name")
-                                .and_then(|v| v.as_str())
-                                .unwrap_or("")
-                                .to_string();
-                            let is_dir = entry
-                                .get("is_dir")
-                                .and_then(|v| v.as_bool())
-                                .unwrap_or(false);
-                            let size = entry
-                                .get("size")
-                                .and_then(|v| v.as_u64())
-                                .unwrap_or(0);
-                            let modified = entry
-                                .get("modified")
-                                .and_then(|v| v.as_str())
-                                .unwrap_or("")
-                                .to_string();
-
-                            Box::new(RelayDirEntry {
-                                name,
-                                is_dir,
-                                size,
-                                modified,
-                            }) as Box<dyn DavDirEntry>
-                        })
-                        .collect()
+            let listing = fs.readdir(&path_str).await?;
+            let entries: Vec<Box<dyn DavDirEntry>> = listing
+                .into_iter()
+                .map(|entry| {
+                    Box::new(RelayDirEntry {
+                        name: entry.name,
+                        is_dir: entry.is_dir,
+                        size: entry.size,
+                        modified: entry.modified,
+                    }) as Box<dyn DavDirEntry>
                 })
-                .unwrap_or_default();
+                .collect();
 
             let stream = stream::iter(entries.into_iter().map(Ok));
             Ok(Box::pin(stream) as FsStream<Box<dyn DavDirEntry>>)
@@ -295,27 +478,12 @@ impl DavFileSystem for RelayFs {
         let fs = self.clone();
 
         Box::pin(async move {
-            let resp = fs
-                .send_command(json!({
-                    "type": "stat",
-                    "path": path_str,
-                }))
-                .await?;
-
-            let empty = json!({});
-            let data = resp.get("data").unwrap_or(&empty);
-            let is_dir = data.get("is_dir").and_then(|v| v.as_bool()).unwrap_or(false);
-            let size = data.get("size").and_then(|v| v.as_u64()).unwrap_or(0);
-            let modified = data
-                .get("modified")
-                .and_then(|v| v.as_str())
-                .unwrap_or("")
-                .to_string();
+            let stat: FileStat = fs.query(RelayRequest::Stat { path: path_str }).await?;
 
             Ok(Box::new(RelayMetaData {
-                is_dir,
-                size,
-                modified,
+                is_dir: stat.is_dir,
+                size: stat.size,
+                modified: stat.modified,
             }) as Box<dyn DavMetaData>)
         })
     }
@@ -324,11 +492,7 @@ impl DavFileSystem for RelayFs {
         let path_str = dav_path_to_string(path);
         let fs = self.clone();
         Box::pin(async move {
-            fs.send_command(json!({
-                "type": "mkdir",
-                "path": path_str,
-            }))
-            .await?;
+            fs.send_command(RelayRequest::Mkdir { path: path_str }).await?;
             Ok(())
         })
     }
@@ -337,11 +501,7 @@ impl DavFileSystem for RelayFs {
         let path_str = dav_path_to_string(path);
         let fs = self.clone();
         Box::pin(async move {
-            fs.send_command(json!({
-                "type": "delete",
-                "path": path_str,
-            }))
-            .await?;
+            fs.send_command(RelayRequest::Delete { path: path_str }).await?;
             Ok(())
         })
     }
@@ -350,11 +510,7 @@ impl DavFileSystem for RelayFs {
         let path_str = dav_path_to_string(path);
         let fs = self.clone();
         Box::pin(async move {
-            fs.send_command(json!({
-                "type": "delete",
-                "path": path_str,
-            }))
-            .await?;
+            fs.send_command(RelayRequest::Delete { path: path_str }).await?;
             Ok(())
         })
     }
@@ -364,33 +520,96 @@ impl DavFileSystem for RelayFs {
         let to_str = dav_path_to_string(to);
         let fs = self.clone();
         Box::pin(async move {
-            fs.send_command(json!({
-                "type": "rename",
-                "oldPath": from_str,
-                "newPath": to_str,
-            }))
-            .await?;
+            // Clients without DirRename (the connect page) cannot move
+            // directories; refuse up front instead of failing half-way
+            if !fs.supports(Capability::DirRename).await {
+                // dav-server passes collections with a trailing slash
+                let is_dir = from_str.ends_with('/')
+                    || fs
+                        .query::<FileStat>(RelayRequest::Stat { path: from_str.clone() })
+                        .await?
+                        .is_dir;
+                if is_dir {
+                    return Err(FsError::NotImplemented);
+                }
+            }
+            let rename = RelayRequest::Rename {
+                old_path: from_str,
+                new_path: to_str.clone(),
+            };
+            match fs.send_command(rename.clone()).await {
+                // Clients never replace the target of a rename. dav-server
+                // only gets here for an existing target with `Overwrite: T`
+                // (after deleting a collection itself), so replace the file.
+                Err(RelayError::Client {
+                    code: ErrorCode::AlreadyExists,
+                    ..
+                }) => {
+                    let target: FileStat = fs
+                        .query(RelayRequest::Stat { path: to_str.clone() })
+                        .await?;
+                    if target.is_dir {
+                        return Err(FsError::Exists);
+                    }
+                    fs.send_command(RelayRequest::Delete { path: to_str }).await?;
+                    fs.send_command(rename).await?;
+                }
+                result => {
+                    result?;
+                }
+            }
             Ok(())
         })
     }
 
-    fn copy<'a>(&'a self, _from: &'a DavPath, _to: &'a DavPath) -> FsFuture<'a, ()> {
-        Box::pin(async { Err(FsError::NotImplemented) })
+    fn copy<'a>(&'a self, from: &'a DavPath, to: &'a DavPath) -> FsFuture<'a, ()> {
+        // dav-server walks collections itself (create_dir + copy per file),
+        // so only single files arrive here; the bytes never leave the client
+        let from_str = dav_path_to_string(from);
+        let to_str = dav_path_to_string(to);
+        let fs = self.clone();
+        Box::pin(async move {
+            if !fs.supports(Capability::Copy).await {
+                return Err(FsError::NotImplemented);
+            }
+            fs.send_command(RelayRequest::Copy {
+                old_path: from_str,
+                new_path: to_str,
+            })
+            .await?;
+            Ok(())
+        })
     }
 }
 
 // --- RelayFile ---
 
+/// Size of each `uploadChunk` forwarded to the client while a PUT (or
+/// /api/upload) body arrives.
+pub(crate) const UPLOAD_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Maximum size of each `readChunk` request. dav-server reads GET bodies
+/// (and each `Range:` of a partial GET) in pieces of at most this size, and
+/// every read is fetched from the client on demand, so only the requested
+/// region is transferred and memory per open file stays bounded.
+const READ_CHUNK_SIZE: u64 = 1024 * 1024;
+
 struct RelayFile {
-    data: Vec<u8>,
-    pos: usize,
+    fs: RelayFs,
+    path: String,
+    pos: u64,
     size: u64,
     modified: String,
-    // For writable files
-    state: Option<Arc<AppState>>,
-    client_id: Option<String>,
-    path: Option<String>,
+    // For writable files: bytes not yet sent, and the open upload session
+    writable: bool,
     write_buf: Vec<u8>,
+    upload: Option<UploadSession>,
+}
+
+/// An upload session on the client (uploadOpen -> uploadChunk* -> uploadCommit).
+struct UploadSession {
+    id: String,
+    next_seq: u64,
 }
 
 impl std::fmt::Debug for RelayFile {
@@ -404,30 +623,79 @@ impl std::fmt::Debug for RelayFile {
 }
 
 impl RelayFile {
-    fn new_readable(data: Vec<u8>, size: u64, modified: String) -> Self {
+    fn new_readable(fs: RelayFs, path: String, size: u64, modified: String) -> Self {
         Self {
-            data,
+            fs,
+            path,
             pos: 0,
             size,
             modified,
-            state: None,
-            client_id: None,
-            path: None,
+            writable: false,
             write_buf: Vec::new(),
+            upload: None,
         }
     }
 
-    fn new_writable(state: Arc<AppState>, client_id: String, path: String) -> Self {
-        Self {
-            data: Vec::new(),
-            pos: 0,
-            size: 0,
-            modified: String::new(),
-            state: Some(state),
-            client_id: Some(client_id),
-            path: Some(path),
-            write_buf: Vec::new(),
+    fn new_writable(fs: RelayFs, path: String) -> Self {
+        let mut file = Self::new_readable(fs, path, 0, String::new());
+        file.writable = true;
+        file
+    }
+
+    /// Open the upload session on the client if it is not open yet.
+    async fn ensure_upload(&mut self) -> FsResult<()> {
+        if self.upload.is_some() {
+            return Ok(());
         }
+        let id = uuid::Uuid::new_v4().to_string();
+        self.fs
+            .send_command(RelayRequest::UploadOpen {
+                upload_id: id.clone(),
+                path: self.path.clone(),
+            })
+            .await?;
+        self.upload = Some(UploadSession { id, next_seq: 0 });
+        Ok(())
+    }
+
+    /// Forward buffered bytes to the client in UPLOAD_CHUNK_SIZE pieces.
+    /// Unless `all` is set, a partial trailing chunk stays buffered.
+    async fn send_upload_chunks(&mut self, all: bool) -> FsResult<()> {
+        while self.write_buf.len() >= UPLOAD_CHUNK_SIZE || (all && !self.write_buf.is_empty()) {
+            self.ensure_upload().await?;
+            let n = self.write_buf.len().min(UPLOAD_CHUNK_SIZE);
+            let chunk: Vec<u8> = self.write_buf.drain(..n).collect();
+            let session = self.upload.as_mut().expect("upload session is open");
+            let (upload_id, seq) = (session.id.clone(), session.next_seq);
+            session.next_seq += 1;
+            if let Err(e) = self.fs.upload_chunk(&upload_id, seq, &chunk).await {
+                self.abort_upload();
+                return Err(e.into());
+            }
+            self.size += n as u64;
+        }
+        Ok(())
+    }
+
+    /// Abort the open upload session (if any) without waiting for the client.
+    fn abort_upload(&mut self) {
+        if let Some(session) = self.upload.take() {
+            let fs = self.fs.clone();
+            tokio::spawn(async move {
+                let _ = fs
+                    .send_command(RelayRequest::UploadAbort {
+                        upload_id: session.id,
+                    })
+                    .await;
+            });
+        }
+    }
+}
+
+impl Drop for RelayFile {
+    fn drop(&mut self) {
+        // PUT was interrupted before flush committed the upload
+        self.abort_upload();
     }
 }
 
@@ -446,59 +714,66 @@ impl DavFile for RelayFile {
 
     fn write_bytes<'a>(&'a mut self, buf: Bytes) -> FsFuture<'a, ()> {
         self.write_buf.extend_from_slice(&buf);
-        Box::pin(async { Ok(()) })
+        Box::pin(self.send_upload_chunks(false))
     }
 
-    fn write_buf<'a>(&'a mut self, buf: Box<dyn bytes::Buf + Send>) -> FsFuture<'a, ()> {
-        let data = buf.chunk().to_vec();
-        self.write_buf.extend_from_slice(&data);
-        Box::pin(async { Ok(()) })
+    fn write_buf<'a>(&'a mut self, mut buf: Box<dyn bytes::Buf + Send>) -> FsFuture<'a, ()> {
+        while buf.has_remaining() {
+            let n = buf.chunk().len();
+            self.write_buf.extend_from_slice(buf.chunk());
+            buf.advance(n);
+        }
+        Box::pin(self.send_upload_chunks(false))
     }
 
     fn read_bytes<'a>(&'a mut self, count: usize) -> FsFuture<'a, Bytes> {
-        let end = (self.pos + count).min(self.data.len());
-        let chunk = Bytes::copy_from_slice(&self.data[self.pos..end]);
-        self.pos = end;
-        Box::pin(async move { Ok(chunk) })
+        Box::pin(async move {
+            if count == 0 || self.pos >= self.size {
+                return Ok(Bytes::new());
+            }
+            let length = (count as u64).min(READ_CHUNK_SIZE).min(self.size - self.pos);
+            // An empty chunk means the file was truncated on the client since stat
+            let chunk = self.fs.read_chunk(&self.path, self.pos, length).await?;
+            self.pos += chunk.len() as u64;
+            Ok(chunk)
+        })
     }
 
     fn seek<'a>(&'a mut self, pos: std::io::SeekFrom) -> FsFuture<'a, u64> {
+        if self.writable {
+            // The upload is sequential: only a no-op seek to the write end works
+            let end = self.size + self.write_buf.len() as u64;
+            let target = match pos {
+                std::io::SeekFrom::Start(n) => Some(n),
+                std::io::SeekFrom::End(n) | std::io::SeekFrom::Current(n) => end.checked_add_signed(n),
+            };
+            let result = if target == Some(end) { Ok(end) } else { Err(FsError::NotImplemented) };
+            return Box::pin(async move { result });
+        }
         let new_pos = match pos {
-            std::io::SeekFrom::Start(n) => n as usize,
-            std::io::SeekFrom::End(n) => (self.data.len() as i64 + n) as usize,
-            std::io::SeekFrom::Current(n) => (self.pos as i64 + n) as usize,
+            std::io::SeekFrom::Start(n) => n as i64,
+            std::io::SeekFrom::End(n) => self.size as i64 + n,
+            std::io::SeekFrom::Current(n) => self.pos as i64 + n,
         };
-        self.pos = new_pos.min(self.data.len());
-        let result = self.pos as u64;
+        self.pos = (new_pos.max(0) as u64).min(self.size);
+        let result = self.pos;
         Box::pin(async move { Ok(result) })
     }
 
     fn flush<'a>(&'a mut self) -> FsFuture<'a, ()> {
-        if self.write_buf.is_empty() {
-            return Box::pin(async { Ok(()) });
-        }
-
-        let state = self.state.clone();
-        let client_id = self.client_id.clone();
-        let path = self.path.clone();
-        let data = std::mem::take(&mut self.write_buf);
-
         Box::pin(async move {
-            if let (Some(state), Some(client_id), Some(path)) = (state, client_id, path) {
-                let relay = RelayFs {
-                    state,
-                    client_id,
-                };
-                let encoded = base64_encode(&data);
-                relay
-                    .send_command(json!({
-                        "type": "writeFile",
-                        "path": path,
-                        "data": encoded,
-                    }))
-                    .await
-                    .map_err(|_| FsError::GeneralFailure)?;
+            if !self.writable {
+                return Ok(());
             }
+            // Also opens the session for empty files so they get created
+            self.ensure_upload().await?;
+            self.send_upload_chunks(true).await?;
+            let session = self.upload.take().expect("upload session is open");
+            self.fs
+                .send_command(RelayRequest::UploadCommit {
+                    upload_id: session.id,
+                })
+                .await?;
             Ok(())
         })
     }
@@ -562,7 +837,7 @@ impl DavMetaData for RelayMetaData {
 
 // --- Base64 helpers ---
 
-fn base64_decode(s: &str) -> Vec<u8> {
+pub(crate) fn base64_decode(s: &str) -> Vec<u8> {
     // Simple base64 decode
     use std::collections::HashMap;
     let chars = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
@@ -619,12 +894,50 @@ fn base64_encode(data: &[u8]) -> String {
 
 // --- WebDAV handler construction ---
 
-pub fn create_webdav_handler(state: Arc<AppState>, client_id: &str) -> DavHandler {
+pub fn create_webdav_handler(fs: Box<RelayFs>, client_id: &str) -> DavHandler {
     let prefix = format!("/webdav/{}", client_id);
     DavHandler::builder()
-        .filesystem(RelayFs::new(state, client_id.to_string()))
+        .filesystem(fs)
         .locksystem(FakeLs::new())
         .strip_prefix(&prefix)
+        .read_buf_size(READ_CHUNK_SIZE as usize)
+        .build_handler()
+}
+
+/// Name and text of the note in the offline placeholder.
+const PLACEHOLDER_NOTE: (&str, &str) = (
+    "/OFFLINE.txt",
+    "このPCは現在オフラインです。再接続するとファイルが再び表示されます。\n\
+     This PC is offline. Its files will appear again when it reconnects.\n",
+);
+
+/// Read-only share shown for an auto-mounted client while it is offline
+/// (see auto_mount.rs): only a note, answered at once, so Finder does not
+/// hang on a PC that is gone.
+async fn create_placeholder_handler(client_id: &str) -> DavHandler {
+    let fs = MemFs::new();
+    let (name, text) = PLACEHOLDER_NOTE;
+    if let Ok(path) = DavPath::new(name) {
+        let options = OpenOptions {
+            read: false,
+            write: true,
+            append: false,
+            truncate: true,
+            create: true,
+            create_new: false,
+            size: None,
+            checksum: None,
+        };
+        if let Ok(mut file) = fs.open(&path, options).await {
+            let _ = file.write_bytes(Bytes::from_static(text.as_bytes())).await;
+            let _ = file.flush().await;
+        }
+    }
+    DavHandler::builder()
+        .filesystem(fs)
+        .locksystem(FakeLs::new())
+        .strip_prefix(format!("/webdav/{}", client_id))
+        .methods(DavMethodSet::WEBDAV_RO)
         .build_handler()
 }
 
@@ -636,7 +949,7 @@ pub async fn webdav_handler(state: Arc<AppState>, req: Request) -> Response<Body
 
     // Handle trailing slash removal from client_id
     let stripped = path.strip_prefix("/webdav/").unwrap_or(&path);
-    let (client_id, _sub_path) = stripped.split_once('/').unwrap_or((stripped, ""));
+    let (client_id, sub_path) = stripped.split_once('/').unwrap_or((stripped, ""));
     // Remove any trailing slash from client_id
     let client_id = client_id.trim_end_matches('/');
 
@@ -651,8 +964,8 @@ pub async fn webdav_handler(state: Arc<AppState>, req: Request) -> Response<Body
 
     // For OPTIONS requests, return DAV capabilities immediately
     // without checking if client exists. Finder sends OPTIONS first
-    // to discover DAV support — it must get DAV headers to proceed
-    // without showing an authentication dialog.
+    // to discover DAV support. OPTIONS exposes no data, so it is
+    // answered before authentication.
     if method == http::Method::OPTIONS {
         tracing::debug!("WebDAV OPTIONS -> returning DAV capabilities");
         return Response::builder()
@@ -665,22 +978,69 @@ pub async fn webdav_handler(state: Arc<AppState>, req: Request) -> Response<Body
             .unwrap();
     }
 
-    // Check client exists
-    {
-        let clients = state.clients.read().await;
-        if !clients.contains_key(client_id) {
-            tracing::warn!("WebDAV: client '{}' not connected", client_id);
-            return Response::builder()
-                .status(404)
-                .body(Body::from(format!("Client '{}' not connected", client_id)))
-                .unwrap();
+    // Authenticate before revealing whether the client exists. Basic sends
+    // the password in clear text, so it is only offered over TLS or from
+    // this Mac itself (loopback mounts); Digest works on any connection.
+    let tls = req.extensions().get::<TlsConnection>().is_some();
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let loopback = peer.is_some_and(|ip| ip.is_loopback());
+    let allow_basic = tls || loopback;
+    let uri = req
+        .uri()
+        .path_and_query()
+        .map(|pq| pq.as_str().to_string())
+        .unwrap_or_else(|| path.clone());
+    match webdav_auth::authorize(&state, req.headers(), &method, &uri, client_id, allow_basic, peer).await {
+        AuthOutcome::Authorized => {}
+        AuthOutcome::Rejected { stale } => {
+            tracing::debug!("WebDAV {} {} -> 401 (stale nonce: {})", method, path, stale);
+            return webdav_auth::challenge(&state, allow_basic, stale, peer);
         }
     }
 
-    let handler = create_webdav_handler(state.clone(), client_id);
+    // Refuse paths the relay would refuse anyway, before dav-server resolves
+    // `..` across the /webdav/{client_id} prefix
+    if let Err(e) = paths::normalize(&url_decode(sub_path)) {
+        tracing::warn!("WebDAV {} {} -> {}: {}", method, path, e.status(), e);
+        return Response::builder()
+            .status(e.status())
+            .body(Body::from(e.to_string()))
+            .unwrap();
+    }
+
+    // Check client exists. An auto-mounted client that is offline or not
+    // responding answers from the placeholder instead
+    let online = state
+        .clients
+        .read()
+        .await
+        .get(client_id)
+        .map(|c| !c.health.is_stale());
+    if online != Some(true) && auto_mount::shows_placeholder(&state, client_id).await {
+        tracing::debug!("WebDAV {} {} -> offline placeholder", method, path);
+        let handler = create_placeholder_handler(client_id).await;
+        let (mut parts, body) = req.into_parts();
+        parts.headers.remove(http::header::AUTHORIZATION);
+        let (resp_parts, dav_body) = handler.handle(Request::from_parts(parts, body)).await.into_parts();
+        return Response::from_parts(resp_parts, Body::new(dav_body));
+    }
+    if online.is_none() {
+        tracing::warn!("WebDAV: client '{}' not connected", client_id);
+        return Response::builder()
+            .status(404)
+            .body(Body::from(format!("Client '{}' not connected", client_id)))
+            .unwrap();
+    }
+
+    let fs = RelayFs::new(state.clone(), client_id.to_string());
+    let last_failure = fs.last_failure.clone();
+    let handler = create_webdav_handler(fs, client_id);
 
     // DavHandler with strip_prefix handles URI rewriting and correct href generation.
-    // We only need to strip the Authorization header to allow guest access.
+    // Credentials were verified above; dav-server does not need them.
     let (mut parts, body) = req.into_parts();
     parts.headers.remove(http::header::AUTHORIZATION);
     let req = Request::from_parts(parts, body);
@@ -689,25 +1049,19 @@ pub async fn webdav_handler(state: Arc<AppState>, req: Request) -> Response<Body
     let (resp_parts, dav_body) = resp.into_parts();
     let mut response = Response::from_parts(resp_parts, Body::new(dav_body));
 
-    // dav-server may return 500 for FsError::NotFound (e.g. PROPFIND on a
-    // non-existent path). Finder expects 404 in this case. Downgrade 500 to
-    // 404 when the underlying relay reported "not found" — this prevents
-    // tower_http from logging these expected misses as server errors.
-    if response.status() == http::StatusCode::INTERNAL_SERVER_ERROR {
-        tracing::debug!(
-            "WebDAV {} {} -> 500, downgrading to 404 (likely not-found relay)",
-            method, path
-        );
-        *response.status_mut() = http::StatusCode::NOT_FOUND;
+    // FsError has no variant for conflicts, locks or timeouts, so those
+    // reach dav-server as GeneralFailure (500). Answer with the status of
+    // the relay failure instead.
+    if response.status() == StatusCode::INTERNAL_SERVER_ERROR {
+        if let Some(status) = last_failure.lock().unwrap().take() {
+            tracing::debug!("WebDAV {} {} -> 500, answering {} from the relay failure", method, path, status);
+            *response.status_mut() = status;
+        }
     }
 
     // Add DAV header to all responses so Finder recognizes this as a WebDAV server
     response.headers_mut().insert("DAV", "1, 2".parse().unwrap());
 
-    // Ensure no WWW-Authenticate header is sent — this tells Finder
-    // that authentication is NOT required, so no password dialog appears.
-    response.headers_mut().remove(http::header::WWW_AUTHENTICATE);
-
     tracing::debug!("WebDAV response: {} {}", response.status(), path);
     response
 }
@@ -9,77 +9,170 @@ use futures_util::{SinkExt, StreamExt};
 use std::sync::Arc;
 use tokio::sync::mpsc;
 
-use crate::state::{AppState, ClientInfo, ConnectedClient};
+use crate::auto_mount;
+use crate::devices::{is_valid_device_token, DeviceLookup};
+use crate::frame::{self, FrameOp};
+use crate::protocol::{
+    negotiate, ClientMessage, Register, Registered, CAPABILITIES, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use crate::state::{AppState, ClientHealth, ClientInfo, ConnectedClient, RelayReply};
+use crate::tokens::Scope;
 
 pub async fn handle_ws(socket: WebSocket, state: Arc<AppState>) {
     let (mut ws_tx, mut ws_rx) = socket.split();
     let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
 
-    // Wait for the initial registration message from the Windows client
-    // Expected: {"type":"register","name":"PC Name","folderName":"My Folder"}
+    // Wait for the initial registration message (protocol::Register)
     let registration = match ws_rx.next().await {
-        Some(Ok(Message::Text(text))) => {
-            match serde_json::from_str::<serde_json::Value>(&text) {
-                Ok(v) => v,
-                Err(_) => {
-                    tracing::warn!("Invalid registration JSON");
-                    return;
-                }
+        Some(Ok(Message::Text(text))) => match serde_json::from_str::<Register>(&text) {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::warn!("Invalid registration message: {}", e);
+                return;
             }
-        }
+        },
         _ => {
             tracing::warn!("No registration message received");
             return;
         }
     };
+    if registration.protocol_version < MIN_PROTOCOL_VERSION {
+        tracing::warn!(
+            "WebSocket registration rejected: protocol version {} is older than {}",
+            registration.protocol_version,
+            MIN_PROTOCOL_VERSION
+        );
+        return;
+    }
 
     // Validate token from registration message
-    let token = registration["token"]
-        .as_str()
-        .unwrap_or("");
-    if token != state.api_token {
+    let grant = state
+        .tokens
+        .read()
+        .await
+        .verify(&registration.token)
+        .map(|t| t.grant());
+    let Some(grant) = grant else {
         tracing::warn!("WebSocket registration rejected: invalid token");
         return;
+    };
+    if !grant.has(Scope::Register) {
+        tracing::warn!("WebSocket registration rejected: token '{}' lacks register scope", grant.name);
+        return;
     }
 
-    let client_id = uuid::Uuid::new_v4().to_string();
-    let name = registration["name"]
-        .as_str()
-        .unwrap_or("Unknown PC")
-        .to_string();
-    let folder_name = registration["folderName"]
-        .as_str()
-        .unwrap_or("Unknown")
-        .to_string();
+    let name = match registration.name.as_str() {
+        "" => "Unknown PC".to_string(),
+        name => name.to_string(),
+    };
+    let folder_name = match registration.folder_name.as_str() {
+        "" => "Unknown".to_string(),
+        folder => folder.to_string(),
+    };
 
+    // Resolve a stable client_id from the device identity so reconnects keep
+    // the same /webdav/<client_id>/ URL. Pages without a device identity
+    // fall back to the resume token of their previous connection, or get a
+    // fresh ID.
+    let device_id = registration.device_id.as_str();
+    let device_key = registration.device_key.as_str();
+    let resume_token = registration.resume_token.as_str();
+    let (client_id, resumed) = if device_id.is_empty() {
+        let previous = match resume_token {
+            "" => None,
+            token => state.resume_tokens.lock().await.redeem(token),
+        };
+        match previous {
+            Some(id) => (id, true),
+            None => (uuid::Uuid::new_v4().to_string(), false),
+        }
+    } else {
+        if !is_valid_device_token(device_id) || !is_valid_device_token(device_key) {
+            tracing::warn!("WebSocket registration rejected: invalid device identity");
+            return;
+        }
+        let mut devices = state.devices.lock().await;
+        let (id, resumed) = match devices.resolve(device_id, device_key) {
+            DeviceLookup::Resumed(id) => (id, true),
+            DeviceLookup::New(id) => (id, false),
+            DeviceLookup::KeyMismatch => {
+                tracing::warn!("WebSocket registration rejected: device key mismatch ({})", device_id);
+                return;
+            }
+        };
+        // Checked before the device is recorded, so a client-restricted
+        // token cannot add devices to the registry
+        if !grant.allows_client(&id) {
+            tracing::warn!("WebSocket registration rejected: token '{}' not allowed for client {}", grant.name, id);
+            return;
+        }
+        devices.record(device_id, device_key, &id, &name, &folder_name, &chrono_now());
+        (id, resumed)
+    };
+
+    // Client-restricted register tokens only work for those (known) devices
+    if !grant.allows_client(&client_id) {
+        tracing::warn!("WebSocket registration rejected: token '{}' not allowed for client {}", grant.name, client_id);
+        return;
+    }
+
+    // Speak the older of the two versions, and only features both sides know
+    let protocol_version = registration.protocol_version.min(PROTOCOL_VERSION);
+    let capabilities = negotiate(&registration.offered_capabilities(), CAPABILITIES);
+
+    let connected_at = chrono_now();
     let info = ClientInfo {
         id: client_id.clone(),
         name: name.clone(),
         folder_name: folder_name.clone(),
-        connected_at: chrono_now(),
+        connected_at: connected_at.clone(),
+        protocol_version,
+        capabilities: capabilities.clone(),
+        last_seen: connected_at,
+        stale: false,
     };
+    let health = Arc::new(ClientHealth::new());
 
-    // Send client_id back to Windows
-    let ack = serde_json::json!({
-        "type": "registered",
-        "clientId": &client_id,
-    });
-    if tx.send(Message::Text(ack.to_string().into())).is_err() {
+    // Send client_id and the negotiated protocol back to Windows
+    let ack = Registered {
+        client_id: client_id.clone(),
+        resumed,
+        protocol_version,
+        capabilities,
+        resume_token: state.resume_tokens.lock().await.issue(&client_id),
+    };
+    let Ok(ack) = serde_json::to_string(&ack) else {
+        return;
+    };
+    if tx.send(Message::Text(ack.into())).is_err() {
         return;
     }
 
-    // Register client
+    // Register client. If the same device is still registered (e.g. the old
+    // socket has not noticed the network drop yet), the new connection
+    // replaces it and the old one is closed.
     {
         let mut clients = state.clients.write().await;
-        clients.insert(
+        if let Some(old) = clients.insert(
             client_id.clone(),
             ConnectedClient {
                 info,
                 tx: tx.clone(),
+                health: health.clone(),
             },
-        );
+        ) {
+            tracing::info!("Replacing previous connection for {}", client_id);
+            let _ = old.tx.send(Message::Close(None));
+        }
     }
-    tracing::info!("Client connected: {} ({}) - folder: {}", name, client_id, folder_name);
+    tracing::info!(
+        "Client connected: {} ({}) - folder: {}, protocol v{}",
+        name,
+        client_id,
+        folder_name,
+        protocol_version
+    );
+    tokio::spawn(auto_mount::on_registered(state.clone(), client_id.clone()));
 
     // Spawn task to forward messages from channel to WebSocket
     let send_task = tokio::spawn(async move {
@@ -90,18 +183,75 @@ pub async fn handle_ws(socket: WebSocket, state: Arc<AppState>) {
         }
     });
 
-    // Read messages from WebSocket (responses from Windows client)
-    while let Some(msg) = ws_rx.next().await {
+    // Read messages from WebSocket (responses from Windows client), and ping
+    // the client every heartbeat interval. Any message (including the pong)
+    // counts as a sign of life; a sleeping laptop or dropped network sends
+    // nothing, so it is evicted after `heartbeat_misses` silent intervals.
+    let mut heartbeat = tokio::time::interval(state.heartbeat_interval);
+    heartbeat.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    heartbeat.tick().await;
+    let mut seen_since_ping = true;
+    let mut evicted = false;
+    loop {
+        let msg = tokio::select! {
+            msg = ws_rx.next() => msg,
+            _ = heartbeat.tick() => {
+                if !seen_since_ping {
+                    let missed = health.miss();
+                    if missed >= state.heartbeat_misses {
+                        evicted = true;
+                        break;
+                    }
+                    tracing::debug!("Client {} missed heartbeat ({}/{})", client_id, missed, state.heartbeat_misses);
+                }
+                seen_since_ping = false;
+                let _ = tx.send(Message::Ping(Default::default()));
+                continue;
+            }
+        };
+        let Some(msg) = msg else {
+            break;
+        };
+        if msg.is_ok() {
+            health.seen();
+            seen_since_ping = true;
+        }
         match msg {
             Ok(Message::Text(text)) => {
-                if let Ok(value) = serde_json::from_str::<serde_json::Value>(&text) {
-                    // Route response to pending request
-                    if let Some(id) = value.get("id").and_then(|v| v.as_str()) {
+                // Route response to pending request
+                match serde_json::from_str::<ClientMessage>(&text) {
+                    Ok(ClientMessage::Response(response)) => {
                         let mut pending = state.pending.write().await;
-                        if let Some(responder) = pending.remove(id) {
-                            let _ = responder.send(value);
+                        if let Some(request) = pending.remove(&response.id) {
+                            let _ = request.responder.send(RelayReply::Json(response));
                         }
                     }
+                    Ok(ClientMessage::Progress(progress)) => {
+                        tracing::debug!(
+                            "Progress {}: {}/{} {}",
+                            &progress.id[..8.min(progress.id.len())],
+                            progress.done,
+                            progress.total,
+                            progress.current
+                        );
+                        if let Some(request) = state.pending.read().await.get(&progress.id) {
+                            request.progress.notify_one();
+                        }
+                    }
+                    Err(e) => tracing::debug!("Ignoring invalid message from {}: {}", client_id, e),
+                }
+            }
+            Ok(Message::Binary(data)) => {
+                // Binary frame carrying file bytes (readChunk response)
+                match frame::decode(data) {
+                    Some(f) if f.op == FrameOp::ChunkData => {
+                        tracing::debug!("Binary chunk {} #{} ({} bytes)", &f.id[..8], f.seq, f.payload.len());
+                        let mut pending = state.pending.write().await;
+                        if let Some(request) = pending.remove(&f.id) {
+                            let _ = request.responder.send(RelayReply::Binary(f.payload));
+                        }
+                    }
+                    _ => tracing::debug!("Ignoring invalid binary frame from {}", client_id),
                 }
             }
             Ok(Message::Close(_)) => break,
@@ -113,13 +263,31 @@ pub async fn handle_ws(socket: WebSocket, state: Arc<AppState>) {
         }
     }
 
-    // Cleanup
+    // Cleanup (only if this connection was not replaced by a reconnect)
     send_task.abort();
-    {
-        let mut clients = state.clients.write().await;
-        clients.remove(&client_id);
+    if evicted {
+        tracing::warn!("Client {} ({}) stopped answering heartbeats; evicted", name, client_id);
     }
+    // Whatever was sent over this connection will never be answered
+    let failed = state.fail_pending(&tx).await;
+    if failed > 0 {
+        tracing::info!("Failed {} pending requests of {}", failed, client_id);
+    }
+    let removed = {
+        let mut clients = state.clients.write().await;
+        let current = clients
+            .get(&client_id)
+            .is_some_and(|c| c.tx.same_channel(&tx));
+        if current {
+            clients.remove(&client_id);
+            state.resume_tokens.lock().await.release(&client_id);
+        }
+        current
+    };
     tracing::info!("Client disconnected: {} ({})", name, client_id);
+    if removed {
+        tokio::spawn(auto_mount::on_disconnected(state.clone(), client_id));
+    }
 }
 
 fn chrono_now() -> String {
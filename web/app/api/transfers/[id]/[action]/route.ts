@@ -0,0 +1,35 @@
+// SNOWCODE - ソフトウェア製品
+// (C) SNOWCODE
+// 開発者: 雪符しき
+// https://snowcode.jp
+// 問い合わせ: info@snowcode.jp
+
+import { NextRequest, NextResponse } from "next/server";
+
+const RUST_SERVER = process.env.RUST_SERVER_URL ?? "http://localhost:17200";
+
+const ACTIONS = ["cancel", "resume"];
+
+export async function POST(
+  request: NextRequest,
+  { params }: { params: Promise<{ id: string; action: string }> }
+) {
+  const authHeader = request.headers.get("Authorization") || "";
+  const { id, action } = await params;
+  if (!ACTIONS.includes(action)) {
+    return NextResponse.json({ error: "Unknown action" }, { status: 404 });
+  }
+  try {
+    const res = await fetch(`${RUST_SERVER}/api/transfers/${id}/${action}`, {
+      method: "POST",
+      headers: { Authorization: authHeader },
+    });
+    const data = await res.json();
+    return NextResponse.json(data, { status: res.status });
+  } catch {
+    return NextResponse.json(
+      { error: "Failed to connect to server" },
+      { status: 502 }
+    );
+  }
+}
@@ -0,0 +1,39 @@
+// SNOWCODE - ソフトウェア製品
+// (C) SNOWCODE
+// 開発者: 雪符しき
+// https://snowcode.jp
+// 問い合わせ: info@snowcode.jp
+
+import { NextRequest, NextResponse } from "next/server";
+
+const RUST_SERVER = process.env.RUST_SERVER_URL ?? "http://localhost:17200";
+
+export async function POST(
+  request: NextRequest,
+  { params }: { params: Promise<{ clientId: string }> }
+) {
+  const authHeader = request.headers.get("Authorization") || "";
+  const { clientId } = await params;
+  const path = request.nextUrl.searchParams.get("path") ?? "";
+
+  try {
+    // Stream the file through instead of buffering it
+    const res = await fetch(
+      `${RUST_SERVER}/api/upload/${clientId}?path=${encodeURIComponent(path)}`,
+      {
+        method: "POST",
+        headers: { "Content-Type": "application/octet-stream", Authorization: authHeader },
+        body: request.body,
+        duplex: "half",
+      } as RequestInit
+    );
+
+    const data = await res.json();
+    return NextResponse.json(data, { status: res.status });
+  } catch {
+    return NextResponse.json(
+      { error: "Failed to connect to relay server" },
+      { status: 502 }
+    );
+  }
+}
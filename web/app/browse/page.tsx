@@ -9,6 +9,7 @@
 import { Suspense, useEffect, useState } from "react";
 import { useSearchParams } from "next/navigation";
 import { RemoteBrowser } from "@/components/RemoteBrowser";
+import { TransferList } from "@/components/TransferList";
 import type { ConnectedClient } from "@/lib/types";
 import { FontAwesomeIcon } from "@fortawesome/react-fontawesome";
 import { faFolderOpen, faSpinner, faSnowflake } from "@fortawesome/free-solid-svg-icons";
@@ -73,7 +74,7 @@ function BrowseContent() {
       </div>
 
       {selected ? (
-        <RemoteBrowser key={selected} clientId={selected} />
+        <RemoteBrowser key={selected} clientId={selected} clients={clients} />
       ) : (
         <div className="card" style={{ textAlign: "center", padding: "40px 25px" }}>
           <FontAwesomeIcon
@@ -85,6 +86,8 @@ function BrowseContent() {
           </p>
         </div>
       )}
+
+      <TransferList clients={clients} />
     </div>
   );
 }
@@ -7,12 +7,15 @@
 "use client";
 
 import { createContext, useContext, useState, useEffect, useCallback } from "react";
+import type { TokenScope } from "@/lib/types";
 
 interface AuthContextType {
   token: string;
   setToken: (token: string) => void;
   isAuthenticated: boolean;
   authHeaders: () => Record<string, string>;
+  /** Whether the current token has the given scope */
+  can: (scope: TokenScope) => boolean;
 }
 
 const AuthContext = createContext<AuthContextType>({
@@ -20,6 +23,7 @@ const AuthContext = createContext<AuthContextType>({
   setToken: () => {},
   isAuthenticated: false,
   authHeaders: () => ({}),
+  can: () => false,
 });
 
 export function useAuth() {
@@ -52,6 +56,21 @@ export function AuthProvider({ children }: { children: React.ReactNode }) {
     return { Authorization: `Bearer ${token}` };
   }, [token]);
 
+  // Scopes of the current token, used to hide actions it cannot perform
+  const [scopes, setScopes] = useState<TokenScope[]>([]);
+  useEffect(() => {
+    if (!token) {
+      setScopes([]);
+      return;
+    }
+    fetch("/api/token", { headers: { Authorization: `Bearer ${token}` } })
+      .then((res) => (res.ok ? res.json() : { scopes: [] }))
+      .then((data) => setScopes(data.scopes ?? []))
+      .catch(() => setScopes([]));
+  }, [token]);
+
+  const can = useCallback((scope: TokenScope) => scopes.includes(scope), [scopes]);
+
   if (!ready) return null;
 
   if (!token) {
@@ -59,7 +78,7 @@ export function AuthProvider({ children }: { children: React.ReactNode }) {
   }
 
   return (
-    <AuthContext.Provider value={{ token, setToken, isAuthenticated: !!token, authHeaders }}>
+    <AuthContext.Provider value={{ token, setToken, isAuthenticated: !!token, authHeaders, can }}>
       {children}
     </AuthContext.Provider>
   );
@@ -7,7 +7,7 @@
 "use client";
 
 import { useEffect, useState, useCallback } from "react";
-import type { ConnectedClient } from "@/lib/types";
+import type { ConnectedClient, MountPolicy, MountRecord, OfflineAction } from "@/lib/types";
 import Link from "next/link";
 import { FontAwesomeIcon } from "@fortawesome/react-fontawesome";
 import {
@@ -22,20 +22,16 @@ import {
 import { useToast } from "@/components/Toast";
 import { useAuth } from "@/components/AuthProvider";
 
-interface MountInfo {
-  url: string;
-  mount_point: string;
-  raw: string;
-}
-
 export function ClientList() {
   const [clients, setClients] = useState<ConnectedClient[]>([]);
   const [ip, setIp] = useState("...");
   const [mountPath, setMountPath] = useState("~/Public/mount");
   const [mounting, setMounting] = useState<Record<string, boolean>>({});
-  const [mounts, setMounts] = useState<MountInfo[]>([]);
+  const [mounts, setMounts] = useState<MountRecord[]>([]);
+  const [policies, setPolicies] = useState<MountPolicy[]>([]);
   const { showToast } = useToast();
-  const { authHeaders } = useAuth();
+  const { authHeaders, can } = useAuth();
+  const canMount = can("mount");
 
   const fetchClients = useCallback(async () => {
     const res = await fetch("/api/clients", { headers: authHeaders() });
@@ -43,33 +39,42 @@ export function ClientList() {
   }, []);
 
   const fetchMounts = useCallback(async () => {
+    if (!canMount) return;
     try {
       const res = await fetch("/api/mounts", { headers: authHeaders() });
       if (res.ok) setMounts(await res.json());
     } catch {
       // ignore
     }
-  }, []);
+  }, [canMount]);
+
+  const fetchPolicies = useCallback(async () => {
+    if (!canMount) return;
+    try {
+      const res = await fetch("/api/mount-policies", { headers: authHeaders() });
+      if (res.ok) setPolicies(await res.json());
+    } catch {
+      // ignore
+    }
+  }, [canMount]);
 
   useEffect(() => {
     setIp(window.location.hostname);
     fetchClients();
     fetchMounts();
+    fetchPolicies();
     const interval = setInterval(() => {
       fetchClients();
       fetchMounts();
     }, 3000);
     return () => clearInterval(interval);
-  }, [fetchClients, fetchMounts]);
+  }, [fetchClients, fetchMounts, fetchPolicies]);
 
-  const isMounted = (clientId: string) => {
-    return mounts.some((m) => m.url.includes(clientId) || m.mount_point.includes(clientId.slice(0, 8)));
-  };
+  const getMount = (clientId: string) => mounts.find((m) => m.client_id === clientId);
 
-  const getMountPoint = (clientId: string) => {
-    const m = mounts.find((m) => m.url.includes(clientId) || m.mount_point.includes(clientId.slice(0, 8)));
-    return m?.mount_point || "";
-  };
+  const getMountPoint = (clientId: string) => getMount(clientId)?.mount_point || "";
+
+  const getPolicy = (clientId: string) => policies.find((p) => p.client_id === clientId);
 
   const handleMount = async (clientId: string) => {
     setMounting((prev) => ({ ...prev, [clientId]: true }));
@@ -117,6 +122,31 @@ export function ClientList() {
     }
   };
 
+  const handlePolicy = async (clientId: string, whenOffline: OfflineAction | "") => {
+    try {
+      const res = whenOffline
+        ? await fetch(`/api/mount-policies/${clientId}`, {
+            method: "POST",
+            headers: { "Content-Type": "application/json", ...authHeaders() },
+            body: JSON.stringify({ mount_path: mountPath, when_offline: whenOffline }),
+          })
+        : await fetch(`/api/mount-policies/${clientId}`, {
+            method: "DELETE",
+            headers: authHeaders(),
+          });
+      const data = await res.json();
+      if (res.ok && data.ok) {
+        showToast("success", whenOffline ? `Auto-mount on: ${mountPath}` : "Auto-mount off");
+        fetchPolicies();
+        fetchMounts();
+      } else {
+        showToast("error", data.error || "Failed to update auto-mount");
+      }
+    } catch {
+      showToast("error", "Failed to communicate with server");
+    }
+  };
+
   if (clients.length === 0) {
     return (
       <div className="card" style={{ textAlign: "center", padding: "40px 25px" }}>
@@ -135,22 +165,24 @@ export function ClientList() {
   return (
     <div>
       {/* Mount path setting */}
-      <div className="card" style={{ marginBottom: 20 }}>
-        <div style={{ display: "flex", alignItems: "center", gap: 10, marginBottom: 12 }}>
-          <FontAwesomeIcon icon={faFolderPlus} style={{ color: "#7eb8d8" }} />
-          <label className="form-label" style={{ marginBottom: 0 }}>
-            Mount Destination
-          </label>
+      {canMount && (
+        <div className="card" style={{ marginBottom: 20 }}>
+          <div style={{ display: "flex", alignItems: "center", gap: 10, marginBottom: 12 }}>
+            <FontAwesomeIcon icon={faFolderPlus} style={{ color: "#7eb8d8" }} />
+            <label className="form-label" style={{ marginBottom: 0 }}>
+              Mount Destination
+            </label>
+          </div>
+          <input
+            type="text"
+            value={mountPath}
+            onChange={(e) => setMountPath(e.target.value)}
+            className="form-input"
+            style={{ fontFamily: "monospace" }}
+            placeholder="~/Public/mount"
+          />
         </div>
-        <input
-          type="text"
-          value={mountPath}
-          onChange={(e) => setMountPath(e.target.value)}
-          className="form-input"
-          style={{ fontFamily: "monospace" }}
-          placeholder="~/Public/mount"
-        />
-      </div>
+      )}
 
       {/* Client list */}
       <div className="card" style={{ padding: 0, overflow: "hidden" }}>
@@ -172,18 +204,33 @@ export function ClientList() {
             </thead>
             <tbody>
               {clients.map((client) => {
-                const mounted = isMounted(client.id);
-                const mp = getMountPoint(client.id);
+                const mount = getMount(client.id);
+                const mounted = mount !== undefined;
+                const mp = mount?.mount_point || "";
+                const policy = getPolicy(client.id);
                 return (
                   <tr key={client.id}>
                     <td>
                       <div style={{ display: "flex", alignItems: "center", gap: 8 }}>
-                        <span className="status-badge status-online">
-                          <FontAwesomeIcon icon={faCircle} style={{ fontSize: 6 }} />
-                          Connected
-                        </span>
-                        {mounted && (
-                          <span className="status-badge status-mounted">
+                        {client.stale ? (
+                          <span
+                            className="status-badge status-stale"
+                            title={`Last seen ${new Date(Number(client.last_seen) * 1000).toLocaleTimeString()}`}
+                          >
+                            <FontAwesomeIcon icon={faCircle} style={{ fontSize: 6 }} />
+                            Not responding
+                          </span>
+                        ) : (
+                          <span className="status-badge status-online">
+                            <FontAwesomeIcon icon={faCircle} style={{ fontSize: 6 }} />
+                            Connected
+                          </span>
+                        )}
+                        {mount && (
+                          <span
+                            className="status-badge status-mounted"
+                            title={`${mount.strategy} · since ${new Date(mount.mounted_at * 1000).toLocaleString()}`}
+                          >
                             Mounted
                           </span>
                         )}
@@ -209,7 +256,20 @@ export function ClientList() {
                           <FontAwesomeIcon icon={faEye} />
                           Browse
                         </Link>
-                        {!mounted ? (
+                        {canMount && (
+                          <select
+                            className="form-input"
+                            style={{ width: "auto", padding: "6px 10px", fontSize: 12 }}
+                            value={policy?.when_offline ?? ""}
+                            onChange={(e) => handlePolicy(client.id, e.target.value as OfflineAction | "")}
+                            title={policy ? `Auto-mount under ${policy.mount_path}` : "Mount automatically when this device connects"}
+                          >
+                            <option value="">Auto-mount: Off</option>
+                            <option value="unmount">Auto-mount, unmount when offline</option>
+                            <option value="placeholder">Auto-mount, keep when offline</option>
+                          </select>
+                        )}
+                        {!canMount ? null : !mounted ? (
                           <button
                             onClick={() => handleMount(client.id)}
                             disabled={mounting[client.id]}
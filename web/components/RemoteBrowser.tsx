@@ -7,7 +7,7 @@
 "use client";
 
 import { useEffect, useState, useCallback } from "react";
-import type { FileEntry } from "@/lib/types";
+import type { ConnectedClient, FileChunk, FileEntry, TransferMode } from "@/lib/types";
 import { FontAwesomeIcon } from "@fortawesome/react-fontawesome";
 import {
   faFolder,
@@ -22,12 +22,21 @@ import {
   faSpinner,
   faFolderOpen,
   faCircleExclamation,
+  faShareFromSquare,
 } from "@fortawesome/free-solid-svg-icons";
 import { useToast } from "@/components/Toast";
 import { useAuth } from "@/components/AuthProvider";
 
 interface RemoteBrowserProps {
   clientId: string;
+  clients: ConnectedClient[];
+}
+
+interface SendForm {
+  name: string;
+  targetId: string;
+  folder: string;
+  mode: TransferMode;
 }
 
 function formatSize(bytes: number): string {
@@ -52,6 +61,18 @@ function formatDate(iso: string): string {
   });
 }
 
+// Download files in pieces so a single relay message never holds a whole large file
+const DOWNLOAD_CHUNK_SIZE = 2 * 1024 * 1024;
+
+function base64ToBytes(b64: string) {
+  const binary = atob(b64);
+  const bytes = new Uint8Array(binary.length);
+  for (let i = 0; i < binary.length; i++) {
+    bytes[i] = binary.charCodeAt(i);
+  }
+  return bytes;
+}
+
 async function relayCommand(clientId: string, cmd: Record<string, unknown>, authHeaders: () => Record<string, string>) {
   const res = await fetch(`/api/relay/${clientId}`, {
     method: "POST",
@@ -63,14 +84,50 @@ async function relayCommand(clientId: string, cmd: Record<string, unknown>, auth
   return data.data;
 }
 
-export function RemoteBrowser({ clientId }: RemoteBrowserProps) {
+// Upload a file as one streamed request; the server forwards it to the client in chunks.
+// XMLHttpRequest (not fetch) so upload progress can be shown.
+function uploadFile(
+  clientId: string,
+  path: string,
+  file: File,
+  authHeaders: () => Record<string, string>,
+  onProgress: (percent: number) => void
+) {
+  return new Promise<void>((resolve, reject) => {
+    const xhr = new XMLHttpRequest();
+    xhr.open("POST", `/api/upload/${clientId}?path=${encodeURIComponent(path)}`);
+    for (const [name, value] of Object.entries(authHeaders())) {
+      xhr.setRequestHeader(name, value);
+    }
+    xhr.upload.onprogress = (e) => {
+      if (e.lengthComputable) onProgress(Math.floor((e.loaded / e.total) * 100));
+    };
+    xhr.onload = () => {
+      let data: { ok?: boolean; error?: string } = {};
+      try {
+        data = JSON.parse(xhr.responseText);
+      } catch {
+        // non-JSON error page
+      }
+      if (data.ok) resolve();
+      else reject(new Error(data.error || "Operation failed"));
+    };
+    xhr.onerror = () => reject(new Error("Failed to communicate with server"));
+    xhr.send(file);
+  });
+}
+
+export function RemoteBrowser({ clientId, clients }: RemoteBrowserProps) {
   const [files, setFiles] = useState<FileEntry[]>([]);
   const [currentPath, setCurrentPath] = useState<string[]>([]);
   const [loading, setLoading] = useState(true);
   const [error, setError] = useState<string | null>(null);
   const [actionMsg, setActionMsg] = useState<string | null>(null);
+  const [sendForm, setSendForm] = useState<SendForm | null>(null);
   const { showToast } = useToast();
-  const { authHeaders } = useAuth();
+  const { authHeaders, can } = useAuth();
+  const canWrite = can("relay:write");
+  const otherClients = clients.filter((c) => c.id !== clientId);
 
   const pathStr = "/" + currentPath.join("/");
 
@@ -105,24 +162,27 @@ export function RemoteBrowser({ clientId }: RemoteBrowserProps) {
   const handleDownload = async (fileName: string) => {
     setActionMsg(`Downloading ${fileName}...`);
     try {
-      const filePath = [...currentPath, fileName].join("/");
-      const result = (await relayCommand(clientId, {
-        type: "readFile",
-        path: "/" + filePath,
-      }, authHeaders)) as { data: string; name: string; type: string };
-
-      const binary = atob(result.data);
-      const bytes = new Uint8Array(binary.length);
-      for (let i = 0; i < binary.length; i++) {
-        bytes[i] = binary.charCodeAt(i);
+      const filePath = "/" + [...currentPath, fileName].join("/");
+      const parts: BlobPart[] = [];
+      let offset = 0;
+      for (;;) {
+        const chunk = (await relayCommand(clientId, {
+          type: "readChunk",
+          path: filePath,
+          offset,
+          length: DOWNLOAD_CHUNK_SIZE,
+        }, authHeaders)) as FileChunk;
+        const bytes = base64ToBytes(chunk.data);
+        parts.push(bytes);
+        offset += bytes.length;
+        if (chunk.eof || bytes.length === 0) break;
+        setActionMsg(`Downloading ${fileName}... ${Math.floor((offset / chunk.size) * 100)}%`);
       }
-      const blob = new Blob([bytes], {
-        type: result.type || "application/octet-stream",
-      });
+      const blob = new Blob(parts, { type: "application/octet-stream" });
       const url = URL.createObjectURL(blob);
       const a = document.createElement("a");
       a.href = url;
-      a.download = result.name;
+      a.download = fileName;
       a.click();
       URL.revokeObjectURL(url);
       setActionMsg(null);
@@ -142,18 +202,10 @@ export function RemoteBrowser({ clientId }: RemoteBrowserProps) {
       for (const file of Array.from(input.files)) {
         setActionMsg(`Uploading ${file.name}...`);
         try {
-          const buffer = await file.arrayBuffer();
-          const bytes = new Uint8Array(buffer);
-          let binary = "";
-          for (let i = 0; i < bytes.length; i++) {
-            binary += String.fromCharCode(bytes[i]);
-          }
-          const filePath = [...currentPath, file.name].join("/");
-          await relayCommand(clientId, {
-            type: "writeFile",
-            path: "/" + filePath,
-            data: btoa(binary),
-          }, authHeaders);
+          const filePath = "/" + [...currentPath, file.name].join("/");
+          await uploadFile(clientId, filePath, file, authHeaders, (percent) =>
+            setActionMsg(`Uploading ${file.name}... ${percent}%`)
+          );
         } catch (err: unknown) {
           setActionMsg(null);
           showToast("error", `Upload failed: ${err instanceof Error ? err.message : "Error"}`);
@@ -216,6 +268,31 @@ export function RemoteBrowser({ clientId }: RemoteBrowserProps) {
     }
   };
 
+  const handleSend = async () => {
+    if (!sendForm || !sendForm.targetId) return;
+    const folder = sendForm.folder.trim().replace(/\/+$/, "");
+    try {
+      const res = await fetch("/api/transfers", {
+        method: "POST",
+        headers: { "Content-Type": "application/json", ...authHeaders() },
+        body: JSON.stringify({
+          source: { client_id: clientId, path: "/" + [...currentPath, sendForm.name].join("/") },
+          target: { client_id: sendForm.targetId, path: `${folder}/${sendForm.name}` },
+          mode: sendForm.mode,
+        }),
+      });
+      const data = await res.json();
+      if (!res.ok) {
+        showToast("error", data.error || "Failed to start transfer");
+        return;
+      }
+      showToast("success", `Started ${sendForm.mode} of "${sendForm.name}"`);
+      setSendForm(null);
+    } catch {
+      showToast("error", "Failed to communicate with server");
+    }
+  };
+
   const breadcrumbs = ["root", ...currentPath];
 
   return (
@@ -258,14 +335,18 @@ export function RemoteBrowser({ clientId }: RemoteBrowserProps) {
 
         {/* Action buttons */}
         <div style={{ display: "flex", gap: 8 }}>
-          <button onClick={handleUpload} className="btn btn-sm">
-            <FontAwesomeIcon icon={faCloudArrowUp} />
-            Upload
-          </button>
-          <button onClick={handleNewFolder} className="btn btn-sm btn-secondary">
-            <FontAwesomeIcon icon={faFolderPlus} />
-            New Folder
-          </button>
+          {canWrite && (
+            <>
+              <button onClick={handleUpload} className="btn btn-sm">
+                <FontAwesomeIcon icon={faCloudArrowUp} />
+                Upload
+              </button>
+              <button onClick={handleNewFolder} className="btn btn-sm btn-secondary">
+                <FontAwesomeIcon icon={faFolderPlus} />
+                New Folder
+              </button>
+            </>
+          )}
           <button onClick={fetchFiles} className="btn btn-sm btn-secondary">
             <FontAwesomeIcon icon={faArrowsRotate} />
             Refresh
@@ -281,6 +362,42 @@ export function RemoteBrowser({ clientId }: RemoteBrowserProps) {
         </div>
       )}
 
+      {/* Send to another client */}
+      {sendForm && (
+        <div className="alert alert-info" style={{ margin: 0, borderRadius: 0, flexWrap: "wrap", gap: 10 }}>
+          <FontAwesomeIcon icon={faShareFromSquare} />
+          <span>Send &quot;{sendForm.name}&quot; to</span>
+          <select
+            className="form-input"
+            style={{ width: "auto", padding: "6px 10px" }}
+            value={sendForm.targetId}
+            onChange={(e) => setSendForm({ ...sendForm, targetId: e.target.value })}
+          >
+            {otherClients.map((c) => (
+              <option key={c.id} value={c.id}>{c.name}</option>
+            ))}
+          </select>
+          <input
+            className="form-input"
+            style={{ width: 200, padding: "6px 10px" }}
+            value={sendForm.folder}
+            onChange={(e) => setSendForm({ ...sendForm, folder: e.target.value })}
+            placeholder="/"
+          />
+          <select
+            className="form-input"
+            style={{ width: "auto", padding: "6px 10px" }}
+            value={sendForm.mode}
+            onChange={(e) => setSendForm({ ...sendForm, mode: e.target.value as TransferMode })}
+          >
+            <option value="copy">Copy</option>
+            <option value="move">Move</option>
+          </select>
+          <button onClick={handleSend} className="btn btn-sm">Start</button>
+          <button onClick={() => setSendForm(null)} className="btn btn-sm btn-secondary">Cancel</button>
+        </div>
+      )}
+
       {/* Content */}
       {loading ? (
         <div style={{ padding: 40, textAlign: "center", color: "#7eb8d8" }}>
@@ -365,22 +482,36 @@ export function RemoteBrowser({ clientId }: RemoteBrowserProps) {
                           DL
                         </button>
                       )}
-                      <button
-                        onClick={() => handleRename(file.name)}
-                        className="btn btn-sm btn-secondary"
-                        style={{ padding: "5px 12px", fontSize: 11 }}
-                      >
-                        <FontAwesomeIcon icon={faPenToSquare} />
-                        Rename
-                      </button>
-                      <button
-                        onClick={() => handleDelete(file.name, file.is_dir)}
-                        className="btn btn-sm btn-danger"
-                        style={{ padding: "5px 12px", fontSize: 11 }}
-                      >
-                        <FontAwesomeIcon icon={faTrash} />
-                        Delete
-                      </button>
+                      {canWrite && (
+                        <>
+                          <button
+                            onClick={() => handleRename(file.name)}
+                            className="btn btn-sm btn-secondary"
+                            style={{ padding: "5px 12px", fontSize: 11 }}
+                          >
+                            <FontAwesomeIcon icon={faPenToSquare} />
+                            Rename
+                          </button>
+                          {otherClients.length > 0 && (
+                            <button
+                              onClick={() => setSendForm({ name: file.name, targetId: otherClients[0].id, folder: "/", mode: "copy" })}
+                              className="btn btn-sm btn-secondary"
+                              style={{ padding: "5px 12px", fontSize: 11 }}
+                            >
+                              <FontAwesomeIcon icon={faShareFromSquare} />
+                              Send
+                            </button>
+                          )}
+                          <button
+                            onClick={() => handleDelete(file.name, file.is_dir)}
+                            className="btn btn-sm btn-danger"
+                            style={{ padding: "5px 12px", fontSize: 11 }}
+                          >
+                            <FontAwesomeIcon icon={faTrash} />
+                            Delete
+                          </button>
+                        </>
+                      )}
                     </div>
                   </td>
                 </tr>
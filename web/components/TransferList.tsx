@@ -0,0 +1,173 @@
+// SNOWCODE - ソフトウェア製品
+// (C) SNOWCODE
+// 開発者: 雪符しき
+// https://snowcode.jp
+// 問い合わせ: info@snowcode.jp
+
+"use client";
+
+import { useEffect, useState, useCallback } from "react";
+import type { ConnectedClient, Transfer } from "@/lib/types";
+import { FontAwesomeIcon } from "@fortawesome/react-fontawesome";
+import {
+  faRightLeft,
+  faStop,
+  faPlay,
+  faXmark,
+  faArrowRight,
+} from "@fortawesome/free-solid-svg-icons";
+import { useToast } from "@/components/Toast";
+import { useAuth } from "@/components/AuthProvider";
+
+function formatBytes(bytes: number): string {
+  const units = ["B", "KB", "MB", "GB", "TB"];
+  let i = 0;
+  let size = bytes;
+  while (size >= 1024 && i < units.length - 1) {
+    size /= 1024;
+    i++;
+  }
+  return `${size.toFixed(i > 0 ? 1 : 0)} ${units[i]}`;
+}
+
+const STATUS_BADGE: Record<Transfer["status"], string> = {
+  running: "status-mounted",
+  completed: "status-online",
+  failed: "status-failed",
+  cancelled: "status-stale",
+};
+
+export function TransferList({ clients }: { clients: ConnectedClient[] }) {
+  const [transfers, setTransfers] = useState<Transfer[]>([]);
+  const { showToast } = useToast();
+  const { authHeaders } = useAuth();
+
+  const fetchTransfers = useCallback(async () => {
+    try {
+      const res = await fetch("/api/transfers", { headers: authHeaders() });
+      if (res.ok) setTransfers(await res.json());
+    } catch {
+      // ignore
+    }
+  }, []);
+
+  useEffect(() => {
+    fetchTransfers();
+    const interval = setInterval(fetchTransfers, 2000);
+    return () => clearInterval(interval);
+  }, [fetchTransfers]);
+
+  const clientName = (id: string) => clients.find((c) => c.id === id)?.name ?? `${id.slice(0, 8)}...`;
+
+  const handleAction = async (id: string, action: "cancel" | "resume" | "remove") => {
+    try {
+      const res = await fetch(action === "remove" ? `/api/transfers/${id}` : `/api/transfers/${id}/${action}`, {
+        method: action === "remove" ? "DELETE" : "POST",
+        headers: authHeaders(),
+      });
+      const data = await res.json();
+      if (!res.ok) {
+        showToast("error", data.error || `Failed to ${action} transfer`);
+      }
+      fetchTransfers();
+    } catch {
+      showToast("error", "Failed to communicate with server");
+    }
+  };
+
+  if (transfers.length === 0) return null;
+
+  return (
+    <div className="card" style={{ padding: 0, overflow: "hidden", marginTop: 25 }}>
+      <div className="card-header" style={{ padding: "18px 25px", margin: 0, borderRadius: "20px 20px 0 0" }}>
+        <div className="card-title">
+          <FontAwesomeIcon icon={faRightLeft} />
+          Transfers
+        </div>
+      </div>
+      <div className="table-wrapper">
+        <table className="table">
+          <thead>
+            <tr>
+              <th>Status</th>
+              <th>Transfer</th>
+              <th>Progress</th>
+              <th style={{ textAlign: "right" }}>Actions</th>
+            </tr>
+          </thead>
+          <tbody>
+            {transfers.map((t) => {
+              const percent = t.bytes_total > 0 ? Math.floor((t.bytes_done / t.bytes_total) * 100) : 0;
+              return (
+                <tr key={t.id}>
+                  <td>
+                    <span className={`status-badge ${STATUS_BADGE[t.status]}`}>
+                      {t.mode === "move" ? "Move" : "Copy"} · {t.status}
+                    </span>
+                  </td>
+                  <td>
+                    <div style={{ display: "flex", alignItems: "center", gap: 8, color: "#4a6b7c" }}>
+                      <span>
+                        <strong style={{ color: "#4a7c9b" }}>{clientName(t.source.client_id)}</strong> {t.source.path}
+                      </span>
+                      <FontAwesomeIcon icon={faArrowRight} style={{ color: "#a8c8dc" }} />
+                      <span>
+                        <strong style={{ color: "#4a7c9b" }}>{clientName(t.target.client_id)}</strong> {t.target.path}
+                      </span>
+                    </div>
+                    {t.error && (
+                      <div style={{ fontSize: 11, color: "#c62828", marginTop: 4 }}>{t.error}</div>
+                    )}
+                  </td>
+                  <td style={{ minWidth: 200 }}>
+                    <div className="progress-bar">
+                      <div className="progress-fill" style={{ width: `${t.status === "completed" ? 100 : percent}%` }} />
+                    </div>
+                    <div style={{ fontSize: 11, color: "#7eb8d8", marginTop: 4 }}>
+                      {t.files_done}/{t.files_total} files · {formatBytes(t.bytes_done)} / {formatBytes(t.bytes_total)}
+                      {t.status === "running" && t.current && ` · ${t.current}`}
+                    </div>
+                  </td>
+                  <td style={{ textAlign: "right" }}>
+                    <div style={{ display: "flex", gap: 6, justifyContent: "flex-end" }}>
+                      {t.status === "running" && (
+                        <button
+                          onClick={() => handleAction(t.id, "cancel")}
+                          className="btn btn-sm btn-danger"
+                          style={{ padding: "5px 12px", fontSize: 11 }}
+                        >
+                          <FontAwesomeIcon icon={faStop} />
+                          Cancel
+                        </button>
+                      )}
+                      {(t.status === "failed" || t.status === "cancelled") && (
+                        <button
+                          onClick={() => handleAction(t.id, "resume")}
+                          className="btn btn-sm"
+                          style={{ padding: "5px 12px", fontSize: 11 }}
+                        >
+                          <FontAwesomeIcon icon={faPlay} />
+                          Resume
+                        </button>
+                      )}
+                      {t.status !== "running" && (
+                        <button
+                          onClick={() => handleAction(t.id, "remove")}
+                          className="btn btn-sm btn-secondary"
+                          style={{ padding: "5px 12px", fontSize: 11 }}
+                        >
+                          <FontAwesomeIcon icon={faXmark} />
+                          Clear
+                        </button>
+                      )}
+                    </div>
+                  </td>
+                </tr>
+              );
+            })}
+          </tbody>
+        </table>
+      </div>
+    </div>
+  );
+}
@@ -9,6 +9,24 @@ export interface ConnectedClient {
   name: string;
   folder_name: string;
   connected_at: string;
+  /** Relay protocol version negotiated at registration */
+  protocol_version: number;
+  capabilities: ClientCapability[];
+  /** Unix seconds of the last message from the client */
+  last_seen: string;
+  /** Missed a heartbeat; requests fail until it answers again */
+  stale: boolean;
+}
+
+export type ClientCapability = "binaryFrames" | "dirRename" | "cancel" | "progress" | "copy";
+
+export type TokenScope = "relay:read" | "relay:write" | "mount" | "register" | "admin";
+
+export interface TokenInfo {
+  id: string;
+  name: string;
+  scopes: TokenScope[];
+  clients: string[] | null;
 }
 
 export interface FileEntry {
@@ -18,17 +36,105 @@ export interface FileEntry {
   modified: string;
 }
 
+/** Commands accepted by /api/relay (uploads go through /api/upload) */
 export interface RelayCommand {
-  type: "readdir" | "readFile" | "writeFile" | "mkdir" | "delete" | "rename" | "stat";
+  type:
+    | "readdir"
+    | "readFile"
+    | "readChunk"
+    | "mkdir"
+    | "delete"
+    | "rename"
+    | "copy"
+    | "stat";
   path?: string;
-  data?: string;
+  offset?: number;
+  length?: number;
   oldPath?: string;
   newPath?: string;
 }
 
+export interface TransferEndpoint {
+  client_id: string;
+  path: string;
+}
+
+export type TransferMode = "copy" | "move";
+
+export type TransferStatus = "running" | "completed" | "failed" | "cancelled";
+
+/** Client-to-client transfer run by the server (/api/transfers) */
+export interface Transfer {
+  id: string;
+  source: TransferEndpoint;
+  /** Full destination path */
+  target: TransferEndpoint;
+  mode: TransferMode;
+  status: TransferStatus;
+  files_total: number;
+  files_done: number;
+  bytes_total: number;
+  bytes_done: number;
+  /** File in progress, relative to the source path */
+  current: string;
+  error: string | null;
+  created_at: string;
+  updated_at: string;
+}
+
+/** A mounted client share from the server's mount registry (/api/mounts) */
+export interface MountRecord {
+  client_id: string;
+  mount_point: string;
+  /** Share URL (without credentials) */
+  url: string;
+  /** "webdav" (macOS), "davfs" or "gvfs" (Linux) */
+  fs_type: string;
+  /** How it was mounted, e.g. "mount_webdav", "finder", "davfs"; "external" when not mounted by the server */
+  strategy: string;
+  /** Unix seconds (for external mounts: when first seen) */
+  mounted_at: number;
+}
+
+export type RelayErrorCode =
+  | "notFound"
+  | "permissionDenied"
+  | "alreadyExists"
+  | "notEmpty"
+  | "typeMismatch"
+  | "busy"
+  | "noSpace"
+  | "nameTooLong"
+  | "tooLarge"
+  | "invalidPath"
+  | "invalidRequest"
+  | "unsupported"
+  | "other";
+
 export interface RelayResponse {
   id: string;
   ok: boolean;
   data?: unknown;
   error?: string;
+  /** Set on failures the client reported; null when the relay itself failed */
+  code?: RelayErrorCode | null;
+}
+
+export interface FileChunk {
+  data: string;
+  offset: number;
+  size: number;
+  modified: string;
+  eof: boolean;
+}
+
+/** What happens to an auto-mounted share while its device is offline */
+export type OfflineAction = "unmount" | "placeholder";
+
+export interface MountPolicy {
+  client_id: string;
+  name: string;
+  /** Directory the share is mounted under */
+  mount_path: string;
+  when_offline: OfflineAction;
 }